@@ -2,7 +2,7 @@ use tokio::time::{Duration, sleep};
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 use urtcp::device::LoopDevice;
-use urtcp::tcp::socket::{TcpSocketAddr, TcpStream};
+use urtcp::tcp::socket::{TcpListener, TcpSocketAddr, TcpStream};
 use urtcp::{Stack, StackConfig};
 
 #[tokio::main(flavor = "multi_thread")]
@@ -16,16 +16,18 @@ async fn main() -> anyhow::Result<()> {
     let mtu = 1500;
     let (dev_a, dev_b) = LoopDevice::pair(1500);
     let cfg = StackConfig {
-        local_ip: [10, 0, 0, 1],
+        local_ips: vec![[10, 0, 0, 1].into()],
         ttl: 64,
         ident_seed: 1,
         mtu: 1500,
+        ..StackConfig::default()
     };
     let cfg_a = StackConfig {
-        local_ip: [10, 0, 0, 1],
+        local_ips: vec![[10, 0, 0, 1].into()],
         ttl: 64,
         ident_seed: 1,
         mtu,
+        ..StackConfig::default()
     };
     let stack_a = Stack::new(dev_a, cfg.clone());
     let ctrl_a = stack_a.control();
@@ -37,29 +39,38 @@ async fn main() -> anyhow::Result<()> {
     let stack_b = Stack::new(
         dev_b,
         StackConfig {
-            local_ip: [10, 0, 0, 2],
+            local_ips: vec![[10, 0, 0, 2].into()],
             ..cfg
         },
     );
 
     let cfg_b = StackConfig {
-        local_ip: [10, 0, 0, 2],
+        local_ips: vec![[10, 0, 0, 2].into()],
         ..cfg_a
     };
-    let _ctrl_b = stack_b.control();
+    let ctrl_b = stack_b.control();
     info!(?cfg_b, "spawning stack B");
     tokio::spawn(async move {
         let _ = stack_b.run().await;
     });
     // attempt a connect from A -> B
     let local = TcpSocketAddr {
-        ip: [10, 0, 0, 1],
+        ip: [10, 0, 0, 1].into(),
         port: 50_000,
     };
     let remote = TcpSocketAddr {
-        ip: [10, 0, 0, 2],
+        ip: [10, 0, 0, 2].into(),
         port: 80,
     };
+    let mut listener = TcpListener::bind(ctrl_b, remote).await?;
+    tokio::spawn(async move {
+        while let Ok(mut stream) = listener.accept().await {
+            info!(peer = ?stream.peer_addr(), "B accepted connection");
+            while let Ok(Some(data)) = stream.read().await {
+                info!(len = data.len(), "B received payload");
+            }
+        }
+    });
     info!(?local, ?remote, "attempting TcpStream::connect");
     // This will currently send a SYN and then stall (no TCP logic yet).
     // let _stream = TcpStream::connect(
@@ -27,10 +27,11 @@ async fn main() -> anyhow::Result<()> {
 
     // stack config — this IP is the urTCP side's "real" IPv4 on the TUN link
     let cfg = StackConfig {
-        local_ip: [10, 10, 0, 1],
+        local_ips: vec![[10, 10, 0, 1].into()],
         ttl: 64,
         ident_seed: 1,
         mtu,
+        ..StackConfig::default()
     };
 
     let stack = Stack::new(tun_a, cfg.clone());
@@ -41,10 +42,7 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    info!(
-        "TUN interface {} up, IP {}.{}.{}.{}",
-        tun_name, cfg.local_ip[0], cfg.local_ip[1], cfg.local_ip[2], cfg.local_ip[3]
-    );
+    info!("TUN interface {} up, IP {}", tun_name, cfg.local_ips[0]);
     info!("configure your host to assign an IP to {}, e.g.:", tun_name);
     println!("  sudo ip addr add 10.10.0.2/24 dev {}", tun_name);
     println!("  sudo ip link set {} up", tun_name);
@@ -53,11 +51,11 @@ async fn main() -> anyhow::Result<()> {
     sleep(Duration::from_secs(5)).await;
 
     let local = TcpSocketAddr {
-        ip: cfg.local_ip,
+        ip: cfg.local_ips[0],
         port: 50000,
     };
     let remote = TcpSocketAddr {
-        ip: [10, 10, 0, 2],
+        ip: [10, 10, 0, 2].into(),
         port: 80,
     };
     info!(?local, ?remote, "attempting TcpStream::connect");
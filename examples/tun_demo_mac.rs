@@ -3,6 +3,7 @@ use urtcp::device::tun_backend::TunDevice;
 use urtcp::tcp::socket::{TcpSocketAddr, TcpStream};
 use urtcp::{Stack, StackConfig};
 
+#[cfg(target_os = "macos")]
 use std::process::Command;
 use tokio::time::{Duration, sleep};
 use tracing::{debug, error, info, warn};
@@ -89,10 +90,11 @@ async fn main() -> anyhow::Result<()> {
 
     // Spin up the stack on this TUN
     let cfg = StackConfig {
-        local_ip,
+        local_ips: vec![local_ip.into()],
         ttl: 64,
         ident_seed: 1,
         mtu,
+        ..StackConfig::default()
     };
 
     let stack = Stack::new(tun, cfg.clone());
@@ -113,16 +115,16 @@ async fn main() -> anyhow::Result<()> {
     // Try a connect to the peer IP on port 80.
     // NOTE: This will only fully succeed once your TCP state machine is implemented.
     let local = TcpSocketAddr {
-        ip: cfg.local_ip,
+        ip: cfg.local_ips[0],
         port: 50_000,
     };
     let remote = TcpSocketAddr {
-        ip: peer_ip,
+        ip: peer_ip.into(),
         port: 80,
     };
     info!(?local, ?remote, "TcpStream::connect()");
     match TcpStream::connect(ctrl.clone(), local, remote).await {
-        Ok(mut stream) => {
+        Ok(stream) => {
             info!("connect() returned Ok (handshake may be stubbed)");
             let payload = b"hello over TUN".to_vec();
             debug!(len = payload.len(), "sending payload");
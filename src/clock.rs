@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+/// Where the stack gets the time from, for its timers, RTT samples,
+/// timestamps and ISNs.
+#[async_trait::async_trait]
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Instant;
+    /// Resolve once `now()` has reached `at`.
+    async fn sleep_until(&self, at: Instant);
+}
+
+/// Real time, with tokio's timers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait::async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep_until(&self, at: Instant) {
+        tokio::time::sleep_until(tokio::time::Instant::from_std(at)).await
+    }
+}
+
+/// A clock that only moves when told to, for driving timers step by step.
+/// Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<watch::Sender<Instant>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl ManualClock {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            now: Arc::new(watch::Sender::new(start)),
+        }
+    }
+
+    /// Move time forward by `by`, waking whatever sleeps until then.
+    pub fn advance(&self, by: Duration) {
+        self.now.send_modify(|now| *now += by);
+    }
+
+    /// Move time forward to `at`; earlier instants are ignored.
+    pub fn advance_to(&self, at: Instant) {
+        self.now.send_if_modified(|now| {
+            let later = at > *now;
+            if later {
+                *now = at;
+            }
+            later
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.borrow()
+    }
+
+    async fn sleep_until(&self, at: Instant) {
+        let mut rx = self.now.subscribe();
+        // The sender lives as long as `self`, so `changed` cannot fail.
+        while *rx.borrow_and_update() < at {
+            let _ = rx.changed().await;
+        }
+    }
+}
@@ -71,7 +71,7 @@ pub mod tun_backend {
 
     /// Minimal TUN wrapper (IPv4 only, for now).
     pub struct TunDevice {
-        dev: TunDev,
+        dev: std::sync::Arc<TunDev>,
         mtu: usize,
         ifname: String,
     }
@@ -79,16 +79,21 @@ pub mod tun_backend {
         pub fn new(name: &str, mtu: usize) -> Result<Self> {
             let mut cfg = Configuration::default();
             // cfg.up();
-            cfg.address((10, 0, 0, 1))
+            cfg.tun_name(name)
+                .address((10, 0, 0, 1))
                 .netmask((255, 255, 255, 0))
                 .mtu(mtu as u16)
                 .up();
 
-            let mut dev = tun::create(&cfg).map_err(|e| UrtcpError::Device(e.to_string()))?;
+            let dev = tun::create(&cfg).map_err(|e| UrtcpError::Device(e.to_string()))?;
             let ifname = dev
                 .tun_name()
                 .map_err(|e| UrtcpError::Device(e.to_string()))?;
-            Ok(Self { dev, mtu, ifname })
+            Ok(Self {
+                dev: std::sync::Arc::new(dev),
+                mtu,
+                ifname,
+            })
         }
 
         pub fn ifname(&self) -> &str {
@@ -98,24 +103,29 @@ pub mod tun_backend {
     #[async_trait::async_trait]
     impl NetDevice for TunDevice {
         async fn recv(&self) -> Result<bytes::BytesMut> {
-            // NOTE: TunDev is blocking; in production wrap with spawn_blocking or use async fd
+            // NOTE: TunDev is blocking; in production use an async fd
             use tokio::task;
+            let dev = self.dev.clone();
             let mut buf = vec![0u8; self.mtu + 64];
-            let n = task::spawn_blocking(move || {
-                // SAFETY: demo
-                // Read directly; replace with async-io wrapper in real code
-                use std::io::Read;
-                // This is placeholder pseudo; adapt to tun crate's API
-                Ok::<usize, std::io::Error>(0)
+            let buf = task::spawn_blocking(move || {
+                let n = dev.recv(&mut buf)?;
+                buf.truncate(n);
+                Ok::<Vec<u8>, std::io::Error>(buf)
             })
             .await
             .map_err(|e| UrtcpError::Device(format!("join error: {e}")))?
             .map_err(|e| UrtcpError::Device(e.to_string()))?;
-            buf.truncate(n);
             Ok(bytes::BytesMut::from(&buf[..]))
         }
-        async fn send(&self, _frame: &[u8]) -> Result<()> {
-            todo!("write to tun device")
+        async fn send(&self, frame: &[u8]) -> Result<()> {
+            use tokio::task;
+            let dev = self.dev.clone();
+            let frame = frame.to_vec();
+            task::spawn_blocking(move || dev.send(&frame))
+                .await
+                .map_err(|e| UrtcpError::Device(format!("join error: {e}")))?
+                .map_err(|e| UrtcpError::Device(e.to_string()))?;
+            Ok(())
         }
         fn mtu(&self) -> usize {
             self.mtu
@@ -10,6 +10,22 @@ pub enum UrtcpError {
     Checksum,
     #[error("connection not found")]
     ConnNotFound,
+    #[error("connection timed out")]
+    TimedOut,
+    #[error("connection reset by peer")]
+    ConnectionReset,
+    #[error("connection refused")]
+    ConnectionRefused,
+    #[error("broken pipe")]
+    BrokenPipe,
+    #[error("address in use")]
+    AddrInUse,
+    #[error("local and remote addresses of different families")]
+    AddrFamily,
+    #[error("no local address to send from")]
+    AddrNotAvailable,
+    #[error("encrypted stream failed to decrypt")]
+    DecryptFailed,
     #[error("would block")]
     WouldBlock,
     #[error("not implemented: {0}")]
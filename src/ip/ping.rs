@@ -0,0 +1,42 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::error::*;
+use crate::tcp::conn::TcpCmd;
+
+/// How to ping.
+#[derive(Debug, Clone, Copy)]
+pub struct PingOptions {
+    /// Bytes of data in the echo request; larger than the MTU is sent in
+    /// fragments.
+    pub size: usize,
+    /// Wait this long for the reply before giving up with `TimedOut`.
+    pub timeout: Duration,
+}
+
+impl Default for PingOptions {
+    fn default() -> Self {
+        Self {
+            size: 56,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Send an ICMP echo request to `dst` from the stack behind `ctrl`, and
+/// wait for the matching reply: the round trip time.
+pub async fn ping(
+    ctrl: &mpsc::Sender<TcpCmd>,
+    dst: Ipv4Addr,
+    opts: PingOptions,
+) -> Result<Duration> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    ctrl.send(TcpCmd::Ping(dst, opts, reply_tx))
+        .await
+        .map_err(|_| UrtcpError::Device("control channel".into()))?;
+    reply_rx
+        .await
+        .map_err(|_| UrtcpError::Device("ping drop".into()))?
+}
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Fragments one datagram may arrive in before it is given up.
+pub const MAX_FRAGMENTS: usize = 64;
+/// Bookkeeping charged per datagram and per fragment against the memory
+/// limit, so floods of tiny fragments are bounded too.
+const OVERHEAD: usize = 64;
+
+/// One fragment of a datagram.
+pub struct Fragment<'a> {
+    /// Where `data` goes in the datagram's payload, in bytes.
+    pub offset: usize,
+    /// More fragments follow this one.
+    pub more: bool,
+    pub data: &'a [u8],
+    /// Carried a congestion experienced mark.
+    pub ce: bool,
+    /// What precedes the payload, e.g. the IPv4 header; only the first
+    /// fragment's is kept.
+    pub header: &'a [u8],
+}
+
+/// A datagram put back together.
+pub struct Reassembled {
+    /// The first fragment's header.
+    pub header: Vec<u8>,
+    pub payload: Vec<u8>,
+    /// Some fragment was marked congestion experienced (RFC 3168 §5.3).
+    pub ce: bool,
+}
+
+struct Partial {
+    expires: Instant,
+    header: Option<Vec<u8>>,
+    /// By offset, never overlapping.
+    frags: Vec<(usize, Vec<u8>)>,
+    /// Payload length, once the last fragment is in.
+    total: Option<usize>,
+    received: usize,
+    ce: bool,
+    /// Fragments overlapped: the datagram is discarded, and so is anything
+    /// more of it arriving until it expires (RFC 5722).
+    poisoned: bool,
+    /// Charged against the memory limit.
+    cost: usize,
+}
+
+/// Datagrams being reassembled, keyed by whatever identifies a datagram,
+/// e.g. (src, dst, protocol, ident) for IPv4.
+pub struct Reassembler<K> {
+    entries: HashMap<K, Partial>,
+    /// Expiry order. Every datagram gets the same time, so arrival order is
+    /// expiry order; slots of datagrams completed early are skipped.
+    queue: VecDeque<(Instant, K)>,
+    timeout: Duration,
+    /// Bound on memory held; past it the oldest datagrams are dropped.
+    limit: usize,
+    used: usize,
+}
+
+impl<K: Copy + Eq + Hash + std::fmt::Debug> Reassembler<K> {
+    pub fn new(timeout: Duration, limit: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            queue: VecDeque::new(),
+            timeout,
+            limit,
+            used: 0,
+        }
+    }
+
+    /// Bytes held, bookkeeping included.
+    pub fn memory(&self) -> usize {
+        self.used
+    }
+
+    /// Add a fragment; returns the datagram once it is complete.
+    pub fn insert(&mut self, key: K, frag: Fragment<'_>, now: Instant) -> Option<Reassembled> {
+        self.expire(now);
+        let end = frag.offset + frag.data.len();
+        // All but the last fragment carry a multiple of eight bytes.
+        if frag.more && (frag.data.is_empty() || !frag.data.len().is_multiple_of(8)) {
+            return None;
+        }
+        let entry = self.entries.entry(key).or_insert_with(|| {
+            self.queue.push_back((now + self.timeout, key));
+            self.used += OVERHEAD;
+            Partial {
+                expires: now + self.timeout,
+                header: None,
+                frags: Vec::new(),
+                total: None,
+                received: 0,
+                ce: false,
+                poisoned: false,
+                cost: OVERHEAD,
+            }
+        });
+        if entry.poisoned {
+            return None;
+        }
+        let last_end = entry.frags.last().map_or(0, |(o, d)| o + d.len());
+        let bad_end = match (frag.more, entry.total) {
+            (false, Some(total)) => total != end,
+            (false, None) => last_end > end,
+            (true, Some(total)) => end > total,
+            (true, None) => false,
+        };
+        let mut overlap = None;
+        for (o, d) in &entry.frags {
+            if *o < end && frag.offset < o + d.len() {
+                overlap = Some(*o == frag.offset && d[..] == *frag.data);
+                break;
+            }
+        }
+        if overlap == Some(true) {
+            // An exact duplicate, e.g. from a retransmitting link.
+            return None;
+        }
+        if bad_end || overlap.is_some() || entry.frags.len() >= MAX_FRAGMENTS {
+            tracing::debug!(?key, "discarding datagram with inconsistent fragments");
+            self.used -= entry.cost - OVERHEAD;
+            entry.cost = OVERHEAD;
+            entry.frags = Vec::new();
+            entry.header = None;
+            entry.poisoned = true;
+            return None;
+        }
+        if !frag.more {
+            entry.total = Some(end);
+        }
+        if frag.offset == 0 {
+            entry.header = Some(frag.header.to_vec());
+        }
+        entry.ce |= frag.ce;
+        let at = entry.frags.partition_point(|(o, _)| *o < frag.offset);
+        entry.frags.insert(at, (frag.offset, frag.data.to_vec()));
+        entry.received += frag.data.len();
+        entry.cost += frag.data.len() + OVERHEAD;
+        self.used += frag.data.len() + OVERHEAD;
+        let complete = entry.header.is_some() && entry.total == Some(entry.received);
+        if complete {
+            let entry = self.entries.remove(&key)?;
+            self.used -= entry.cost;
+            let mut payload = Vec::with_capacity(entry.received);
+            for (_, d) in entry.frags {
+                payload.extend_from_slice(&d);
+            }
+            return Some(Reassembled {
+                header: entry.header.unwrap_or_default(),
+                payload,
+                ce: entry.ce,
+            });
+        }
+        self.limit_memory();
+        None
+    }
+
+    /// When the oldest datagram expires.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.queue.front().map(|(t, _)| *t)
+    }
+
+    /// Drop datagrams whose time is up.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some(&(t, key)) = self.queue.front() {
+            if t > now {
+                break;
+            }
+            self.queue.pop_front();
+            if self.entries.get(&key).is_some_and(|e| e.expires == t) {
+                tracing::debug!(?key, "reassembly timed out");
+                self.remove(&key);
+            }
+        }
+    }
+
+    /// Drop the oldest datagrams until within the memory limit.
+    fn limit_memory(&mut self) {
+        while self.used > self.limit {
+            let Some((t, key)) = self.queue.pop_front() else {
+                break;
+            };
+            if self.entries.get(&key).is_some_and(|e| e.expires == t) {
+                tracing::debug!(?key, "reassembly memory exhausted");
+                self.remove(&key);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.used -= entry.cost;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn frag(offset: usize, more: bool, data: &[u8]) -> Fragment<'_> {
+        Fragment {
+            offset,
+            more,
+            data,
+            ce: false,
+            header: b"hdr",
+        }
+    }
+
+    #[test]
+    fn fragments_in_any_order() {
+        let mut r = Reassembler::new(TIMEOUT, usize::MAX);
+        let now = Instant::now();
+        assert!(r.insert(1, frag(16, false, b"tail"), now).is_none());
+        let mut middle = frag(8, true, b"89abcdef");
+        middle.ce = true;
+        assert!(r.insert(1, middle, now).is_none());
+        let done = r.insert(1, frag(0, true, b"01234567"), now).unwrap();
+        assert_eq!(done.payload, b"0123456789abcdeftail");
+        assert_eq!(done.header, b"hdr");
+        assert!(done.ce);
+        assert_eq!(r.memory(), 0);
+    }
+
+    #[test]
+    fn exact_duplicates_are_ignored() {
+        let mut r = Reassembler::new(TIMEOUT, usize::MAX);
+        let now = Instant::now();
+        assert!(r.insert(1, frag(0, true, b"01234567"), now).is_none());
+        assert!(r.insert(1, frag(0, true, b"01234567"), now).is_none());
+        let done = r.insert(1, frag(8, false, b"89"), now).unwrap();
+        assert_eq!(done.payload, b"0123456789");
+    }
+
+    #[test]
+    fn overlap_poisons_until_expiry() {
+        let mut r = Reassembler::new(TIMEOUT, usize::MAX);
+        let now = Instant::now();
+        assert!(
+            r.insert(1, frag(0, true, b"0123456789abcdef"), now)
+                .is_none()
+        );
+        assert!(r.insert(1, frag(8, true, b"XXXXXXXX"), now).is_none());
+        // Only the bookkeeping is left, and nothing completes the datagram.
+        assert_eq!(r.memory(), OVERHEAD);
+        assert!(r.insert(1, frag(16, false, b"tail"), now).is_none());
+        assert!(
+            r.insert(1, frag(0, true, b"0123456789abcdef"), now)
+                .is_none()
+        );
+
+        // Once expired the key is usable again.
+        let later = now + TIMEOUT;
+        r.expire(later);
+        assert_eq!(r.memory(), 0);
+        assert!(r.insert(1, frag(0, true, b"01234567"), later).is_none());
+        assert!(r.insert(1, frag(8, false, b"ok"), later).is_some());
+    }
+
+    #[test]
+    fn conflicting_ends_poison() {
+        let mut r = Reassembler::new(TIMEOUT, usize::MAX);
+        let now = Instant::now();
+        assert!(r.insert(1, frag(8, false, b"end"), now).is_none());
+        assert!(r.insert(1, frag(16, false, b"later"), now).is_none());
+        assert!(r.insert(1, frag(0, true, b"01234567"), now).is_none());
+        assert_eq!(r.memory(), OVERHEAD);
+    }
+
+    #[test]
+    fn unaligned_middle_fragment_is_dropped() {
+        let mut r = Reassembler::new(TIMEOUT, usize::MAX);
+        assert!(
+            r.insert(1, frag(0, true, b"short"), Instant::now())
+                .is_none()
+        );
+        assert_eq!(r.memory(), 0);
+    }
+
+    #[test]
+    fn too_many_fragments_poison() {
+        let mut r = Reassembler::new(TIMEOUT, usize::MAX);
+        let now = Instant::now();
+        for i in 0..MAX_FRAGMENTS {
+            assert!(
+                r.insert(1, frag(8 * (i + 1), true, b"01234567"), now)
+                    .is_none()
+            );
+        }
+        assert!(r.insert(1, frag(0, true, b"01234567"), now).is_none());
+        assert_eq!(r.memory(), OVERHEAD);
+    }
+
+    #[test]
+    fn memory_limit_drops_the_oldest() {
+        let data = [0; 96];
+        // Room for one datagram with its first fragment, not two.
+        let one = OVERHEAD + data.len() + OVERHEAD;
+        let mut r = Reassembler::new(TIMEOUT, one + OVERHEAD);
+        let now = Instant::now();
+        assert!(r.insert(1, frag(0, true, &data), now).is_none());
+        assert!(r.insert(2, frag(0, true, &data), now).is_none());
+        assert_eq!(r.memory(), one);
+        assert!(r.insert(2, frag(96, false, b"end"), now).is_some());
+        assert!(r.insert(1, frag(96, false, b"end"), now).is_none());
+    }
+
+    #[test]
+    fn expired_datagrams_are_dropped() {
+        let mut r = Reassembler::new(TIMEOUT, usize::MAX);
+        let now = Instant::now();
+        assert!(r.insert(1, frag(0, true, b"01234567"), now).is_none());
+        r.expire(now + TIMEOUT - Duration::from_millis(1));
+        assert!(r.memory() > 0);
+        r.expire(now + TIMEOUT);
+        assert_eq!(r.memory(), 0);
+        assert_eq!(r.next_expiry(), None);
+        assert!(r.insert(1, frag(8, false, b"89"), now + TIMEOUT).is_none());
+    }
+}
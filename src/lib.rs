@@ -1,8 +1,17 @@
+pub mod clock;
 pub mod device;
 pub mod error;
+pub mod ip;
 pub mod stack;
 pub mod tcp;
 pub mod wire;
 
-pub use crate::stack::{Stack, StackConfig};
-pub use crate::tcp::socket::{TcpListener, TcpSocketAddr, TcpStream};
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::ip::ping::PingOptions;
+pub use crate::stack::{CongestionRoute, IpOptionsPolicy, Stack, StackConfig};
+pub use crate::tcp::auth::{AoAlgorithm, AuthKey, MasterKey, TcpAuth};
+pub use crate::tcp::metrics::DestMetrics;
+pub use crate::tcp::socket::{
+    ConnectOptions, Keepalive, ListenOptions, ListenerStats, SockOpt, TcpListener, TcpSocketAddr,
+    TcpStream,
+};
@@ -1,41 +1,345 @@
-use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::BuildHasher;
+use std::net::{self, IpAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
 
+use crate::clock::{Clock, SystemClock};
 use crate::device::NetDevice;
 use crate::error::*;
+use crate::ip::ping::PingOptions;
+use crate::ip::reassembly::{Fragment, Reassembler};
 use crate::tcp::{
-    conn::{Connection, Quad, State, TcpCmd},
-    timers::TimerWheel,
+    auth::{self, AuthState, Signer},
+    congestion::{CongestionAlgorithm, Cwv},
+    conn::{
+        self, Connection, DEFAULT_MSS, Quad, RCV_WND, RxAction, Segment, State, TcpCmd, Timestamps,
+    },
+    crypt::Tcpcrypt,
+    fastopen::FastOpenCache,
+    isn::IsnGenerator,
+    metrics::MetricsCache,
+    pacing::Pacer,
+    pmtu::{self, MtuProbing, Plpmtud, PmtuCache},
+    socket::{Keepalive, ListenOptions, ListenerStats, SockOpt, TcpStream},
+    syncookie::{self, CookieOptions, SYN_COOKIE_LIFETIME, SynCookies},
+    timers::{self, TimerWheel},
+    timewait::{TimeWaitAction, TimeWaitTable},
+};
+use crate::wire::{
+    icmpv4::{
+        self, CODE_FRAG_NEEDED, IcmpHeader, IcmpView, TYPE_DEST_UNREACHABLE, TYPE_ECHO_REPLY,
+        TYPE_ECHO_REQUEST,
+    },
+    ipv4::{self, ECN_CE, ECN_NOT_ECT, Ipv4Addr, Ipv4Header, Ipv4Option, parse_ipv4},
+    ipv6::{self, Extensions, Ipv6Header, Ipv6View, parse_ipv6},
+    tcp::{self, FLAG_ACK, FLAG_CWR, FLAG_ECE, FLAG_RST, FLAG_SYN, TcpHeader, TcpOption, TcpView},
 };
-use crate::wire::{ipv4::Ipv4Header, tcp::TcpHeader};
 
 #[derive(Clone, Debug)]
 pub struct StackConfig {
-    pub local_ip: [u8; 4],
+    /// Addresses the stack answers on, IPv4 and IPv6 alike.
+    pub local_ips: Vec<IpAddr>,
+    /// TTL, or hop limit for IPv6.
     pub ttl: u8,
     pub ident_seed: u16,
     pub mtu: usize,
+    /// What to do with datagrams carrying IP options the stack does not
+    /// implement.
+    pub ip_options: IpOptionsPolicy,
+    /// Give up on a fragmented datagram, IPv4 or IPv6, whose fragments have
+    /// not all arrived after this long.
+    pub reassembly_timeout: Duration,
+    /// Bound on memory held by fragments awaiting reassembly, per address
+    /// family; past it the oldest datagrams are dropped.
+    pub reassembly_memory: usize,
+    /// Answer ICMP echo requests.
+    pub echo_reply: bool,
+    /// Lower the path MTU on ICMP Fragmentation Needed (RFC 1191).
+    pub pmtu_discovery: bool,
+    /// Packetization layer PMTU discovery and black-hole detection
+    /// (RFC 4821), for paths where ICMP is filtered.
+    pub mtu_probing: MtuProbing,
+    /// Negotiate ECN (RFC 3168) on new connections.
+    pub ecn: bool,
+    /// Negotiate SACK (RFC 2018) on new connections.
+    pub sack: bool,
+    /// RACK-TLP loss detection (RFC 8985) on SACK connections.
+    pub rack: bool,
+    /// Pace transmissions at the congestion controller's rate.
+    pub pacing: bool,
+    /// Congestion window validation (RFC 7661) and restart after idle.
+    pub cwv: bool,
+    /// Seed new connections from per-destination metrics of closed ones.
+    pub metrics: bool,
+    /// Keepalive for new connections unless a socket overrides it.
+    pub keepalive: Option<Keepalive>,
+    /// User timeout for new connections unless a socket overrides it.
+    pub user_timeout: Option<Duration>,
+    /// Exchange user timeouts with peers via the UTO option (RFC 5482).
+    pub uto_option: bool,
+    /// Negotiate timestamps (RFC 7323) on new connections.
+    pub timestamps: bool,
+    /// Opportunistically encrypt streams with tcpcrypt (RFC 8548),
+    /// negotiated with TCP-ENO (RFC 8547); peers without it get plain TCP.
+    pub tcpcrypt: bool,
+    /// Bound on connections kept in TIME-WAIT; beyond it closing
+    /// connections skip TIME-WAIT.
+    pub time_wait_buckets: usize,
+    /// How long a closed connection waits in FIN-WAIT-2 for the peer's FIN.
+    pub fin_wait2_timeout: Duration,
+    /// Bound on closed connections still finishing; past it the oldest
+    /// are reset.
+    pub max_orphans: usize,
+    /// Retransmission timeouts before a closed connection is given up.
+    pub orphan_retries: u32,
+    /// Key for TCP Fast Open server cookies; `None` picks a random one.
+    pub fast_open_key: Option<[u8; 16]>,
+    /// Half-open connections per listener before further SYNs are answered
+    /// with SYN cookies, or dropped if those are off.
+    pub syn_backlog: usize,
+    pub syn_cookies: bool,
+    /// Congestion control for connections no route or listener overrides.
+    pub congestion: CongestionAlgorithm,
+    /// Per-destination congestion control overrides, longest prefix wins.
+    pub congestion_routes: Vec<CongestionRoute>,
+}
+
+/// Handling of received datagrams with IP options an end host has to act
+/// on but the stack does not implement: source routes, security labels and
+/// unknown kinds. Record route, timestamp, stream ID and router alert ask
+/// nothing of us and are always accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpOptionsPolicy {
+    /// Process the datagram as if they were absent (RFC 1122 §3.2.1.8).
+    Ignore,
+    Drop,
+}
+
+impl IpOptionsPolicy {
+    fn accepts(self, opt: &Ipv4Option<'_>) -> bool {
+        self == IpOptionsPolicy::Ignore
+            || matches!(
+                opt,
+                Ipv4Option::RecordRoute { .. }
+                    | Ipv4Option::Timestamp { .. }
+                    | Ipv4Option::StreamId(_)
+                    | Ipv4Option::RouterAlert(_)
+            )
+    }
+}
+
+/// Use `algorithm` for peers inside `prefix/prefix_len`.
+#[derive(Clone, Debug)]
+pub struct CongestionRoute {
+    pub prefix: IpAddr,
+    pub prefix_len: u8,
+    pub algorithm: CongestionAlgorithm,
+}
+
+impl CongestionRoute {
+    fn matches(&self, ip: IpAddr) -> bool {
+        let (ip, prefix, bits) = match (ip, self.prefix) {
+            (IpAddr::V4(ip), IpAddr::V4(prefix)) => {
+                (u32::from(ip) as u128, u32::from(prefix) as u128, 32)
+            }
+            (IpAddr::V6(ip), IpAddr::V6(prefix)) => (u128::from(ip), u128::from(prefix), 128),
+            _ => return false,
+        };
+        let mask = u128::MAX
+            .checked_shl(bits - (self.prefix_len as u32).min(bits))
+            .unwrap_or(0);
+        ip & mask == prefix & mask
+    }
+}
+
+impl Default for StackConfig {
+    fn default() -> Self {
+        Self {
+            local_ips: Vec::new(),
+            ttl: 64,
+            ident_seed: 1,
+            mtu: 1500,
+            ip_options: IpOptionsPolicy::Ignore,
+            reassembly_timeout: Duration::from_secs(30),
+            reassembly_memory: 4 << 20,
+            echo_reply: true,
+            pmtu_discovery: true,
+            mtu_probing: MtuProbing::BlackHole,
+            ecn: true,
+            sack: true,
+            rack: true,
+            pacing: true,
+            cwv: true,
+            metrics: true,
+            keepalive: None,
+            user_timeout: None,
+            uto_option: false,
+            timestamps: true,
+            tcpcrypt: false,
+            time_wait_buckets: 32768,
+            fin_wait2_timeout: Duration::from_secs(60),
+            max_orphans: 8192,
+            orphan_retries: 8,
+            fast_open_key: None,
+            syn_backlog: 256,
+            syn_cookies: true,
+            congestion: CongestionAlgorithm::Reno,
+            congestion_routes: Vec::new(),
+        }
+    }
 }
 
 pub struct Stack<D: NetDevice> {
     dev: D,
     cfg: StackConfig,
     conns: HashMap<Quad, Connection>,
-    listeners: HashMap<u16, ()>, // port -> placeholder
+    listeners: HashMap<u16, Listener>,
+    /// Streams for passive opens, handed to `accept()` once established.
+    pending_accept: HashMap<Quad, TcpStream>,
+    /// Orphaned connections, oldest first.
+    orphans: BTreeSet<(Instant, Quad)>,
+    metrics: MetricsCache,
+    pmtu: PmtuCache,
+    fastopen: FastOpenCache,
+    syncookies: SynCookies,
+    timewait: TimeWaitTable,
+    /// Inbound fragments by (src, dst, protocol, ident).
+    reassembly: Reassembler<([u8; 4], [u8; 4], u8, u16)>,
+    /// Inbound IPv6 fragments by (src, dst, ident).
+    reassembly6: Reassembler<([u8; 16], [u8; 16], u32)>,
+    /// Identification of the next datagram sent.
+    ident: AtomicU16,
+    /// Identification of the next IPv6 packet sent in fragments.
+    ident6: AtomicU32,
+    /// Echo requests awaiting their reply, by sequence number.
+    pings: HashMap<u16, Ping>,
+    /// Identifier of our echo requests.
+    ping_ident: u16,
+    ping_seq: u16,
+    timers: TimerWheel<Timer>,
+    isn: IsnGenerator,
+    clock: Arc<dyn Clock>,
+    /// Origin of our timestamp clock.
+    epoch: Instant,
     tx_cmd: mpsc::Sender<TcpCmd>,
     rx_cmd: mpsc::Receiver<TcpCmd>,
+    /// Closes from dropped streams, which cannot wait for room in `rx_cmd`.
+    tx_drop: mpsc::UnboundedSender<TcpCmd>,
+    rx_drop: mpsc::UnboundedReceiver<TcpCmd>,
+}
+
+/// A datagram addressed to us, whole and of either family.
+struct Datagram<'a> {
+    src: IpAddr,
+    dst: IpAddr,
+    proto: u8,
+    ecn: u8,
+    payload: &'a [u8],
+}
+
+impl<'a> Datagram<'a> {
+    fn v6(ip: &Ipv6View<'a>, ext: &Extensions<'a>) -> Self {
+        Self {
+            src: ip.src.into(),
+            dst: ip.dst.into(),
+            proto: ext.next_header,
+            ecn: ip.ecn,
+            payload: ext.payload,
+        }
+    }
+}
+
+impl<'a> From<ipv4::Ipv4View<'a>> for Datagram<'a> {
+    fn from(ip: ipv4::Ipv4View<'a>) -> Self {
+        Self {
+            src: ip.src.into(),
+            dst: ip.dst.into(),
+            proto: ip.proto,
+            ecn: ip.ecn,
+            payload: ip.payload,
+        }
+    }
+}
+
+/// What a deadline on the stack's timer wheel is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Timer {
+    /// The earliest of the connection's timers.
+    Conn(Quad),
+    /// The oldest TIME-WAIT entry expires.
+    TimeWait,
+    /// The oldest datagram awaiting reassembly expires.
+    Reassembly,
+    /// An echo request goes unanswered.
+    Ping(u16),
+}
+
+struct Ping {
+    dst: [u8; 4],
+    sent: Instant,
+    data: Vec<u8>,
+    reply: oneshot::Sender<Result<Duration>>,
+}
+
+struct Listener {
+    accept: mpsc::Sender<TcpStream>,
+    opts: ListenOptions,
+    stats: ListenerStats,
+    /// When a SYN cookie was last sent; ACKs are checked for one until it
+    /// would have expired.
+    last_cookie: Option<Instant>,
+    /// Half-open connections whose SYN data was accepted under Fast Open.
+    tfo_pending: usize,
 }
 
 impl<D: NetDevice> Stack<D> {
     pub fn new(dev: D, cfg: StackConfig) -> Self {
+        Self::with_clock(dev, cfg, Arc::new(SystemClock))
+    }
+
+    /// A stack whose timers run on `clock`, e.g. a `ManualClock` that
+    /// tests advance step by step.
+    pub fn with_clock(dev: D, cfg: StackConfig, clock: Arc<dyn Clock>) -> Self {
         let (tx_cmd, rx_cmd) = mpsc::channel(1024);
+        let (tx_drop, rx_drop) = mpsc::unbounded_channel();
+        let fastopen = FastOpenCache::new(cfg.fast_open_key);
+        let timewait = TimeWaitTable::new(cfg.time_wait_buckets);
+        let reassembly = Reassembler::new(cfg.reassembly_timeout, cfg.reassembly_memory);
+        let reassembly6 = Reassembler::new(cfg.reassembly_timeout, cfg.reassembly_memory);
+        let ident = AtomicU16::new(cfg.ident_seed);
+        let ident6 = AtomicU32::new(cfg.ident_seed as u32);
+        let epoch = clock.now();
         Self {
             dev,
             cfg,
             conns: HashMap::new(),
             listeners: HashMap::new(),
+            pending_accept: HashMap::new(),
+            orphans: BTreeSet::new(),
+            metrics: MetricsCache::default(),
+            pmtu: PmtuCache::default(),
+            fastopen,
+            syncookies: SynCookies::new(epoch),
+            timewait,
+            reassembly,
+            reassembly6,
+            ident,
+            ident6,
+            pings: HashMap::new(),
+            ping_ident: RandomState::new().hash_one(epoch) as u16,
+            ping_seq: 0,
+            timers: TimerWheel::new(epoch),
+            isn: IsnGenerator::new(epoch),
+            clock,
+            epoch,
             tx_cmd,
             rx_cmd,
+            tx_drop,
+            rx_drop,
         }
     }
 
@@ -43,91 +347,1061 @@ impl<D: NetDevice> Stack<D> {
         self.tx_cmd.clone()
     }
 
-    /// Main event loop: device frames, control cmds, and timers.
+    /// Main event loop: device frames, control cmds, and timers. An idle
+    /// stack sleeps until its next deadline.
     pub async fn run(mut self) -> Result<()> {
-        let mut timers = TimerWheel::new();
+        let clock = self.clock.clone();
         loop {
+            let deadline = self.timers.next_deadline();
             tokio::select! {
                 // Inbound frame from device
                 frame = self.dev.recv() => {
                     let frame = frame?;
-                    self.on_frame(&frame)?;
+                    self.on_frame(&frame).await?;
                 }
                 // Control plane (connect/listen/send/close)
                 Some(cmd) = self.rx_cmd.recv() => {
                     self.on_cmd(cmd).await?;
                 }
-                // Timer ticks
-                _ = timers.tick() => {
-                    self.on_tick()?;
+                // A stream was dropped. What it sent before that is queued
+                // on the control channel and goes first.
+                Some(cmd) = self.rx_drop.recv() => {
+                    while let Ok(queued) = self.rx_cmd.try_recv() {
+                        self.on_cmd(queued).await?;
+                    }
+                    self.on_cmd(cmd).await?;
+                }
+                // Connection and TIME-WAIT deadlines
+                _ = timers::sleep_until(&*clock, deadline) => {
+                    self.on_deadlines().await?;
                 }
             }
         }
     }
 
-    fn on_frame(&mut self, frame: &[u8]) -> Result<()> {
-        // Parse IPv4 + TCP, route to connection
-        // NOTE: skeleton only
-        let _ = frame;
-        Err(UrtcpError::NotImplemented("parse ipv4/tcp"))
+    async fn on_frame(&mut self, frame: &[u8]) -> Result<()> {
+        // Malformed or foreign traffic is dropped, never fatal to the stack.
+        match frame.first().map(|b| b >> 4) {
+            Some(4) => self.on_ipv4(frame).await,
+            Some(6) => self.on_ipv6(frame).await,
+            _ => Ok(()),
+        }
     }
 
-    async fn on_cmd(&mut self, cmd: TcpCmd) -> Result<()> {
-        match cmd {
-            TcpCmd::Connect(id, reply) => {
-                // Create connection in SynSent, send SYN
-                let (app_rx_s, _app_rx_r) = tokio::sync::mpsc::channel(64);
-                let (_app_tx_s, app_tx_r) = tokio::sync::mpsc::channel(64);
-                let mut conn = Connection::new(id, State::SynSent, app_rx_s, app_tx_r);
-                // Build SYN
-                let tcp = TcpHeader {
-                    src_port: id.src_port,
-                    dst_port: id.dst_port,
-                    seq: conn.iss,
-                    ack: 0,
-                    data_offset: 5,
-                    flags: crate::wire::tcp::FLAG_SYN,
-                    window: 65535,
-                    urg_ptr: 0,
-                    options: bytes::BytesMut::new(),
+    fn is_local(&self, ip: IpAddr) -> bool {
+        self.cfg.local_ips.contains(&ip)
+    }
+
+    async fn on_ipv4(&mut self, frame: &[u8]) -> Result<()> {
+        let Some(ip) = parse_ipv4(frame) else {
+            return Ok(());
+        };
+        if !self.is_local(ip.dst.into()) {
+            return Ok(());
+        }
+        if let Some(opt) = ip.options().find(|o| !self.cfg.ip_options.accepts(o)) {
+            tracing::debug!(src = ?ip.src, kind = opt.kind(), "dropping datagram with IP option");
+            return Ok(());
+        }
+        if !ip.is_fragment() {
+            return self.on_datagram(ip.into()).await;
+        }
+        if ip.ihl_bytes + ip.frag_offset + ip.payload.len() > u16::MAX as usize {
+            tracing::debug!(src = ?ip.src, "dropping fragment past the maximum datagram size");
+            return Ok(());
+        }
+        let key = (ip.src, ip.dst, ip.proto, ip.ident);
+        let frag = Fragment {
+            offset: ip.frag_offset,
+            more: ip.more_fragments,
+            data: ip.payload,
+            ce: ip.ecn == ECN_CE,
+            header: ip.header,
+        };
+        let done = self.reassembly.insert(key, frag, self.clock.now());
+        self.rearm_reassembly();
+        let Some(done) = done else {
+            return Ok(());
+        };
+        let datagram = ipv4::defragmented(&done.header, &done.payload, done.ce);
+        match parse_ipv4(&datagram) {
+            Some(ip) => self.on_datagram(ip.into()).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn on_ipv6(&mut self, frame: &[u8]) -> Result<()> {
+        let Some(ip) = parse_ipv6(frame) else {
+            return Ok(());
+        };
+        if !self.is_local(ip.dst.into()) {
+            return Ok(());
+        }
+        let Some(ext) = ip.extensions() else {
+            tracing::debug!(src = ?ip.src, "dropping packet for its extension headers");
+            return Ok(());
+        };
+        let Some(frag) = ext.fragment else {
+            return self.on_datagram(Datagram::v6(&ip, &ext)).await;
+        };
+        if ext.headers.len() + frag.offset + ext.payload.len() > u16::MAX as usize {
+            tracing::debug!(src = ?ip.src, "dropping fragment past the maximum packet size");
+            return Ok(());
+        }
+        let header = match frag.offset {
+            0 => ext.unfragmentable(ip.header),
+            _ => Vec::new(),
+        };
+        let key = (ip.src, ip.dst, frag.ident);
+        let frag = Fragment {
+            offset: frag.offset,
+            more: frag.more,
+            data: ext.payload,
+            ce: ip.ecn == ECN_CE,
+            header: &header,
+        };
+        let done = self.reassembly6.insert(key, frag, self.clock.now());
+        self.rearm_reassembly();
+        let Some(done) = done else {
+            return Ok(());
+        };
+        let packet = ipv6::defragmented(&done.header, &done.payload, done.ce);
+        let Some(ip) = parse_ipv6(&packet) else {
+            return Ok(());
+        };
+        // A Fragment header inside the reassembled packet is not undone again.
+        match ip.extensions().filter(|ext| ext.fragment.is_none()) {
+            Some(ext) => self.on_datagram(Datagram::v6(&ip, &ext)).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn on_datagram(&mut self, ip: Datagram<'_>) -> Result<()> {
+        if let (1, IpAddr::V4(src), IpAddr::V4(dst)) = (ip.proto, ip.src, ip.dst) {
+            return self.on_icmp(src.octets(), dst.octets(), ip.payload).await;
+        }
+        if ip.proto != 6 {
+            return Ok(());
+        }
+        if !tcp::verify_checksum(ip.payload, ip.src, ip.dst) {
+            tracing::debug!(src = ?ip.src, "dropping segment with bad checksum");
+            return Ok(());
+        }
+        let Some(tv) = tcp::parse(ip.payload) else {
+            return Ok(());
+        };
+        let id = Quad {
+            src_ip: ip.dst,
+            src_port: tv.dst_port,
+            dst_ip: ip.src,
+            dst_port: tv.src_port,
+        };
+        if !self.authenticate(&id, &tv, ip.payload) {
+            tracing::debug!(?id, "dropping segment failing authentication");
+            return Ok(());
+        }
+
+        match self.timewait.on_segment(&id, &tv, self.clock.now()) {
+            None | Some(TimeWaitAction::Reopen) => {}
+            Some(TimeWaitAction::Drop) => return Ok(()),
+            Some(TimeWaitAction::Ack) => {
+                return match self.timewait.ack(&id, self.clock.now()) {
+                    Some(seg) => self.transmit(id, seg).await,
+                    None => Ok(()),
+                };
+            }
+        }
+
+        if !self.conns.contains_key(&id) && tv.flags & (FLAG_SYN | FLAG_ACK | FLAG_RST) == FLAG_ACK
+        {
+            self.on_cookie_ack(id, &tv);
+        }
+
+        if let Some(conn) = self.conns.get_mut(&id) {
+            let was_syn_rcvd = matches!(conn.state, State::SynReceived);
+            let seg = match conn.on_segment(ip.payload, ip.ecn)? {
+                RxAction::None => None,
+                RxAction::SendAck => Some(conn.segment(conn.snd_nxt, FLAG_ACK, Vec::new())),
+                RxAction::SendSynAck => {
+                    Some(conn.segment(conn.iss, FLAG_SYN | FLAG_ACK, Vec::new()))
+                }
+            };
+            let established = was_syn_rcvd && matches!(conn.state, State::Established);
+            if let Some(cookie) = conn.fast_open.new_cookie.take() {
+                self.fastopen.store(id.dst_ip, cookie);
+            }
+            // Control segments queued while processing go out ahead of the reply.
+            let early: Vec<Segment> = conn.outbox.drain(..).collect();
+            for seg in early {
+                self.transmit(id, seg).await?;
+            }
+            if let Some(seg) = seg {
+                self.transmit(id, seg).await?;
+            }
+            if established
+                && let (Some(stream), Some(q)) = (
+                    self.pending_accept.remove(&id),
+                    self.listeners.get(&id.src_port),
+                )
+            {
+                let _ = q.accept.try_send(stream);
+            }
+            self.flush(id).await?;
+            self.reap(id);
+            return Ok(());
+        }
+
+        let is_syn = tv.flags & (FLAG_SYN | FLAG_ACK | FLAG_RST) == FLAG_SYN;
+        let Some(listener) = self.listeners.get(&tv.dst_port).filter(|_| is_syn) else {
+            return Ok(());
+        };
+        // Passive open
+        let fast_open = listener.opts.fast_open;
+        let tfo_pending = listener.tfo_pending;
+        if listener.stats.half_open >= self.cfg.syn_backlog {
+            return self.on_syn_overflow(id, &tv).await;
+        }
+        let iss = self.isn.isn(&id, self.clock.now());
+        let (mut conn, stream) = self.passive_conn(id, iss, tv.seq, tv.window);
+        conn.sack_ok = self.cfg.sack && tv.options().any(|o| o == TcpOption::SackPermitted);
+        if let Some(TcpOption::UserTimeout { minutes, timeout }) = tv
+            .options()
+            .find(|o| matches!(o, TcpOption::UserTimeout { .. }))
+            .filter(|_| conn.uto_enabled)
+        {
+            conn.peer_uto = Some(tcp::user_timeout_duration(minutes, timeout));
+        }
+        // ECN-setup SYN carries both ECE and CWR
+        conn.ecn.enabled = self.cfg.ecn && tv.flags & (FLAG_ECE | FLAG_CWR) == FLAG_ECE | FLAG_CWR;
+        if self.cfg.timestamps
+            && let Some(TcpOption::Timestamps { val, .. }) = tv
+                .options()
+                .find(|o| matches!(o, TcpOption::Timestamps { .. }))
+        {
+            conn.ts = Some(self.timestamps(val));
+        }
+        if self.cfg.tcpcrypt {
+            conn.crypt = conn::eno_option(&tv).and_then(Tcpcrypt::passive);
+        }
+        if let Some(max_pending) = fast_open.filter(|_| conn.auth.is_none() && conn.crypt.is_none())
+            && let Some(TcpOption::FastOpen(cookie)) =
+                tv.options().find(|o| matches!(o, TcpOption::FastOpen(_)))
+        {
+            if self.fastopen.valid(id.dst_ip, cookie) && tfo_pending < max_pending {
+                conn.accept_syn_data(tv.payload);
+            } else {
+                // A request, a stale cookie or too many pending: hand out
+                // a cookie and complete a regular handshake.
+                conn.fast_open.cookie = Some(self.fastopen.cookie(id.dst_ip).to_vec());
+            }
+        }
+        let seg = conn.segment(conn.iss, FLAG_SYN | FLAG_ACK, Vec::new());
+        conn.timers.rto = Some(self.clock.now() + conn.rto);
+        let accepted = conn.fast_open.accepted;
+        conn.half_open = true;
+        if let Some(listener) = self.listeners.get_mut(&id.src_port) {
+            listener.stats.half_open += 1;
+            listener.tfo_pending += accepted as usize;
+        }
+        self.conns.insert(id, conn);
+        self.rearm(id);
+        if accepted {
+            // SYN data is for the application now, not after the handshake.
+            if let Some(listener) = self.listeners.get(&id.src_port) {
+                let _ = listener.accept.try_send(stream);
+            }
+        } else {
+            self.pending_accept.insert(id, stream);
+        }
+        self.transmit(id, seg).await
+    }
+
+    /// An ICMP message from `src` to our `dst`.
+    async fn on_icmp(&mut self, src: [u8; 4], dst: [u8; 4], msg: &[u8]) -> Result<()> {
+        let Some(icmp) = icmpv4::parse(msg) else {
+            return Ok(());
+        };
+        match (icmp.ty, icmp.code) {
+            (TYPE_ECHO_REQUEST, 0) if self.cfg.echo_reply => {
+                self.on_echo_request(src, dst, &icmp).await
+            }
+            (TYPE_ECHO_REPLY, 0) => {
+                self.on_echo_reply(src, &icmp);
+                Ok(())
+            }
+            (TYPE_DEST_UNREACHABLE, CODE_FRAG_NEEDED) if self.cfg.pmtu_discovery => {
+                self.on_frag_needed(&icmp).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Echo the request back, from the address it was sent to.
+    async fn on_echo_request(&self, src: [u8; 4], dst: [u8; 4], icmp: &IcmpView<'_>) -> Result<()> {
+        let peer = net::Ipv4Addr::from(src);
+        if peer.is_broadcast() || peer.is_multicast() || peer.is_unspecified() {
+            return Ok(());
+        }
+        let (ident, seq) = icmp.echo().unwrap_or_default();
+        let msg = IcmpHeader::echo(TYPE_ECHO_REPLY, ident, seq).encode(icmp.payload);
+        let hdr = Ipv4Header {
+            src: Ipv4Addr(dst),
+            dst: Ipv4Addr(src),
+            proto: 1,
+            ident: 0,
+            ttl: self.cfg.ttl,
+            ecn: ECN_NOT_ECT,
+            dont_fragment: false,
+        };
+        self.send_ip(hdr, &msg).await
+    }
+
+    /// A reply to one of our echo requests, if it matches one by address,
+    /// identifier, sequence number and data.
+    fn on_echo_reply(&mut self, src: [u8; 4], icmp: &IcmpView<'_>) {
+        let Some((_, seq)) = icmp.echo().filter(|(ident, _)| *ident == self.ping_ident) else {
+            return;
+        };
+        if !self
+            .pings
+            .get(&seq)
+            .is_some_and(|p| p.dst == src && p.data == icmp.payload)
+        {
+            return;
+        }
+        let Some(ping) = self.pings.remove(&seq) else {
+            return;
+        };
+        self.timers.cancel(&Timer::Ping(seq));
+        let rtt = self.clock.now().saturating_duration_since(ping.sent);
+        let _ = ping.reply.send(Ok(rtt));
+    }
+
+    /// Send an echo request to `dst`, to be answered on `reply`.
+    async fn ping(
+        &mut self,
+        dst: net::Ipv4Addr,
+        opts: PingOptions,
+        reply: oneshot::Sender<Result<Duration>>,
+    ) -> Result<()> {
+        let Some(src) = self.cfg.local_ips.iter().find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(ip.octets()),
+            IpAddr::V6(_) => None,
+        }) else {
+            let _ = reply.send(Err(UrtcpError::AddrNotAvailable));
+            return Ok(());
+        };
+        if self.pings.len() > u16::MAX as usize {
+            let _ = reply.send(Err(UrtcpError::WouldBlock));
+            return Ok(());
+        }
+        while self.pings.contains_key(&self.ping_seq) {
+            self.ping_seq = self.ping_seq.wrapping_add(1);
+        }
+        let seq = self.ping_seq;
+        self.ping_seq = seq.wrapping_add(1);
+        let data: Vec<u8> = (0..opts.size).map(|i| i as u8).collect();
+        let msg = IcmpHeader::echo(TYPE_ECHO_REQUEST, self.ping_ident, seq).encode(&data);
+        let now = self.clock.now();
+        self.pings.insert(
+            seq,
+            Ping {
+                dst: dst.octets(),
+                sent: now,
+                data,
+                reply,
+            },
+        );
+        self.timers.schedule(Timer::Ping(seq), now + opts.timeout);
+        let hdr = Ipv4Header {
+            src: Ipv4Addr(src),
+            dst: Ipv4Addr(dst.octets()),
+            proto: 1,
+            ident: 0,
+            ttl: self.cfg.ttl,
+            ecn: ECN_NOT_ECT,
+            dont_fragment: false,
+        };
+        self.send_ip(hdr, &msg).await
+    }
+
+    /// ICMP Fragmentation Needed for one of our segments: lower the path
+    /// MTU to its destination and resend what no longer fits.
+    async fn on_frag_needed(&mut self, icmp: &IcmpView<'_>) -> Result<()> {
+        let Some(quoted) = icmp.quoted() else {
+            return Ok(());
+        };
+        let t = quoted.transport;
+        if quoted.proto != 6 || !self.is_local(quoted.src.into()) {
+            return Ok(());
+        }
+        let id = Quad {
+            src_ip: quoted.src.into(),
+            src_port: u16::from_be_bytes([t[0], t[1]]),
+            dst_ip: quoted.dst.into(),
+            dst_port: u16::from_be_bytes([t[2], t[3]]),
+        };
+        let seq = u32::from_be_bytes([t[4], t[5], t[6], t[7]]);
+        // Only for data in flight, so blind forgeries are unlikely to hit
+        // (RFC 5927 §4.1).
+        if !self
+            .conns
+            .get(&id)
+            .is_some_and(|c| conn::seq_le(c.snd_una, seq) && conn::seq_lt(seq, c.snd_nxt))
+        {
+            return Ok(());
+        }
+        let mtu = pmtu::frag_needed_mtu(icmp.next_hop_mtu(), quoted.total_len);
+        if self.pmtu.lower(id.dst_ip, mtu, self.clock.now()) {
+            tracing::debug!(dst = ?id.dst_ip, mtu, "path MTU lowered");
+        }
+        let ids: Vec<Quad> = self
+            .conns
+            .keys()
+            .filter(|q| q.dst_ip == id.dst_ip)
+            .copied()
+            .collect();
+        for id in ids {
+            if let Some(conn) = self.conns.get_mut(&id)
+                && conn.on_path_mtu(mtu)
+            {
+                self.flush(id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `seg` is signed as configured for `id`, by its connection,
+    /// TIME-WAIT entry or listener. Failures are counted against the
+    /// connection, else the listening port.
+    fn authenticate(&mut self, id: &Quad, tv: &TcpView<'_>, seg: &[u8]) -> bool {
+        let (src, dst) = (id.dst_ip, id.src_ip);
+        if let Some(conn) = self.conns.get_mut(id) {
+            let ok = auth::accept(conn.auth.as_mut(), id, tv, seg, src, dst);
+            if !ok {
+                conn.stats.auth_failures += 1;
+            }
+            return ok;
+        }
+        let ok = match self.timewait.auth(id) {
+            Some(auth) => auth::accept(auth.as_mut(), id, tv, seg, src, dst),
+            None => {
+                let Some(listener) = self.listeners.get(&id.src_port) else {
+                    return true;
                 };
-                let seg = tcp.encode(&[], id.src_ip, id.dst_ip);
+                // Keyed as the SYN, or as the ACK completing a cookie
+                // handshake, would be.
+                let mut auth = listener.opts.auth.get(&id.dst_ip).map(|a| {
+                    AuthState::new(a, tv.ack.wrapping_sub(1), Some(tv.seq.wrapping_sub(1)))
+                });
+                auth::accept(auth.as_mut(), id, tv, seg, src, dst)
+            }
+        };
+        if !ok && let Some(listener) = self.listeners.get_mut(&id.src_port) {
+            listener.stats.auth_failures += 1;
+        }
+        ok
+    }
+
+    /// A connection in SYN-RECEIVED with the stack's per-connection settings,
+    /// and the stream to hand to `accept()` once it is established.
+    fn passive_conn(
+        &self,
+        id: Quad,
+        iss: u32,
+        peer_isn: u32,
+        window: u16,
+    ) -> (Connection, TcpStream) {
+        let algorithm = self
+            .listeners
+            .get(&id.src_port)
+            .and_then(|l| l.opts.congestion)
+            .unwrap_or_else(|| self.congestion_for(id.dst_ip));
+        let (app_rx_s, app_rx_r) = tokio::sync::mpsc::channel(64);
+        let (_app_tx_s, app_tx_r) = tokio::sync::mpsc::channel(64);
+        let mut conn = Connection::new(
+            id,
+            State::SynReceived,
+            app_rx_s,
+            app_tx_r,
+            self.clock.clone(),
+        );
+        conn.iss = iss;
+        conn.rcv_nxt = peer_isn.wrapping_add(1);
+        conn.snd_una = conn.iss;
+        conn.snd_nxt = conn.iss.wrapping_add(1);
+        conn.snd_wnd = window as u32;
+        conn.cc = algorithm.build();
+        conn.rack_enabled = self.cfg.rack;
+        conn.pacer = self.cfg.pacing.then(Pacer::default);
+        conn.cwv = self.cfg.cwv.then(Cwv::default);
+        conn.keepalive = self.cfg.keepalive;
+        conn.uto_enabled = self.cfg.uto_option;
+        conn.set_user_timeout(self.cfg.user_timeout);
+        self.set_path_mtu(&mut conn);
+        conn.auth = self
+            .listeners
+            .get(&id.src_port)
+            .and_then(|l| l.opts.auth.get(&id.dst_ip))
+            .map(|a| AuthState::new(a, iss, Some(peer_isn)));
+        if self.cfg.metrics {
+            self.metrics.seed(&mut conn, self.clock.now());
+        }
+        let stream = TcpStream::from_parts(id, self.tx_cmd.clone(), self.tx_drop.clone(), app_rx_r);
+        (conn, stream)
+    }
+
+    /// The listener's half-open queue is full: answer with a SYN cookie and
+    /// keep no state, or drop the SYN when cookies are off.
+    async fn on_syn_overflow(&mut self, id: Quad, tv: &TcpView<'_>) -> Result<()> {
+        let now = self.clock.now();
+        let Some(listener) = self.listeners.get_mut(&id.src_port) else {
+            return Ok(());
+        };
+        let auth = listener.opts.auth.get(&id.dst_ip).cloned();
+        if !self.cfg.syn_cookies {
+            listener.stats.syn_drops += 1;
+            return Ok(());
+        }
+        listener.stats.syn_cookies_sent += 1;
+        listener.last_cookie = Some(now);
+        let mss = tv
+            .options()
+            .find_map(|o| match o {
+                TcpOption::Mss(mss) => Some(mss),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_MSS as u16);
+        let cookie = self
+            .syncookies
+            .make(&id, tv.seq, SynCookies::mss_index(mss), now);
+        let mut hdr = TcpHeader {
+            src_port: id.src_port,
+            dst_port: id.dst_port,
+            seq: cookie,
+            ack: tv.seq.wrapping_add(1),
+            data_offset: 5,
+            flags: FLAG_SYN | FLAG_ACK,
+            window: 65535,
+            urg_ptr: 0,
+            options: Default::default(),
+        };
+        // Without a timestamp to carry them in, the cookie handshake goes
+        // without SACK and ECN.
+        let mut opts = Vec::new();
+        if self.cfg.timestamps
+            && let Some(TcpOption::Timestamps { val, .. }) = tv
+                .options()
+                .find(|o| matches!(o, TcpOption::Timestamps { .. }))
+        {
+            let negotiated = CookieOptions {
+                wscale: tv.options().find_map(|o| match o {
+                    TcpOption::WindowScale(shift) => Some(shift),
+                    _ => None,
+                }),
+                sack: self.cfg.sack && tv.options().any(|o| o == TcpOption::SackPermitted),
+                ecn: self.cfg.ecn && tv.flags & (FLAG_ECE | FLAG_CWR) == FLAG_ECE | FLAG_CWR,
+            };
+            if negotiated.sack {
+                opts.push(TcpOption::SackPermitted);
+            }
+            if negotiated.ecn {
+                hdr.flags |= FLAG_ECE;
+            }
+            opts.push(TcpOption::Timestamps {
+                val: syncookie::encode_ts(self.timestamps(val).val(now), negotiated),
+                ecr: val,
+            });
+        }
+        if !opts.is_empty() {
+            hdr.set_options(&opts);
+        }
+        let signer = auth.and_then(|a| AuthState::new(&a, cookie, Some(tv.seq)).signer(&id, &hdr));
+        let seg = Segment {
+            hdr,
+            ecn: ECN_NOT_ECT,
+            payload: Vec::new(),
+        };
+        self.transmit_signed(id, seg, signer.as_ref()).await
+    }
+
+    /// An ACK for a listening port without a connection may complete a
+    /// handshake answered with a SYN cookie: rebuild the SYN-RECEIVED state
+    /// the cookie stands for, so the ACK is then processed as usual.
+    fn on_cookie_ack(&mut self, id: Quad, tv: &TcpView<'_>) {
+        let now = self.clock.now();
+        let Some(listener) = self.listeners.get_mut(&id.src_port) else {
+            return;
+        };
+        if listener
+            .last_cookie
+            .is_none_or(|t| now.saturating_duration_since(t) > SYN_COOKIE_LIFETIME)
+        {
+            return;
+        }
+        let peer_isn = tv.seq.wrapping_sub(1);
+        let iss = tv.ack.wrapping_sub(1);
+        let Some(mss) = self.syncookies.check(&id, peer_isn, iss, now) else {
+            listener.stats.syn_cookies_failed += 1;
+            return;
+        };
+        listener.stats.syn_cookies_validated += 1;
+        let (mut conn, stream) = self.passive_conn(id, iss, peer_isn, tv.window);
+        conn.mss = conn.mss.min(mss as usize);
+        if self.cfg.timestamps
+            && let Some(TcpOption::Timestamps { ecr, .. }) = tv
+                .options()
+                .find(|o| matches!(o, TcpOption::Timestamps { .. }))
+        {
+            // Windows are never scaled here: the SYN-ACK carried no window
+            // scale option, whatever the peer asked for.
+            let negotiated = syncookie::decode_ts(ecr);
+            conn.sack_ok = self.cfg.sack && negotiated.sack;
+            conn.ecn.enabled = self.cfg.ecn && negotiated.ecn;
+            conn.ts = Some(self.timestamps(0));
+        }
+        // As if our SYN-ACK had been sent from this state.
+        conn.last_ack_sent = conn.rcv_nxt;
+        self.conns.insert(id, conn);
+        self.pending_accept.insert(id, stream);
+    }
+
+    /// Size a new connection's segments for the interface and what is known
+    /// about the path, and set up probing for more.
+    fn set_path_mtu(&self, conn: &mut Connection) {
+        let dst = conn.id.dst_ip;
+        let mtu = self.path_mtu(dst);
+        conn.mss = conn.mss.min(pmtu::mss_for(mtu, dst));
+        if self.cfg.mtu_probing != MtuProbing::Off {
+            let probing = self.cfg.mtu_probing == MtuProbing::Always;
+            conn.plpmtud = Some(Plpmtud::new(probing, pmtu::mss_for(self.cfg.mtu, dst)));
+        }
+    }
+
+    /// What the interface and the path to `dst`, as far as known, carry.
+    fn path_mtu(&self, dst: IpAddr) -> usize {
+        self.pmtu
+            .get(dst, self.clock.now())
+            .map_or(self.cfg.mtu, |m| m.min(self.cfg.mtu))
+    }
+
+    /// Timestamp state for a new connection, echoing `recent`.
+    fn timestamps(&self, recent: u32) -> Timestamps {
+        Timestamps {
+            epoch: self.epoch,
+            recent,
+        }
+    }
+
+    /// Congestion control for an active open, or a passive one whose listener
+    /// has no preference.
+    fn congestion_for(&self, peer: IpAddr) -> CongestionAlgorithm {
+        self.cfg
+            .congestion_routes
+            .iter()
+            .filter(|r| r.matches(peer))
+            .max_by_key(|r| r.prefix_len)
+            .map_or(self.cfg.congestion, |r| r.algorithm)
+    }
+
+    /// Sign a segment as its connection or TIME-WAIT entry requires, then
+    /// send it.
+    async fn transmit(&mut self, id: Quad, seg: Segment) -> Result<()> {
+        let auth = match self.conns.get_mut(&id) {
+            Some(conn) => conn.auth.as_mut(),
+            None => self.timewait.auth(&id).and_then(Option::as_mut),
+        };
+        let signer = auth.and_then(|a| a.signer(&id, &seg.hdr));
+        self.transmit_signed(id, seg, signer.as_ref()).await
+    }
+
+    /// Encapsulate a segment in IPv4 or IPv6 and hand it to the device.
+    async fn transmit_signed(&self, id: Quad, seg: Segment, signer: Option<&Signer>) -> Result<()> {
+        let tcp = match signer {
+            Some(signer) => seg
+                .hdr
+                .encode_signed(&seg.payload, id.src_ip, id.dst_ip, signer),
+            None => seg.hdr.encode(&seg.payload, id.src_ip, id.dst_ip),
+        };
+        match (id.src_ip, id.dst_ip) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
                 let ip = Ipv4Header {
-                    src: crate::wire::ipv4::Ipv4Addr(id.src_ip),
-                    dst: crate::wire::ipv4::Ipv4Addr(id.dst_ip),
+                    src: Ipv4Addr(src.octets()),
+                    dst: Ipv4Addr(dst.octets()),
                     proto: 6,
-                    ident: self.cfg.ident_seed,
+                    ident: 0,
                     ttl: self.cfg.ttl,
+                    ecn: seg.ecn,
+                    // TCP sizes segments to the path MTU instead.
+                    dont_fragment: true,
+                };
+                self.send_ip(ip, &tcp).await
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                let ip = Ipv6Header {
+                    src,
+                    dst,
+                    next_header: 6,
+                    hop_limit: self.cfg.ttl,
+                    ecn: seg.ecn,
+                    flow_label: 0,
+                };
+                self.send_ip6(ip, &tcp).await
+            }
+            // Connect refuses such quads.
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a datagram with the next identification, in fragments if it
+    /// exceeds the path MTU and may be fragmented.
+    async fn send_ip(&self, mut hdr: Ipv4Header, payload: &[u8]) -> Result<()> {
+        hdr.ident = self.ident.fetch_add(1, Ordering::Relaxed);
+        let mtu = self.path_mtu(IpAddr::from(hdr.dst.0));
+        for frame in hdr.fragment(payload, mtu) {
+            self.dev.send(&frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Send an IPv6 packet, in fragments if it exceeds the path MTU. TCP
+    /// sizes its segments to fit, so only other protocols are fragmented.
+    async fn send_ip6(&self, hdr: Ipv6Header, payload: &[u8]) -> Result<()> {
+        let mtu = self.path_mtu(IpAddr::V6(hdr.dst));
+        let ident = if ipv6::IPV6_HEADER_LEN + payload.len() > mtu {
+            self.ident6.fetch_add(1, Ordering::Relaxed)
+        } else {
+            0
+        };
+        for frame in hdr.fragment(payload, mtu, ident) {
+            self.dev.send(&frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Send queued control segments, then as much data on `id` as the
+    /// windows allow.
+    async fn flush(&mut self, id: Quad) -> Result<()> {
+        let early: Vec<Segment> = match self.conns.get_mut(&id) {
+            Some(conn) => conn.outbox.drain(..).collect(),
+            None => return Ok(()),
+        };
+        for seg in early {
+            self.transmit(id, seg).await?;
+        }
+        while let Some(seg) = match self.conns.get_mut(&id) {
+            Some(conn) => conn.poll_send()?,
+            None => None,
+        } {
+            self.transmit(id, seg).await?;
+        }
+        self.rearm(id);
+        Ok(())
+    }
+
+    async fn on_cmd(&mut self, cmd: TcpCmd) -> Result<()> {
+        match cmd {
+            TcpCmd::Connect(id, opts, app_rx_s, reply) => {
+                if id.src_ip.is_ipv4() != id.dst_ip.is_ipv4() {
+                    let _ = reply.send(Err(UrtcpError::AddrFamily));
+                    return Ok(());
+                }
+                let now = self.clock.now();
+                let mut iss = self.isn.isn(&id, now);
+                if self.timewait.contains(&id) {
+                    match self.timewait.reuse(&id, now) {
+                        // Well above anything the old connection sent.
+                        Some(old) => iss = old.wrapping_add(2 * RCV_WND),
+                        None => {
+                            let _ = reply.send(Err(UrtcpError::AddrInUse));
+                            return Ok(());
+                        }
+                    }
                 }
-                .encode(&seg);
-                self.dev.send(&ip).await?;
+                // Create connection in SynSent, send SYN
+                let (_app_tx_s, app_tx_r) = tokio::sync::mpsc::channel(64);
+                let mut conn =
+                    Connection::new(id, State::SynSent, app_rx_s, app_tx_r, self.clock.clone());
+                conn.iss = iss;
+                conn.snd_una = conn.iss;
+                conn.snd_nxt = conn.iss.wrapping_add(1);
+                conn.ecn.enabled = self.cfg.ecn;
+                conn.sack_ok = self.cfg.sack;
+                conn.rack_enabled = self.cfg.rack;
+                conn.pacer = self.cfg.pacing.then(Pacer::default);
+                conn.cwv = self.cfg.cwv.then(Cwv::default);
+                conn.keepalive = self.cfg.keepalive;
+                conn.uto_enabled = self.cfg.uto_option;
+                conn.set_user_timeout(self.cfg.user_timeout);
+                self.set_path_mtu(&mut conn);
+                conn.ts = self.cfg.timestamps.then(|| self.timestamps(0));
+                conn.auth = opts.auth.as_ref().map(|a| AuthState::new(a, iss, None));
+                conn.crypt = self.cfg.tcpcrypt.then(Tcpcrypt::active);
+                conn.cc = self.congestion_for(id.dst_ip).build();
+                if self.cfg.metrics {
+                    self.metrics.seed(&mut conn, self.clock.now());
+                }
+                // SYN data would go out before encryption is negotiated.
+                if opts.fast_open && conn.auth.is_none() && conn.crypt.is_none() {
+                    conn.fast_open = self.fastopen.client(id.dst_ip, self.clock.now());
+                }
+                conn.queue_send(&opts.data);
+                let seg = conn.syn(self.clock.now());
+                // In the table first, for `transmit` to find its signer.
                 self.conns.insert(id, conn);
-                let _ = reply.send(Ok(()));
+                self.transmit(id, seg).await?;
+                self.rearm(id);
+                let _ = reply.send(Ok(self.tx_drop.clone()));
             }
-            TcpCmd::Listen(port, reply) => {
-                self.listeners.insert(port, ());
+            TcpCmd::Listen(port, opts, accept, reply) => {
+                self.listeners.insert(
+                    port,
+                    Listener {
+                        accept,
+                        opts,
+                        stats: ListenerStats::default(),
+                        last_cookie: None,
+                        tfo_pending: 0,
+                    },
+                );
                 let _ = reply.send(Ok(()));
             }
-            TcpCmd::Send(id, _data) => {
+            // Commands for connections that are already gone, e.g. reset
+            // by the peer, are dropped: that is no reason to stop the stack.
+            TcpCmd::Send(id, data) => {
+                if let Some(conn) = self.conns.get_mut(&id) {
+                    conn.queue_send(&data);
+                    self.flush(id).await?;
+                }
+            }
+            TcpCmd::SendUrgent(id, data) => {
+                if let Some(conn) = self.conns.get_mut(&id) {
+                    conn.queue_urgent(&data);
+                    self.flush(id).await?;
+                }
+            }
+            TcpCmd::Shutdown(id, how) => {
+                if let Some(conn) = self.conns.get_mut(&id) {
+                    conn.shutdown(how);
+                    self.flush(id).await?;
+                    self.reap(id);
+                }
+            }
+            TcpCmd::Close(id, linger) => match self.conns.get_mut(&id) {
+                Some(conn) => {
+                    conn.close(linger);
+                    conn.orphan(
+                        self.clock.now(),
+                        self.cfg.orphan_retries,
+                        self.cfg.fin_wait2_timeout,
+                    );
+                    if let Some(since) = conn.orphaned {
+                        self.orphans.insert((since, id));
+                    }
+                    self.flush(id).await?;
+                    self.reap(id);
+                    self.limit_orphans().await?;
+                }
+                None => {
+                    if let Some((_, linger)) = linger {
+                        let _ = linger.send(Ok(()));
+                    }
+                }
+            },
+            TcpCmd::Abort(id) => {
                 if let Some(conn) = self.conns.get_mut(&id) {
-                    let _ = conn.poll_send()?; // build segments (todo)
-                } else {
-                    return Err(UrtcpError::ConnNotFound);
+                    conn.abort(UrtcpError::ConnectionReset);
+                    self.flush(id).await?;
+                    self.reap(id);
                 }
             }
-            TcpCmd::Close(id) => {
-                // Transition to FIN-WAIT (active) or LAST-ACK (passive)
-                let _ = id;
+            TcpCmd::Stats(id, reply) => {
+                let _ = reply.send(self.conns.get(&id).map(Connection::stats));
+            }
+            TcpCmd::SessionId(id, reply) => {
+                let _ = reply.send(
+                    self.conns
+                        .get(&id)
+                        .map(|c| {
+                            c.crypt
+                                .as_ref()
+                                .and_then(|c| c.session_id())
+                                .map(<[u8]>::to_vec)
+                        })
+                        .ok_or(UrtcpError::ConnNotFound),
+                );
+            }
+            TcpCmd::ListenerStats(port, reply) => {
+                let _ = reply.send(self.listeners.get(&port).map(|l| l.stats.clone()));
+            }
+            TcpCmd::Metrics(reply) => {
+                let _ = reply.send(self.metrics.snapshot(self.clock.now()));
+            }
+            TcpCmd::FlushMetrics(dst) => self.metrics.flush(dst),
+            TcpCmd::Ping(dst, opts, reply) => self.ping(dst, opts, reply).await?,
+            TcpCmd::SetOption(id, opt) => {
+                let Some(conn) = self.conns.get_mut(&id) else {
+                    return Ok(());
+                };
+                match opt {
+                    SockOpt::MaxPacingRate(rate) => {
+                        // A cap applies even when the stack itself runs unpaced.
+                        let pacer = conn.pacer.get_or_insert_with(Pacer::default);
+                        pacer.max_rate = rate;
+                    }
+                    SockOpt::UserTimeout(timeout) => conn.set_user_timeout(timeout),
+                    SockOpt::OobInline(inline) => conn.oob_inline = inline,
+                    SockOpt::Keepalive(keepalive) => {
+                        conn.keepalive = keepalive;
+                        conn.keepalive_probes = 0;
+                        conn.arm_keepalive();
+                    }
+                    SockOpt::AuthKeys(keys) => {
+                        if let Some(auth) = &mut conn.auth {
+                            auth.set_keys(keys);
+                        }
+                    }
+                    SockOpt::AuthRNextKey(key_id) => {
+                        if let Some(auth) = &mut conn.auth {
+                            auth.set_rnext(key_id);
+                        }
+                    }
+                }
+                self.rearm(id);
             }
         }
         Ok(())
     }
 
-    fn on_tick(&mut self) -> Result<()> {
-        // Drive retransmissions, delayed ACKs, persist timer, TIME-WAIT
-        for (_k, _c) in self.conns.iter_mut() {
-            // _c.on_retransmit_timeout()?;
+    async fn on_deadlines(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        for timer in self.timers.poll(now) {
+            let id = match timer {
+                Timer::Conn(id) => id,
+                Timer::TimeWait => {
+                    self.timewait.expire(now);
+                    self.rearm_time_wait();
+                    continue;
+                }
+                Timer::Reassembly => {
+                    self.reassembly.expire(now);
+                    self.reassembly6.expire(now);
+                    self.rearm_reassembly();
+                    continue;
+                }
+                Timer::Ping(seq) => {
+                    if let Some(ping) = self.pings.remove(&seq) {
+                        let _ = ping.reply.send(Err(UrtcpError::TimedOut));
+                    }
+                    continue;
+                }
+            };
+            if let Some(conn) = self.conns.get_mut(&id) {
+                conn.on_timer(now)?;
+                if conn.fast_open.syn_data_lost {
+                    conn.fast_open.syn_data_lost = false;
+                    self.fastopen.disable(id.dst_ip, now);
+                }
+            }
+            self.flush(id).await?;
+            self.reap(id);
         }
         Ok(())
     }
+
+    /// Put `id`'s earliest deadline on the timer wheel, or take it off.
+    fn rearm(&mut self, id: Quad) {
+        match self.conns.get(&id).and_then(Connection::next_deadline) {
+            Some(at) => self.timers.schedule(Timer::Conn(id), at),
+            None => self.timers.cancel(&Timer::Conn(id)),
+        }
+    }
+
+    fn rearm_time_wait(&mut self) {
+        match self.timewait.next_expiry() {
+            Some(at) => self.timers.schedule(Timer::TimeWait, at),
+            None => self.timers.cancel(&Timer::TimeWait),
+        }
+    }
+
+    fn rearm_reassembly(&mut self) {
+        let next = [
+            self.reassembly.next_expiry(),
+            self.reassembly6.next_expiry(),
+        ];
+        match next.into_iter().flatten().min() {
+            Some(at) => self.timers.schedule(Timer::Reassembly, at),
+            None => self.timers.cancel(&Timer::Reassembly),
+        }
+    }
+
+    /// Reset the oldest orphans while there are more than `max_orphans`.
+    async fn limit_orphans(&mut self) -> Result<()> {
+        while self.orphans.len() > self.cfg.max_orphans {
+            let Some((_, id)) = self.orphans.pop_first() else {
+                break;
+            };
+            tracing::debug!(?id, "too many orphans, resetting");
+            if let Some(conn) = self.conns.get_mut(&id) {
+                conn.abort(UrtcpError::ConnectionReset);
+            }
+            self.flush(id).await?;
+            self.reap(id);
+        }
+        Ok(())
+    }
+
+    /// Take `id` off its listener's half-open queue once out of
+    /// SYN-RECEIVED, and release it once it has closed or reached TIME-WAIT.
+    fn reap(&mut self, id: Quad) {
+        if let Some(conn) = self.conns.get_mut(&id)
+            && conn.half_open
+            && !matches!(conn.state, State::SynReceived)
+        {
+            conn.half_open = false;
+            let fast_open = conn.fast_open.accepted;
+            self.leave_half_open(id, fast_open);
+        }
+        if self
+            .conns
+            .get(&id)
+            .is_some_and(|c| matches!(c.state, State::Closed | State::TimeWait))
+        {
+            self.release(id);
+        }
+    }
+
+    fn leave_half_open(&mut self, id: Quad, fast_open: bool) {
+        if let Some(listener) = self.listeners.get_mut(&id.src_port) {
+            // A listener bound again since counts from zero.
+            listener.stats.half_open = listener.stats.half_open.saturating_sub(1);
+            if fast_open {
+                listener.tfo_pending = listener.tfo_pending.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Forget a finished connection, keeping what it learned about the path
+    /// and, in TIME-WAIT, what that needs.
+    fn release(&mut self, id: Quad) {
+        self.pending_accept.remove(&id);
+        let Some(conn) = self.conns.remove(&id) else {
+            return;
+        };
+        if conn.half_open {
+            self.leave_half_open(id, conn.fast_open.accepted);
+        }
+        if let Some(since) = conn.orphaned {
+            self.orphans.remove(&(since, id));
+        }
+        let now = self.clock.now();
+        if self.cfg.metrics {
+            self.metrics.update(&conn, now);
+        }
+        self.timers.cancel(&Timer::Conn(id));
+        if matches!(conn.state, State::TimeWait) {
+            self.timewait.insert(&conn, now);
+            self.rearm_time_wait();
+        }
+    }
 }
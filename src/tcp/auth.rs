@@ -0,0 +1,717 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::ops::Range;
+
+use aes::Aes128;
+use cmac::Cmac;
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+use super::conn::{Quad, seq_le};
+use crate::wire::checksum::pseudo_header;
+use crate::wire::tcp::{
+    FLAG_ACK, FLAG_SYN, OPT_AO, OPT_MD5, SegmentSigner, TcpHeader, TcpOption, TcpView, find_option,
+};
+
+/// Bytes of MAC TCP-AO carries with either RFC 5926 algorithm.
+const AO_MAC_LEN: usize = 12;
+/// Option space taken by TCP-AO and by TCP-MD5, padding included.
+const AO_OPTION_LEN: usize = 4 + AO_MAC_LEN;
+const MD5_OPTION_LEN: usize = 20;
+
+/// Key material; kept out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthKey(pub Vec<u8>);
+
+impl fmt::Debug for AuthKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AuthKey(<{} bytes>)", self.0.len())
+    }
+}
+
+impl From<&[u8]> for AuthKey {
+    fn from(key: &[u8]) -> Self {
+        Self(key.to_vec())
+    }
+}
+
+/// TCP-AO MAC algorithms (RFC 5926), each with its key derivation function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AoAlgorithm {
+    /// HMAC-SHA-1-96, keys from KDF_HMAC_SHA1.
+    HmacSha1,
+    /// AES-128-CMAC-96, keys from KDF_AES_128_CMAC.
+    AesCmac,
+}
+
+/// A TCP-AO master key tuple (RFC 5925 §3.1). The connection identifier is
+/// where the key is configured: the peer address of a listener, or a connect.
+#[derive(Debug, Clone)]
+pub struct MasterKey {
+    /// KeyID of segments we send with this key.
+    pub send_id: u8,
+    /// KeyID of segments the peer sends with it.
+    pub recv_id: u8,
+    pub algorithm: AoAlgorithm,
+    /// MAC the TCP options too, not only TCP-AO itself.
+    pub include_options: bool,
+    pub key: AuthKey,
+}
+
+/// How segments exchanged with a peer are authenticated.
+#[derive(Debug, Clone)]
+pub enum TcpAuth {
+    /// RFC 2385 TCP-MD5, for peers without TCP-AO.
+    Md5(AuthKey),
+    /// RFC 5925 TCP-AO; the first key is used until the peer asks for another.
+    Ao(Vec<MasterKey>),
+}
+
+/// Sequence number extension (RFC 5925 §6.2): the count of times the
+/// sequence space has wrapped, tracked from the highest sequence seen.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sne {
+    sne: u32,
+    highest: Option<u32>,
+}
+
+impl Sne {
+    fn for_seq(&self, seq: u32) -> u32 {
+        match self.highest {
+            None => self.sne,
+            // Ahead of the highest yet numerically below it: wrapped.
+            Some(h) if seq_le(h, seq) && seq < h => self.sne.wrapping_add(1),
+            // Behind the highest from before it wrapped.
+            Some(h) if !seq_le(h, seq) && seq > h => self.sne.wrapping_sub(1),
+            Some(_) => self.sne,
+        }
+    }
+
+    fn update(&mut self, seq: u32) {
+        if self.highest.is_none_or(|h| seq_le(h, seq)) {
+            self.sne = self.for_seq(seq);
+            self.highest = Some(seq);
+        }
+    }
+}
+
+/// Per-connection TCP-AO state.
+#[derive(Debug, Clone)]
+pub struct AoState {
+    keys: Vec<MasterKey>,
+    /// Index into `keys` of the MKT we send with.
+    current: usize,
+    /// KeyID we ask the peer to send with.
+    rnext: u8,
+    local_isn: u32,
+    /// Known once the peer's SYN has been seen.
+    remote_isn: Option<u32>,
+    snd_sne: Sne,
+    rcv_sne: Sne,
+}
+
+/// Per-connection authentication state.
+#[derive(Debug, Clone)]
+pub enum AuthState {
+    Md5(AuthKey),
+    Ao(AoState),
+}
+
+impl AuthState {
+    pub fn new(auth: &TcpAuth, local_isn: u32, remote_isn: Option<u32>) -> Self {
+        match auth {
+            TcpAuth::Md5(key) => AuthState::Md5(key.clone()),
+            TcpAuth::Ao(keys) => AuthState::Ao(AoState {
+                rnext: keys.first().map_or(0, |k| k.recv_id),
+                keys: keys.clone(),
+                current: 0,
+                local_isn,
+                remote_isn,
+                snd_sne: Sne::default(),
+                rcv_sne: Sne::default(),
+            }),
+        }
+    }
+
+    /// Option space every segment loses to authentication.
+    pub fn option_len(&self) -> usize {
+        match self {
+            AuthState::Md5(_) => MD5_OPTION_LEN,
+            AuthState::Ao(_) => AO_OPTION_LEN,
+        }
+    }
+
+    /// Signer for the outgoing segment `hdr` on `id`; `None` if no MKT is
+    /// left to send with.
+    pub fn signer(&mut self, id: &Quad, hdr: &TcpHeader) -> Option<Signer> {
+        match self {
+            AuthState::Md5(key) => Some(Signer::Md5(key.0.clone())),
+            AuthState::Ao(ao) => {
+                let mkt = ao.keys.get(ao.current)?;
+                ao.snd_sne.update(hdr.seq);
+                // A SYN is keyed without the peer's ISN, which it cannot know.
+                let remote_isn = match hdr.flags & (FLAG_SYN | FLAG_ACK) {
+                    FLAG_SYN => 0,
+                    _ => ao.remote_isn.unwrap_or(0),
+                };
+                let context = kdf_context(
+                    (id.src_ip, id.src_port, ao.local_isn),
+                    (id.dst_ip, id.dst_port, remote_isn),
+                );
+                Some(Signer::Ao {
+                    algorithm: mkt.algorithm,
+                    traffic_key: traffic_key(mkt, &context),
+                    key_id: mkt.send_id,
+                    rnext_key_id: ao.rnext,
+                    sne: ao.snd_sne.for_seq(hdr.seq),
+                    include_options: mkt.include_options,
+                })
+            }
+        }
+    }
+
+    /// Check the signature of `seg`, received on `id`. A valid TCP-AO
+    /// segment also moves our send key to the one it asks for, if we have
+    /// it (RFC 5925 §7.5.2).
+    pub fn verify(&mut self, id: &Quad, seg: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> bool {
+        let Some(tv) = crate::wire::tcp::parse(seg) else {
+            return false;
+        };
+        match self {
+            AuthState::Md5(key) => {
+                let Some(opt) = find_option(tv.options, OPT_MD5).filter(|r| r.len() == 18) else {
+                    return false;
+                };
+                let digest = &tv.options[opt.start + 2..opt.end];
+                ct_eq(&md5_digest(&key.0, seg, src_ip, dst_ip), digest)
+            }
+            AuthState::Ao(ao) => {
+                let Some(opt) = find_option(tv.options, OPT_AO) else {
+                    return false;
+                };
+                let Some(TcpOption::Ao {
+                    key_id,
+                    rnext_key_id,
+                    mac,
+                }) = tv.options().find(|o| matches!(o, TcpOption::Ao { .. }))
+                else {
+                    return false;
+                };
+                let Some(mkt) = ao.keys.iter().find(|k| k.recv_id == key_id) else {
+                    tracing::debug!(?id, key_id, "TCP-AO key not found");
+                    return false;
+                };
+                // The ISN of a SYN is its sequence number; a SYN from the
+                // peer is keyed without ours.
+                let syn = tv.flags & FLAG_SYN != 0;
+                let remote_isn = if syn { Some(tv.seq) } else { ao.remote_isn };
+                let Some(remote_isn) = remote_isn else {
+                    return false;
+                };
+                let local_isn = match tv.flags & (FLAG_SYN | FLAG_ACK) {
+                    FLAG_SYN => 0,
+                    _ => ao.local_isn,
+                };
+                let context = kdf_context(
+                    (id.dst_ip, id.dst_port, remote_isn),
+                    (id.src_ip, id.src_port, local_isn),
+                );
+                let key = traffic_key(mkt, &context);
+                let sne = ao.rcv_sne.for_seq(tv.seq);
+                let opt = 20 + opt.start..20 + opt.end;
+                let expected = ao_mac(
+                    mkt.algorithm,
+                    &key,
+                    sne,
+                    seg,
+                    opt,
+                    mkt.include_options,
+                    src_ip,
+                    dst_ip,
+                );
+                if !ct_eq(&expected, mac) {
+                    return false;
+                }
+                if syn {
+                    ao.remote_isn = Some(tv.seq);
+                }
+                ao.rcv_sne.update(tv.seq);
+                if ao.keys[ao.current].send_id != rnext_key_id
+                    && let Some(i) = ao.keys.iter().position(|k| k.send_id == rnext_key_id)
+                {
+                    tracing::debug!(?id, key_id = rnext_key_id, "TCP-AO send key changed");
+                    ao.current = i;
+                }
+                true
+            }
+        }
+    }
+
+    /// Replace the MKTs, e.g. to add the next key of a rollover. The send
+    /// key stays unless it was removed. No-op for TCP-MD5.
+    pub fn set_keys(&mut self, keys: Vec<MasterKey>) {
+        if let AuthState::Ao(ao) = self {
+            let send_id = ao.keys.get(ao.current).map(|k| k.send_id);
+            ao.current = keys
+                .iter()
+                .position(|k| Some(k.send_id) == send_id)
+                .unwrap_or(0);
+            ao.keys = keys;
+        }
+    }
+
+    /// Ask the peer to send with the key it knows as `key_id`, e.g. to
+    /// complete a rollover. No-op for TCP-MD5.
+    pub fn set_rnext(&mut self, key_id: u8) {
+        if let AuthState::Ao(ao) = self {
+            ao.rnext = key_id;
+        }
+    }
+}
+
+/// Whether `seg`, received on `id`, may be processed: signed as `auth`
+/// requires, or unsigned where no authentication is configured.
+pub fn accept(
+    auth: Option<&mut AuthState>,
+    id: &Quad,
+    tv: &TcpView<'_>,
+    seg: &[u8],
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+) -> bool {
+    match auth {
+        Some(auth) => auth.verify(id, seg, src_ip, dst_ip),
+        None => !tv
+            .options()
+            .any(|o| matches!(o, TcpOption::Md5(_) | TcpOption::Ao { .. })),
+    }
+}
+
+/// Signs one outgoing segment.
+pub enum Signer {
+    Md5(Vec<u8>),
+    Ao {
+        algorithm: AoAlgorithm,
+        traffic_key: Vec<u8>,
+        key_id: u8,
+        rnext_key_id: u8,
+        sne: u32,
+        include_options: bool,
+    },
+}
+
+impl SegmentSigner for Signer {
+    fn option(&self) -> Vec<u8> {
+        let mut opt = match self {
+            Signer::Md5(_) => vec![OPT_MD5, 18],
+            Signer::Ao {
+                key_id,
+                rnext_key_id,
+                ..
+            } => vec![OPT_AO, AO_OPTION_LEN as u8, *key_id, *rnext_key_id],
+        };
+        opt.resize(opt[1] as usize, 0);
+        opt
+    }
+
+    fn sign(&self, seg: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> Vec<u8> {
+        match self {
+            Signer::Md5(key) => md5_digest(key, seg, src_ip, dst_ip).to_vec(),
+            Signer::Ao {
+                algorithm,
+                traffic_key,
+                sne,
+                include_options,
+                ..
+            } => ao_mac(
+                *algorithm,
+                traffic_key,
+                *sne,
+                seg,
+                20..20 + AO_OPTION_LEN,
+                *include_options,
+                src_ip,
+                dst_ip,
+            )
+            .to_vec(),
+        }
+    }
+}
+
+/// Connection context of a traffic key (RFC 5925 §5.2): the sending end's
+/// address, port and ISN, then the receiving end's.
+fn kdf_context(from: (IpAddr, u16, u32), to: (IpAddr, u16, u32)) -> Vec<u8> {
+    let mut ctx = Vec::with_capacity(44);
+    ctx.extend_from_slice(&octets(from.0));
+    ctx.extend_from_slice(&octets(to.0));
+    ctx.extend_from_slice(&from.1.to_be_bytes());
+    ctx.extend_from_slice(&to.1.to_be_bytes());
+    ctx.extend_from_slice(&from.2.to_be_bytes());
+    ctx.extend_from_slice(&to.2.to_be_bytes());
+    ctx
+}
+
+/// RFC 5926 §3.1.1 KDF: PRF(master key, 1 || "TCP-AO" || context || length).
+fn traffic_key(mkt: &MasterKey, context: &[u8]) -> Vec<u8> {
+    let bits: u16 = match mkt.algorithm {
+        AoAlgorithm::HmacSha1 => 160,
+        AoAlgorithm::AesCmac => 128,
+    };
+    let mut input = vec![1];
+    input.extend_from_slice(b"TCP-AO");
+    input.extend_from_slice(context);
+    input.extend_from_slice(&bits.to_be_bytes());
+    match mkt.algorithm {
+        AoAlgorithm::HmacSha1 => hmac_sha1(&mkt.key.0, &input),
+        AoAlgorithm::AesCmac => aes_cmac_prf_128(&mkt.key.0, &input),
+    }
+}
+
+/// RFC 5925 §5.1 MAC of `seg`, checksum and MAC zeroed, with TCP-AO at
+/// `opt`: over the SNE, the pseudo-header, the header with or without
+/// its other options, and the payload.
+#[allow(clippy::too_many_arguments)]
+fn ao_mac(
+    algorithm: AoAlgorithm,
+    traffic_key: &[u8],
+    sne: u32,
+    seg: &[u8],
+    opt: Range<usize>,
+    include_options: bool,
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+) -> [u8; AO_MAC_LEN] {
+    let hdr_len = ((seg[12] >> 4) as usize) * 4;
+    let mut input = Vec::with_capacity(16 + seg.len());
+    input.extend_from_slice(&sne.to_be_bytes());
+    input.extend_from_slice(&pseudo_header(src_ip, dst_ip, 6, seg.len()));
+    let start = input.len();
+    if include_options {
+        input.extend_from_slice(&seg[..hdr_len]);
+    } else {
+        input.extend_from_slice(&seg[..20]);
+        input.extend_from_slice(&seg[opt.clone()]);
+    }
+    // Checksum and MAC count as zero.
+    input[start + 16..start + 18].fill(0);
+    let mac_end = if include_options {
+        start + opt.end
+    } else {
+        input.len()
+    };
+    input[mac_end - (opt.len() - 4)..mac_end].fill(0);
+    input.extend_from_slice(&seg[hdr_len..]);
+    let full = match algorithm {
+        AoAlgorithm::HmacSha1 => hmac_sha1(traffic_key, &input),
+        AoAlgorithm::AesCmac => aes_cmac(traffic_key, &input),
+    };
+    let mut mac = [0; AO_MAC_LEN];
+    mac.copy_from_slice(&full[..AO_MAC_LEN]);
+    mac
+}
+
+/// RFC 2385 digest: pseudo-header, header without options and a zero
+/// checksum, payload, key.
+fn md5_digest(key: &[u8], seg: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> [u8; 16] {
+    let hdr_len = ((seg[12] >> 4) as usize) * 4;
+    let mut hdr = [0; 20];
+    hdr.copy_from_slice(&seg[..20]);
+    hdr[16..18].fill(0);
+    let mut md5 = Md5::new();
+    md5.update(pseudo_header(src_ip, dst_ip, 6, seg.len()));
+    md5.update(hdr);
+    md5.update(&seg[hdr_len..]);
+    md5.update(key);
+    md5.finalize().into()
+}
+
+/// Compare MACs in time independent of where they differ.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub(crate) fn octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// RFC 4615 AES-CMAC-PRF-128: keys of any other length are first
+/// condensed to 128 bits.
+fn aes_cmac_prf_128(key: &[u8], data: &[u8]) -> Vec<u8> {
+    match key.len() {
+        16 => aes_cmac(key, data),
+        _ => aes_cmac(&aes_cmac(&[0; 16], key), data),
+    }
+}
+
+fn aes_cmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Cmac::<Aes128>::new_from_slice(key).expect("AES-128 key");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::tcp::FLAG_PSH;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The primitives RFC 5926 builds on, against their own RFCs' vectors.
+
+    #[test]
+    fn hmac_sha1_rfc2202() {
+        assert_eq!(
+            hmac_sha1(&[0x0b; 20], b"Hi There"),
+            hex("b617318655057264e28bc0b6fb378c8ef146be00")
+        );
+        assert_eq!(
+            hmac_sha1(b"Jefe", b"what do ya want for nothing?"),
+            hex("effcdf6ae5eb2fa2d27416d5f184df9c259a7c79")
+        );
+    }
+
+    #[test]
+    fn aes_cmac_rfc4493() {
+        let key = hex("2b7e151628aed2a6abf7158809cf4f3c");
+        assert_eq!(aes_cmac(&key, &[]), hex("bb1d6929e95937287fa37d129b756746"));
+        assert_eq!(
+            aes_cmac(&key, &hex("6bc1bee22e409f96e93d7e117393172a")),
+            hex("070a16b46b4d4144f79bdd9dd04a287c")
+        );
+    }
+
+    #[test]
+    fn aes_cmac_prf_128_rfc4615() {
+        let msg = hex("000102030405060708090a0b0c0d0e0f10111213");
+        for (key, prf) in [
+            (
+                "000102030405060708090a0b0c0d0e0fedcb",
+                "84a348a4a45d235babfffc0d2b4da09a",
+            ),
+            (
+                "000102030405060708090a0b0c0d0e0f",
+                "980ae87b5f4c9c5214f5b6a8455e4c2d",
+            ),
+            ("00010203040506070809", "290d9e112edb09ee141fcf64c0b72f3d"),
+        ] {
+            assert_eq!(aes_cmac_prf_128(&hex(key), &msg), hex(prf));
+        }
+    }
+
+    #[test]
+    fn kdf_context_layout() {
+        let ctx = kdf_context(
+            ([10, 0, 0, 1].into(), 1000, 0x0102_0304),
+            ([10, 0, 0, 2].into(), 80, 0x0a0b_0c0d),
+        );
+        assert_eq!(ctx, hex("0a0000010a00000203e80050010203040a0b0c0d"));
+        let v6 = kdf_context(
+            ("2001:db8::1".parse().unwrap(), 1000, 1),
+            ("2001:db8::2".parse().unwrap(), 80, 2),
+        );
+        assert_eq!(v6.len(), 44);
+        assert_eq!(&v6[32..], hex("03e800500000000100000002"));
+    }
+
+    fn mkt(algorithm: AoAlgorithm, key: &[u8]) -> MasterKey {
+        MasterKey {
+            send_id: 1,
+            recv_id: 1,
+            algorithm,
+            include_options: false,
+            key: key.into(),
+        }
+    }
+
+    #[test]
+    fn traffic_keys_per_algorithm() {
+        let ctx = kdf_context(
+            ([10, 0, 0, 1].into(), 1000, 1),
+            ([10, 0, 0, 2].into(), 80, 2),
+        );
+        // The KDF output is the PRF over 1 || "TCP-AO" || context || bits.
+        let mut input = vec![1];
+        input.extend_from_slice(b"TCP-AO");
+        input.extend_from_slice(&ctx);
+
+        let sha1 = mkt(AoAlgorithm::HmacSha1, b"secret");
+        let key = traffic_key(&sha1, &ctx);
+        assert_eq!(key.len(), 20);
+        assert_eq!(key, hmac_sha1(b"secret", &[&input[..], &[0, 160]].concat()));
+
+        let cmac = mkt(AoAlgorithm::AesCmac, b"secret");
+        let key = traffic_key(&cmac, &ctx);
+        assert_eq!(key.len(), 16);
+        assert_eq!(
+            key,
+            aes_cmac_prf_128(b"secret", &[&input[..], &[0, 128]].concat())
+        );
+
+        // Each direction and connection gets its own key.
+        let back = kdf_context(
+            ([10, 0, 0, 2].into(), 80, 2),
+            ([10, 0, 0, 1].into(), 1000, 1),
+        );
+        assert_ne!(traffic_key(&sha1, &ctx), traffic_key(&sha1, &back));
+    }
+
+    struct End {
+        id: Quad,
+        state: AuthState,
+    }
+
+    /// Both ends of `client` to `server`, before the handshake.
+    fn ends(auth: &TcpAuth, client: IpAddr, server: IpAddr) -> (End, End) {
+        let id = Quad {
+            src_ip: client,
+            src_port: 40000,
+            dst_ip: server,
+            dst_port: 179,
+        };
+        let back = Quad {
+            src_ip: server,
+            src_port: 179,
+            dst_ip: client,
+            dst_port: 40000,
+        };
+        (
+            End {
+                id,
+                state: AuthState::new(auth, 1000, None),
+            },
+            End {
+                id: back,
+                state: AuthState::new(auth, 5000, Some(1000)),
+            },
+        )
+    }
+
+    /// `from` signs a segment and `to` checks it, with `tamper` applied to
+    /// it in between.
+    fn exchange(
+        from: &mut End,
+        to: &mut End,
+        seq: u32,
+        ack: u32,
+        flags: u16,
+        tamper: impl FnOnce(&mut [u8]),
+    ) -> bool {
+        let hdr = TcpHeader {
+            src_port: from.id.src_port,
+            dst_port: from.id.dst_port,
+            seq,
+            ack,
+            data_offset: 5,
+            flags,
+            window: 1000,
+            urg_ptr: 0,
+            options: Default::default(),
+        };
+        let signer = from.state.signer(&from.id, &hdr).unwrap();
+        let mut seg = hdr.encode_signed(b"payload", from.id.src_ip, from.id.dst_ip, &signer);
+        tamper(&mut seg);
+        to.state
+            .verify(&to.id, &seg, from.id.src_ip, from.id.dst_ip)
+    }
+
+    fn handshake(auth: &TcpAuth, client: IpAddr, server: IpAddr) {
+        let (mut c, mut s) = ends(auth, client, server);
+        assert!(exchange(&mut c, &mut s, 1000, 0, FLAG_SYN, |_| ()));
+        assert!(exchange(
+            &mut s,
+            &mut c,
+            5000,
+            1001,
+            FLAG_SYN | FLAG_ACK,
+            |_| ()
+        ));
+        assert!(exchange(
+            &mut c,
+            &mut s,
+            1001,
+            5001,
+            FLAG_ACK | FLAG_PSH,
+            |_| ()
+        ));
+        // Any change to the segment, payload included, is caught.
+        assert!(!exchange(&mut c, &mut s, 1008, 5001, FLAG_ACK, |seg| {
+            *seg.last_mut().unwrap() ^= 1
+        }));
+        assert!(!exchange(&mut c, &mut s, 1008, 5001, FLAG_ACK, |seg| {
+            seg[14] ^= 1
+        }));
+    }
+
+    #[test]
+    fn ao_round_trip() {
+        let v4: (IpAddr, IpAddr) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        let v6: (IpAddr, IpAddr) = (
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        );
+        for algorithm in [AoAlgorithm::HmacSha1, AoAlgorithm::AesCmac] {
+            for include_options in [false, true] {
+                let auth = TcpAuth::Ao(vec![MasterKey {
+                    include_options,
+                    ..mkt(algorithm, b"master key")
+                }]);
+                handshake(&auth, v4.0, v4.1);
+                handshake(&auth, v6.0, v6.1);
+            }
+        }
+    }
+
+    #[test]
+    fn md5_round_trip() {
+        let auth = TcpAuth::Md5(b"md5 key".as_slice().into());
+        handshake(&auth, [10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let client: IpAddr = [10, 0, 0, 1].into();
+        let server: IpAddr = [10, 0, 0, 2].into();
+        let (mut c, _) = ends(
+            &TcpAuth::Ao(vec![mkt(AoAlgorithm::HmacSha1, b"one")]),
+            client,
+            server,
+        );
+        let (_, mut s) = ends(
+            &TcpAuth::Ao(vec![mkt(AoAlgorithm::HmacSha1, b"two")]),
+            client,
+            server,
+        );
+        assert!(!exchange(&mut c, &mut s, 1000, 0, FLAG_SYN, |_| ()));
+
+        // A KeyID the receiver has no MKT for.
+        let (mut c, _) = ends(
+            &TcpAuth::Ao(vec![MasterKey {
+                send_id: 7,
+                ..mkt(AoAlgorithm::HmacSha1, b"one")
+            }]),
+            client,
+            server,
+        );
+        let (_, mut s) = ends(
+            &TcpAuth::Ao(vec![mkt(AoAlgorithm::HmacSha1, b"one")]),
+            client,
+            server,
+        );
+        assert!(!exchange(&mut c, &mut s, 1000, 0, FLAG_SYN, |_| ()));
+    }
+}
@@ -1,3 +1,70 @@
+use std::time::{Duration, Instant};
+
+/// How a controller wants ECN marks fed back and reacted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcnFeedback {
+    /// RFC 3168: latch ECE until CWR, halve once per window.
+    Classic,
+    /// RFC 8257: ECE mirrors the CE state of each segment, reduction scales with alpha.
+    Dctcp,
+}
+
+/// Pluggable congestion controller driven by the connection's ACK clock.
+pub trait CongestionControl: Send + Sync + std::fmt::Debug {
+    fn cwnd(&self) -> usize;
+    fn ssthresh(&self) -> usize;
+    /// `acked` bytes were newly acknowledged.
+    fn on_ack(&mut self, acked: usize, mss: usize);
+    /// The peer echoed a CE mark (ECE). Called at most once per window of data.
+    fn on_ecn_echo(&mut self, flight: usize, mss: usize);
+    /// Loss detected: choose the new ssthresh. The window itself is brought
+    /// down to it by PRR during recovery.
+    fn on_loss(&mut self, flight: usize, mss: usize);
+    /// Set cwnd directly, e.g. to ssthresh when recovery ends.
+    fn set_cwnd(&mut self, cwnd: usize);
+    fn set_ssthresh(&mut self, ssthresh: usize);
+    /// Retransmission timeout: RFC 5681 (4) and a one-segment loss window.
+    fn on_rto(&mut self, flight: usize, mss: usize) {
+        self.on_loss(flight, mss);
+        self.set_cwnd(mss);
+    }
+    /// Pacing rate in bytes per second, `None` to send unpaced. The default
+    /// paces at 2 * cwnd / srtt in slow start and 1.2 * cwnd / srtt after.
+    fn pacing_rate(&self, srtt: Option<Duration>) -> Option<u64> {
+        let nanos = srtt?.as_nanos().max(1);
+        let pct: u128 = if self.cwnd() < self.ssthresh() {
+            200
+        } else {
+            120
+        };
+        Some((self.cwnd() as u128 * pct * 10_000_000 / nanos) as u64)
+    }
+    fn ecn_feedback(&self) -> EcnFeedback {
+        EcnFeedback::Classic
+    }
+    /// Per-ACK ECN accounting: `acked` bytes, whether the ACK carried ECE, and
+    /// the send sequence space at the time it arrived.
+    fn on_ecn_ack(&mut self, _acked: usize, _ece: bool, _snd_una: u32, _snd_nxt: u32) {}
+}
+
+/// Selectable congestion control algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionAlgorithm {
+    #[default]
+    Reno,
+    /// Data-center TCP. Only for ECN-capable fabrics under our control.
+    Dctcp,
+}
+
+impl CongestionAlgorithm {
+    pub fn build(self) -> Box<dyn CongestionControl> {
+        match self {
+            Self::Reno => Box::new(Reno::default()),
+            Self::Dctcp => Box::new(Dctcp::default()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Reno {
     pub cwnd: usize,
@@ -11,3 +78,333 @@ impl Default for Reno {
         }
     }
 }
+
+impl CongestionControl for Reno {
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+    fn on_ack(&mut self, acked: usize, mss: usize) {
+        if self.cwnd < self.ssthresh {
+            // slow start
+            self.cwnd += acked.min(mss);
+        } else {
+            // congestion avoidance: ~1 MSS per RTT
+            self.cwnd += (mss * mss / self.cwnd).max(1);
+        }
+    }
+    fn on_ecn_echo(&mut self, flight: usize, mss: usize) {
+        // RFC 3168 §6.1.2: react as to a single loss, without retransmitting.
+        self.ssthresh = (flight / 2).max(2 * mss);
+        self.cwnd = self.ssthresh;
+    }
+    fn on_loss(&mut self, flight: usize, mss: usize) {
+        // RFC 5681 (4)
+        self.ssthresh = (flight / 2).max(2 * mss);
+    }
+    fn set_cwnd(&mut self, cwnd: usize) {
+        self.cwnd = cwnd;
+    }
+    fn set_ssthresh(&mut self, ssthresh: usize) {
+        self.ssthresh = ssthresh;
+    }
+}
+
+/// Fixed-point scale for `Dctcp::alpha`.
+pub const DCTCP_ALPHA_ONE: u32 = 1024;
+/// EWMA gain g = 1/2^4 (RFC 8257 §4.2).
+const DCTCP_SHIFT_G: u32 = 4;
+
+/// DCTCP (RFC 8257): Reno growth, with ECN reductions proportional to the
+/// fraction of CE-marked bytes per window.
+#[derive(Debug, Clone)]
+pub struct Dctcp {
+    pub reno: Reno,
+    /// Estimated fraction of marked bytes, scaled by `DCTCP_ALPHA_ONE`.
+    pub alpha: u32,
+    bytes_acked: usize,
+    bytes_marked: usize,
+    /// Observation window ends once `snd_una` passes this.
+    window_end: Option<u32>,
+}
+
+impl Default for Dctcp {
+    fn default() -> Self {
+        Self {
+            reno: Reno::default(),
+            // §3.3: start at 1 to stay conservative until the first window.
+            alpha: DCTCP_ALPHA_ONE,
+            bytes_acked: 0,
+            bytes_marked: 0,
+            window_end: None,
+        }
+    }
+}
+
+impl CongestionControl for Dctcp {
+    fn cwnd(&self) -> usize {
+        self.reno.cwnd
+    }
+    fn ssthresh(&self) -> usize {
+        self.reno.ssthresh
+    }
+    fn on_ack(&mut self, acked: usize, mss: usize) {
+        self.reno.on_ack(acked, mss);
+    }
+    fn on_ecn_echo(&mut self, _flight: usize, mss: usize) {
+        let cut = self.reno.cwnd * self.alpha as usize / (2 * DCTCP_ALPHA_ONE as usize);
+        self.reno.cwnd = (self.reno.cwnd - cut).max(2 * mss);
+        self.reno.ssthresh = self.reno.cwnd;
+    }
+    fn on_loss(&mut self, flight: usize, mss: usize) {
+        // §3.5: losses are handled like standard TCP
+        self.reno.on_loss(flight, mss);
+    }
+    fn set_cwnd(&mut self, cwnd: usize) {
+        self.reno.cwnd = cwnd;
+    }
+    fn set_ssthresh(&mut self, ssthresh: usize) {
+        self.reno.ssthresh = ssthresh;
+    }
+    fn ecn_feedback(&self) -> EcnFeedback {
+        EcnFeedback::Dctcp
+    }
+    fn on_ecn_ack(&mut self, acked: usize, ece: bool, snd_una: u32, snd_nxt: u32) {
+        self.bytes_acked += acked;
+        if ece {
+            self.bytes_marked += acked;
+        }
+        let end = *self.window_end.get_or_insert(snd_nxt);
+        if (snd_una.wrapping_sub(end) as i32) < 0 {
+            return;
+        }
+        // alpha = (1 - g) * alpha + g * F
+        let f = (self.bytes_marked * DCTCP_ALPHA_ONE as usize)
+            .checked_div(self.bytes_acked)
+            .unwrap_or(0) as u32;
+        self.alpha = self.alpha - (self.alpha >> DCTCP_SHIFT_G) + (f >> DCTCP_SHIFT_G);
+        self.alpha = self.alpha.min(DCTCP_ALPHA_ONE);
+        self.bytes_acked = 0;
+        self.bytes_marked = 0;
+        self.window_end = Some(snd_nxt);
+    }
+}
+
+/// How long a non-validated cwnd is preserved (RFC 7661 NVP).
+pub const CWV_NVP: Duration = Duration::from_secs(300);
+
+/// Congestion window validation (RFC 7661): tracks how much of cwnd the
+/// application actually uses, so that an application-limited flow does not
+/// keep growing, or later burst, a window it never validated.
+#[derive(Debug, Default)]
+pub struct Cwv {
+    /// Largest volume acknowledged per RTT over the recent samples; `None`
+    /// until the first sample closes.
+    pub pipe_ack: Option<usize>,
+    samples: [usize; 3],
+    next_sample: usize,
+    sample_acked: usize,
+    sample_start: Option<Instant>,
+    /// Data waited on cwnd during the current sample.
+    pub cwnd_limited: bool,
+    nvp_start: Option<Instant>,
+    last_send: Option<Instant>,
+}
+
+impl Cwv {
+    pub fn on_send(&mut self, now: Instant) {
+        self.last_send = Some(now);
+    }
+
+    pub fn on_cwnd_limited(&mut self) {
+        self.cwnd_limited = true;
+    }
+
+    /// Fold newly acknowledged bytes into the pipeACK sample, which closes
+    /// after one smoothed RTT.
+    pub fn on_ack(&mut self, acked: usize, srtt: Option<Duration>, now: Instant) {
+        self.sample_acked += acked;
+        let start = *self.sample_start.get_or_insert(now);
+        let Some(srtt) = srtt else {
+            return;
+        };
+        if now.saturating_duration_since(start) < srtt {
+            return;
+        }
+        self.samples[self.next_sample] = self.sample_acked;
+        self.next_sample = (self.next_sample + 1) % self.samples.len();
+        self.pipe_ack = self.samples.iter().max().copied();
+        self.sample_acked = 0;
+        self.sample_start = Some(now);
+        self.cwnd_limited = false;
+    }
+
+    /// Validated phase: pipeACK is at least half of cwnd.
+    pub fn validated(&self, cwnd: usize) -> bool {
+        self.pipe_ack.is_none_or(|p| p * 2 >= cwnd)
+    }
+
+    /// Grow cwnd only while it is in use.
+    pub fn may_grow(&self, cwnd: usize) -> bool {
+        self.cwnd_limited || self.validated(cwnd)
+    }
+
+    /// Flight a loss response is based on. In the non-validated phase
+    /// pipeACK stands in for a flight the application kept small.
+    pub fn loss_flight(&self, flight: usize, cwnd: usize) -> usize {
+        if self.validated(cwnd) {
+            flight
+        } else {
+            flight.max(self.pipe_ack.unwrap_or(0))
+        }
+    }
+
+    /// Decay cwnd once the non-validated phase has lasted `CWV_NVP`, and
+    /// again for every further period.
+    pub fn check_nvp(&mut self, cc: &mut dyn CongestionControl, iw: usize, now: Instant) {
+        let cwnd = cc.cwnd();
+        if self.validated(cwnd) {
+            self.nvp_start = None;
+            return;
+        }
+        let start = *self.nvp_start.get_or_insert(now);
+        if now.saturating_duration_since(start) < CWV_NVP {
+            return;
+        }
+        cc.set_ssthresh(cc.ssthresh().max(cwnd * 3 / 4));
+        cc.set_cwnd((cwnd / 2).max(iw));
+        self.nvp_start = Some(now);
+    }
+
+    /// With nothing in flight and no transmission for longer than `rto`,
+    /// restart from RW = min(IW, cwnd) (RFC 5681 §4.1), remembering the old
+    /// window in ssthresh. Returns whether cwnd was cut.
+    pub fn restart_after_idle(
+        &mut self,
+        cc: &mut dyn CongestionControl,
+        iw: usize,
+        rto: Duration,
+        now: Instant,
+    ) -> bool {
+        if self
+            .last_send
+            .is_none_or(|t| now.saturating_duration_since(t) <= rto)
+        {
+            return false;
+        }
+        // Whatever was measured before the idle period no longer applies.
+        *self = Self::default();
+        let cwnd = cc.cwnd();
+        if cwnd <= iw {
+            return false;
+        }
+        cc.set_ssthresh(cc.ssthresh().max(cwnd * 3 / 4));
+        cc.set_cwnd(iw);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    /// One observation window of `acks` full-sized ACKs starting at `una`,
+    /// `marked` of them with ECE; returns where the next window starts.
+    /// The next window goes out as the last ACK arrives.
+    fn window(cc: &mut Dctcp, una: u32, acks: u32, marked: u32) -> u32 {
+        let len = acks * MSS as u32;
+        let end = una.wrapping_add(len);
+        for i in 0..acks {
+            let una = una.wrapping_add((i + 1) * MSS as u32);
+            let nxt = if i + 1 == acks {
+                end.wrapping_add(len)
+            } else {
+                end
+            };
+            cc.on_ecn_ack(MSS, i < marked, una, nxt);
+        }
+        end
+    }
+
+    fn dctcp(alpha: u32) -> Dctcp {
+        Dctcp {
+            alpha,
+            ..Dctcp::default()
+        }
+    }
+
+    #[test]
+    fn alpha_starts_at_one() {
+        assert_eq!(Dctcp::default().alpha, DCTCP_ALPHA_ONE);
+    }
+
+    #[test]
+    fn alpha_updates_once_per_window() {
+        let mut cc = dctcp(DCTCP_ALPHA_ONE);
+        // The first ACK opens a window up to snd_nxt; ACKs within it
+        // leave alpha alone.
+        cc.on_ecn_ack(MSS, false, MSS as u32, 2 * MSS as u32);
+        assert_eq!(cc.alpha, DCTCP_ALPHA_ONE);
+        cc.on_ecn_ack(MSS, false, 2 * MSS as u32, 4 * MSS as u32);
+        // alpha = 15/16 * 1024 + 1/16 * 0
+        assert_eq!(cc.alpha, 960);
+        cc.on_ecn_ack(MSS, true, 3 * MSS as u32, 4 * MSS as u32);
+        assert_eq!(cc.alpha, 960);
+    }
+
+    #[test]
+    fn alpha_follows_the_marked_fraction() {
+        // Half the bytes marked, from zero: 1/16 * 512.
+        let mut cc = dctcp(0);
+        window(&mut cc, 0, 10, 5);
+        assert_eq!(cc.alpha, 32);
+
+        // Every byte marked keeps alpha at one.
+        let mut cc = dctcp(DCTCP_ALPHA_ONE);
+        window(&mut cc, 0, 10, 10);
+        assert_eq!(cc.alpha, DCTCP_ALPHA_ONE);
+    }
+
+    #[test]
+    fn alpha_converges() {
+        let mut cc = dctcp(DCTCP_ALPHA_ONE);
+        let mut una = 0;
+        for _ in 0..200 {
+            una = window(&mut cc, una, 8, 2);
+        }
+        // A quarter marked. Truncating alpha / 16 lets it settle anywhere
+        // the decay rounds to the same step, up to 15/1024 high.
+        assert!((256..272).contains(&cc.alpha), "alpha {}", cc.alpha);
+    }
+
+    #[test]
+    fn windows_survive_sequence_wraparound() {
+        let start = u32::MAX - 3 * MSS as u32;
+        let mut cc = dctcp(0);
+        window(&mut cc, start, 10, 10);
+        assert_eq!(cc.alpha, DCTCP_ALPHA_ONE >> DCTCP_SHIFT_G);
+    }
+
+    #[test]
+    fn reduction_scales_with_alpha() {
+        let mut cc = Dctcp {
+            alpha: DCTCP_ALPHA_ONE / 2,
+            ..Dctcp::default()
+        };
+        cc.set_cwnd(100 * MSS);
+        cc.on_ecn_echo(100 * MSS, MSS);
+        // cwnd * (1 - alpha / 2)
+        assert_eq!(cc.cwnd(), 75 * MSS);
+        assert_eq!(cc.ssthresh(), 75 * MSS);
+
+        // Never below two segments.
+        cc.alpha = DCTCP_ALPHA_ONE;
+        cc.set_cwnd(3 * MSS);
+        cc.on_ecn_echo(3 * MSS, MSS);
+        assert_eq!(cc.cwnd(), 2 * MSS);
+    }
+}
@@ -1,17 +1,34 @@
+use bytes::BytesMut;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Shutdown};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 
+use crate::clock::Clock;
 use crate::error::*;
-use crate::wire::tcp::*;
+use crate::ip::ping::PingOptions;
+use crate::wire::tcp::TcpHeader;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Quad {
-    pub src_ip: [u8; 4],
+    pub src_ip: IpAddr,
     pub src_port: u16,
-    pub dst_ip: [u8; 4],
+    pub dst_ip: IpAddr,
     pub dst_port: u16,
 }
 
+impl Quad {
+    /// Addresses and ports in network byte order, for keyed hashes.
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let mut buf = octets(self.src_ip);
+        buf.extend_from_slice(&self.src_port.to_be_bytes());
+        buf.extend_from_slice(&octets(self.dst_ip));
+        buf.extend_from_slice(&self.dst_port.to_be_bytes());
+        buf
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum State {
     Closed,
@@ -22,6 +39,7 @@ pub enum State {
     FinWait1,
     FinWait2,
     CloseWait,
+    Closing,
     LastAck,
     TimeWait,
 }
@@ -32,14 +50,94 @@ pub struct Connection {
     pub iss: u32,
     pub snd_una: u32,
     pub snd_nxt: u32,
+    pub snd_wnd: u32,
     pub rcv_nxt: u32,
-    pub cwnd: usize,
-    pub ssthresh: usize,
+    /// ACK number carried by the last segment we sent.
+    pub last_ack_sent: u32,
+    pub mss: usize,
+    pub cc: Box<dyn CongestionControl>,
+    pub ecn: Ecn,
+    /// SACK requested before the handshake completes, negotiated afterwards.
+    pub sack_ok: bool,
+    pub rtx: RetransmitQueue,
+    pub dupacks: usize,
+    /// Reordering degree in segments; the duplicate ACK threshold.
+    pub reordering: usize,
+    /// Loss recovery in progress.
+    pub prr: Option<Prr>,
+    /// RACK-TLP requested; only active once SACK is negotiated.
+    pub rack_enabled: bool,
+    pub rack: Rack,
+    pub tlp: Tlp,
+    pub srtt: Option<Duration>,
+    pub rttvar: Duration,
+    pub timers: ConnTimers,
+    /// `None` when the stack runs unpaced.
+    pub pacer: Option<Pacer>,
+    /// `None` when congestion window validation is off.
+    pub cwv: Option<Cwv>,
+    /// `None` when keepalive is off.
+    pub keepalive: Option<Keepalive>,
+    /// Keepalive probes sent since the peer was last heard from.
+    pub keepalive_probes: u32,
+    /// Local user timeout; see `effective_user_timeout`.
+    pub user_timeout: Option<Duration>,
+    /// Advertise the user timeout and honour the peer's (RFC 5482).
+    pub uto_enabled: bool,
+    /// Carry a UTO option on the next segment.
+    pub uto_pending: bool,
+    pub peer_uto: Option<Duration>,
+    /// Since when the data at `snd_una` has waited for an ACK.
+    pub unacked_since: Option<Instant>,
+    /// Send urgent pointer: sequence number after the last urgent byte.
+    pub snd_up: Option<u32>,
+    /// Receive urgent pointer, until the urgent byte is delivered.
+    pub rcv_up: Option<u32>,
+    pub oob_inline: bool,
+    /// The application is done sending: a FIN follows the send buffer.
+    pub fin_queued: bool,
+    /// Sequence number of our FIN once sent.
+    pub fin_seq: Option<u32>,
+    /// The application is done receiving; further data is dropped.
+    pub rd_shutdown: bool,
+    /// Lingering close, woken once our FIN is acknowledged or the linger
+    /// time is up.
+    pub linger: Option<oneshot::Sender<Result<()>>>,
+    pub fast_open: FastOpen,
+    /// TCP-AO or TCP-MD5, if configured for the peer.
+    pub auth: Option<AuthState>,
+    /// tcpcrypt, while offered or once negotiated.
+    pub crypt: Option<Tcpcrypt>,
+    /// `None` when MTU probing and black-hole detection are off.
+    pub plpmtud: Option<Plpmtud>,
+    /// `None` unless timestamps were negotiated.
+    pub ts: Option<Timestamps>,
+    pub syn_retries: u32,
+    /// Retransmission timeouts since the last forward progress.
+    pub retries: u32,
+    /// Fewer for orphans.
+    pub max_retries: u32,
+    /// Since when no `TcpStream` owns the connection.
+    pub orphaned: Option<Instant>,
+    /// Counted in its listener's half-open queue.
+    pub half_open: bool,
+    /// How long an orphan waits in FIN-WAIT-2 for the peer's FIN.
+    pub fin_wait2_timeout: Option<Duration>,
+    pub stats: ConnStats,
+    /// Out-of-order segments awaiting the hole at `rcv_nxt`, in sequence order.
+    pub ooo: Vec<(u32, Vec<u8>)>,
+    /// Start of the most recently queued out-of-order segment (first SACK block).
+    last_ooo: Option<u32>,
     pub rto: Duration,
     pub last_activity: Instant,
     // TX/RX queues (simplified)
-    pub app_rx: mpsc::Sender<Vec<u8>>,
+    pub snd_buf: BytesMut,
+    /// Control segments generated while processing input, sent before any reply.
+    pub outbox: VecDeque<Segment>,
+    pub app_rx: mpsc::Sender<Result<Incoming>>,
     pub app_tx: mpsc::Receiver<Vec<u8>>,
+    /// The stack's clock.
+    pub clock: Arc<dyn Clock>,
 }
 
 // impl Connection {
@@ -84,7 +182,119 @@ pub struct Connection {
 //     }
 // }
 
-use crate::wire::tcp::{self, FLAG_ACK, FLAG_FIN, FLAG_SYN};
+use crate::tcp::auth::{AuthState, octets};
+use crate::tcp::congestion::{CongestionControl, Cwv, EcnFeedback, Reno};
+use crate::tcp::crypt::Tcpcrypt;
+use crate::tcp::fastopen::FastOpen;
+use crate::tcp::metrics::DestMetrics;
+use crate::tcp::pacing::Pacer;
+use crate::tcp::pmtu::{self, BLACK_HOLE_RETRIES, Plpmtud, Probe};
+use crate::tcp::rack::{self, Rack, Tlp};
+use crate::tcp::recovery::{Prr, RecoveryEpisode};
+use crate::tcp::rtx::{DUP_THRESH, RetransmitQueue};
+use crate::tcp::socket::{
+    ConnectOptions, Incoming, Keepalive, ListenOptions, ListenerStats, SockOpt, TcpStream,
+};
+use crate::tcp::timers::ConnTimers;
+use crate::wire::ipv4::{ECN_CE, ECN_ECT0, ECN_NOT_ECT};
+use crate::wire::tcp::{
+    self, FLAG_ACK, FLAG_CWR, FLAG_ECE, FLAG_FIN, FLAG_PSH, FLAG_RST, FLAG_SYN, FLAG_URG,
+    TcpOption, TcpView,
+};
+
+pub const DEFAULT_MSS: usize = 1460;
+/// SACK blocks per ACK; three still fit alongside the timestamp option.
+const MAX_SACK_BLOCKS: usize = 3;
+/// Room for TCP options in a header.
+const MAX_OPTIONS_LEN: usize = 40;
+/// Out-of-order segments kept before further ones are dropped.
+const MAX_OOO_SEGMENTS: usize = 256;
+/// Recovery episodes kept in `ConnStats::recoveries`.
+const RECOVERY_HISTORY: usize = 16;
+pub const MIN_RTO: Duration = Duration::from_millis(200);
+pub const MAX_RTO: Duration = Duration::from_secs(60);
+/// Upper bound on `Connection::reordering`.
+pub const MAX_REORDERING: usize = 300;
+/// Bounds on how far a peer's UTO may move our user timeout (RFC 5482 §3.1).
+pub const UTO_L_LIMIT: Duration = Duration::from_secs(100);
+pub const UTO_U_LIMIT: Duration = Duration::from_secs(900);
+/// SYN retransmissions before an active open gives up.
+pub const MAX_SYN_RETRIES: u32 = 6;
+/// Consecutive retransmission timeouts before a connection is given up.
+pub const MAX_RETRIES: u32 = 15;
+/// Maximum segment lifetime; TIME-WAIT lasts twice this.
+pub const MSL: Duration = Duration::from_secs(30);
+/// The receive window we advertise; the stack does not scale windows.
+pub const RCV_WND: u32 = 65535;
+/// Bytes the timestamp option takes up in every segment, padding included.
+const TS_OPTION_LEN: usize = 12;
+
+/// Per-connection counters.
+#[derive(Debug, Clone, Default)]
+pub struct ConnStats {
+    pub segs_out: u64,
+    pub bytes_out: u64,
+    pub bytes_retrans: u64,
+    pub fast_recoveries: u64,
+    pub rto_timeouts: u64,
+    /// Window reductions for an ECN-Echo.
+    pub ecn_reductions: u64,
+    pub tlp_probes: u64,
+    /// Retransmitted probes whose ACK showed they repaired a tail loss.
+    pub tlp_recoveries: u64,
+    pub keepalive_probes: u64,
+    /// Restarts from the initial window after idling longer than the RTO.
+    pub idle_restarts: u64,
+    /// Segments dropped for a missing or bad TCP-AO or TCP-MD5 signature.
+    pub auth_failures: u64,
+    /// PLPMTUD probes sent, and those lost.
+    pub mtu_probes: u64,
+    pub mtu_probes_lost: u64,
+    /// Times the MSS was lowered for a Fragmentation Needed message or a
+    /// black hole.
+    pub mss_reductions: u64,
+    /// Most recent recovery episodes, oldest first. The last one may still be
+    /// in progress (`finished == false`).
+    pub recoveries: VecDeque<RecoveryEpisode>,
+}
+
+/// Per-connection ECN state (RFC 3168).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ecn {
+    /// Requested before the handshake completes, negotiated afterwards.
+    pub enabled: bool,
+    /// Receiver: set ECE on outgoing ACKs. Classic feedback latches a CE mark
+    /// until CWR arrives, DCTCP feedback tracks the CE state of the last segment.
+    pub echo: bool,
+    /// Sender: set CWR on the next new data segment.
+    pub cwr: bool,
+    /// Sender: ignore further ECE until `snd_una` moves past this.
+    pub recover: u32,
+}
+
+/// RFC 7323 timestamps, offered in the SYN and kept if the peer echoes them.
+/// Connections rebuilt from a SYN cookie have them if the peer offered them.
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamps {
+    /// Our TSval counts milliseconds from here.
+    pub epoch: Instant,
+    /// TS.Recent: the peer's TSval to echo.
+    pub recent: u32,
+}
+
+impl Timestamps {
+    pub fn val(&self, now: Instant) -> u32 {
+        now.saturating_duration_since(self.epoch).as_millis() as u32
+    }
+}
+
+/// An outgoing segment ready for IP encapsulation.
+pub struct Segment {
+    pub hdr: TcpHeader,
+    /// IP ECN codepoint to send the segment with.
+    pub ecn: u8,
+    pub payload: Vec<u8>,
+}
 
 pub enum RxAction {
     None,
@@ -92,12 +302,35 @@ pub enum RxAction {
     SendSynAck,
 }
 
+/// `a < b` in sequence space.
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// `a <= b` in sequence space.
+pub fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// Suboptions of the segment's TCP-ENO option, if it has one.
+pub fn eno_option<'a>(tv: &TcpView<'a>) -> Option<&'a [u8]> {
+    tv.options().find_map(|o| match o {
+        TcpOption::Eno(subopts) => Some(subopts),
+        _ => None,
+    })
+}
+
+fn fin_flag(fin: bool) -> u16 {
+    if fin { FLAG_FIN } else { 0 }
+}
+
 impl Connection {
     pub fn new(
         id: Quad,
         state: State,
-        app_rx: mpsc::Sender<Vec<u8>>,
+        app_rx: mpsc::Sender<Result<Incoming>>,
         app_tx: mpsc::Receiver<Vec<u8>>,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         Self {
             id,
@@ -105,73 +338,1395 @@ impl Connection {
             iss: 0,
             snd_una: 0,
             snd_nxt: 0,
+            snd_wnd: 0,
             rcv_nxt: 0,
-            cwnd: 1_460,
-            ssthresh: 65_535,
+            last_ack_sent: 0,
+            mss: DEFAULT_MSS,
+            cc: Box::new(Reno::default()),
+            ecn: Ecn::default(),
+            sack_ok: false,
+            rtx: RetransmitQueue::default(),
+            dupacks: 0,
+            reordering: DUP_THRESH,
+            prr: None,
+            rack_enabled: false,
+            rack: Rack::default(),
+            tlp: Tlp::default(),
+            srtt: None,
+            rttvar: Duration::ZERO,
+            timers: ConnTimers::default(),
+            pacer: None,
+            cwv: None,
+            keepalive: None,
+            keepalive_probes: 0,
+            user_timeout: None,
+            uto_enabled: false,
+            uto_pending: false,
+            peer_uto: None,
+            unacked_since: None,
+            snd_up: None,
+            rcv_up: None,
+            oob_inline: false,
+            fin_queued: false,
+            fin_seq: None,
+            rd_shutdown: false,
+            linger: None,
+            fast_open: FastOpen::default(),
+            auth: None,
+            crypt: None,
+            plpmtud: None,
+            ts: None,
+            syn_retries: 0,
+            retries: 0,
+            max_retries: MAX_RETRIES,
+            orphaned: None,
+            half_open: false,
+            fin_wait2_timeout: None,
+            stats: ConnStats::default(),
+            ooo: Vec::new(),
+            last_ooo: None,
             rto: Duration::from_millis(300),
-            last_activity: Instant::now(),
+            last_activity: clock.now(),
+            snd_buf: BytesMut::new(),
+            outbox: VecDeque::new(),
             app_rx,
             app_tx,
+            clock,
+        }
+    }
+
+    /// Earliest pending timer deadline.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.next()
+    }
+
+    /// Service whichever timers are due at `now`.
+    pub fn on_timer(&mut self, now: Instant) -> Result<()> {
+        if self.timers.rto.is_some_and(|t| t <= now) {
+            return self.on_retransmit_timeout();
+        }
+        if self.timers.reorder.is_some_and(|t| t <= now) {
+            self.timers.reorder = None;
+            if self.detect_loss(now) && self.prr.is_none() {
+                self.enter_recovery(0);
+            }
+        }
+        if self.timers.tlp.is_some_and(|t| t <= now) {
+            self.timers.tlp = None;
+            self.send_probe(now);
+        }
+        if self.timers.pace.is_some_and(|t| t <= now) {
+            // released; the stack polls for data next
+            self.timers.pace = None;
+        }
+        if self.timers.keepalive.is_some_and(|t| t <= now) {
+            self.timers.keepalive = None;
+            self.on_keepalive(now);
+        }
+        if self.timers.fin_wait2.is_some_and(|t| t <= now) {
+            self.timers.fin_wait2 = None;
+            if matches!(self.state, State::FinWait2) {
+                self.abort(UrtcpError::TimedOut);
+                return Ok(());
+            }
+        }
+        if self.timers.linger.is_some_and(|t| t <= now) {
+            self.timers.linger = None;
+            if let Some(linger) = self.linger.take() {
+                let _ = linger.send(Err(UrtcpError::TimedOut));
+            }
+        }
+        if self.timers.user_timeout.is_some_and(|t| t <= now) {
+            self.timers.user_timeout = None;
+            // Any synchronized state: closing ones retransmit too.
+            let synchronized = !matches!(
+                self.state,
+                State::Closed | State::Listen | State::SynSent | State::SynReceived
+            );
+            if synchronized && !self.rtx.is_empty() {
+                self.abort(UrtcpError::TimedOut);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the local user timeout, advertising it if UTO is enabled.
+    pub fn set_user_timeout(&mut self, timeout: Option<Duration>) {
+        self.user_timeout = timeout;
+        self.uto_pending = self.uto_enabled && timeout.is_some();
+        self.arm_user_timeout();
+    }
+
+    /// The user timeout in force. A peer's UTO can only lengthen ours, and
+    /// only within `UTO_L_LIMIT..=UTO_U_LIMIT` (RFC 5482 §3).
+    pub fn effective_user_timeout(&self) -> Option<Duration> {
+        let local = self.user_timeout?;
+        match self.peer_uto.filter(|_| self.uto_enabled) {
+            Some(remote) => Some(local.max(remote.clamp(UTO_L_LIMIT, UTO_U_LIMIT))),
+            None => Some(local),
+        }
+    }
+
+    fn arm_user_timeout(&mut self) {
+        self.timers.user_timeout = self
+            .unacked_since
+            .zip(self.effective_user_timeout())
+            .map(|(since, uto)| since + uto);
+    }
+
+    /// Schedule the first keepalive check after the peer was last heard from.
+    pub fn arm_keepalive(&mut self) {
+        self.timers.keepalive = self.keepalive.map(|k| self.last_activity + k.idle);
+    }
+
+    /// Probe an idle peer with an already acknowledged sequence number, and
+    /// give up once `probes` probes went unanswered.
+    fn on_keepalive(&mut self, now: Instant) {
+        let Some(ka) = self.keepalive else {
+            return;
+        };
+        if !matches!(self.state, State::Established) {
+            return;
+        }
+        if !self.rtx.is_empty() || !self.snd_buf.is_empty() {
+            // Not idle; retransmissions will notice a dead peer.
+            self.timers.keepalive = Some(now + ka.idle);
+            return;
+        }
+        if now.saturating_duration_since(self.last_activity) < ka.idle {
+            self.arm_keepalive();
+            return;
+        }
+        // With a user timeout it, not the probe count, decides when to give up.
+        let expired = match self.effective_user_timeout() {
+            Some(uto) => {
+                self.keepalive_probes > 0
+                    && now.saturating_duration_since(self.last_activity) >= uto
+            }
+            None => self.keepalive_probes >= ka.probes,
+        };
+        if expired {
+            self.abort(UrtcpError::TimedOut);
+            return;
+        }
+        let probe = self.segment(self.snd_una.wrapping_sub(1), FLAG_ACK, Vec::new());
+        self.outbox.push_back(probe);
+        self.keepalive_probes += 1;
+        self.stats.keepalive_probes += 1;
+        self.timers.keepalive = Some(now + ka.interval);
+    }
+
+    /// Reset the connection and report `err` to the application. The stack
+    /// drops the connection once the RST is out.
+    pub fn abort(&mut self, err: UrtcpError) {
+        if !matches!(self.state, State::SynSent) {
+            let rst = self.segment(self.snd_nxt, FLAG_RST, Vec::new());
+            self.outbox.push_back(rst);
+        }
+        self.reset(err);
+    }
+
+    /// Drop everything queued and close without telling the peer.
+    fn reset(&mut self, err: UrtcpError) {
+        let _ = self.app_rx.try_send(Err(err));
+        self.snd_buf.clear();
+        self.rtx = RetransmitQueue::default();
+        self.ooo.clear();
+        self.state = State::Closed;
+        self.timers = ConnTimers::default();
+    }
+
+    /// Stop sending, with a FIN after the queued data, and/or receiving.
+    pub fn shutdown(&mut self, how: Shutdown) {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.rd_shutdown = true;
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            match self.state {
+                // The peer has not been promised anything yet.
+                State::SynSent
+                    if self.snd_buf.is_empty()
+                        && self.crypt.as_ref().is_none_or(|c| !c.has_pending()) =>
+                {
+                    self.state = State::Closed;
+                    self.timers = ConnTimers::default();
+                }
+                State::SynSent | State::SynReceived | State::Established | State::CloseWait => {
+                    self.fin_queued = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Shut down both directions; `linger` fires once the peer has
+    /// acknowledged everything, FIN included, or with `TimedOut` once its
+    /// time is up.
+    pub fn close(&mut self, linger: Option<(Duration, oneshot::Sender<Result<()>>)>) {
+        self.shutdown(Shutdown::Both);
+        if let Some((t, linger)) = linger {
+            if self.fin_acked() || matches!(self.state, State::Closed) {
+                let _ = linger.send(Ok(()));
+            } else {
+                self.linger = Some(linger);
+                self.timers.linger = Some(self.clock.now() + t);
+            }
+        }
+    }
+
+    /// No `TcpStream` owns the connection any more: it finishes on its own,
+    /// giving up after `retries` retransmission timeouts and, in
+    /// FIN-WAIT-2, after `fin_wait2` without the peer's FIN.
+    pub fn orphan(&mut self, now: Instant, retries: u32, fin_wait2: Duration) {
+        self.orphaned.get_or_insert(now);
+        self.max_retries = self.max_retries.min(retries);
+        self.fin_wait2_timeout = Some(fin_wait2);
+        self.arm_fin_wait2(now);
+    }
+
+    fn arm_fin_wait2(&mut self, now: Instant) {
+        if matches!(self.state, State::FinWait2) {
+            self.timers.fin_wait2 = self.fin_wait2_timeout.map(|t| now + t);
+        }
+    }
+
+    fn fin_acked(&self) -> bool {
+        self.fin_seq.is_some_and(|s| seq_lt(s, self.snd_una))
+    }
+
+    /// Done but for TIME-WAIT, which the stack keeps in its own table.
+    fn enter_time_wait(&mut self) {
+        self.state = State::TimeWait;
+        self.timers = ConnTimers::default();
+    }
+
+    /// RFC 9293 §3.10.7: a reset in window closes the connection; in
+    /// SYN-SENT it must acknowledge our SYN.
+    fn on_rst(&mut self, tv: &TcpView<'_>) {
+        match self.state {
+            State::SynSent => {
+                if (tv.flags & FLAG_ACK) != 0 && tv.ack == self.snd_nxt {
+                    self.reset(UrtcpError::ConnectionRefused);
+                }
+            }
+            // Back to listening, which for us means forgetting it.
+            State::SynReceived => self.reset(UrtcpError::ConnectionReset),
+            State::Closed => {}
+            _ => {
+                let off = tv.seq.wrapping_sub(self.rcv_nxt);
+                if off < RCV_WND {
+                    self.reset(UrtcpError::ConnectionReset);
+                }
+            }
         }
     }
 
     /// Called by timer wheel on RTO
     pub fn on_retransmit_timeout(&mut self) -> Result<()> {
-        // backoff, retransmit snd_una..snd_nxt
-        Err(UrtcpError::NotImplemented("RTO"))
+        let now = self.clock.now();
+        if matches!(self.state, State::SynSent) {
+            self.on_syn_timeout(now);
+            return Ok(());
+        }
+        if matches!(self.state, State::SynReceived) {
+            self.on_syn_ack_timeout(now);
+            return Ok(());
+        }
+        if self.rtx.is_empty() {
+            self.timers.rto = None;
+            return Ok(());
+        }
+        self.retries += 1;
+        // A user timeout, when set, decides alone when to give up (RFC 5482 §3).
+        if self.retries > self.max_retries && self.effective_user_timeout().is_none() {
+            self.abort(UrtcpError::TimedOut);
+            return Ok(());
+        }
+        // RFC 6298 §5.5-5.6: back off and go back to slow start.
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        let flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        self.cc.on_rto(flight, self.mss);
+        if let Some(prr) = self.prr.take() {
+            let mut episode = prr.episode;
+            episode.finished = true;
+            self.record_episode(episode);
+        }
+        self.rtx.mark_all_lost();
+        if !self.check_probe() && self.retries.is_multiple_of(BLACK_HOLE_RETRIES) {
+            self.on_black_hole();
+        }
+        self.dupacks = 0;
+        self.tlp = Tlp::default();
+        self.ecn.recover = self.snd_nxt;
+        self.stats.rto_timeouts += 1;
+        self.timers = ConnTimers {
+            rto: Some(now + self.rto),
+            keepalive: self.timers.keepalive,
+            user_timeout: self.timers.user_timeout,
+            fin_wait2: self.timers.fin_wait2,
+            linger: self.timers.linger,
+            ..ConnTimers::default()
+        };
+        Ok(())
+    }
+
+    /// Full-sized segments keep timing out where smaller ones might not:
+    /// fall back to a smaller MSS (RFC 4821 §7.7).
+    fn on_black_hole(&mut self) {
+        let overhead = self.mss - self.send_mss();
+        let full = self
+            .rtx
+            .iter()
+            .next()
+            .is_some_and(|s| s.data.len() + overhead > pmtu::BASE_MSS);
+        if !full {
+            return;
+        }
+        let Some(mss) = self
+            .plpmtud
+            .as_mut()
+            .and_then(|p| p.on_black_hole(self.mss))
+        else {
+            return;
+        };
+        self.mss = mss;
+        self.stats.mss_reductions += 1;
+        self.rtx.resegment(self.send_mss());
     }
 
-    pub fn on_segment(&mut self, seg: &[u8]) -> Result<RxAction> {
+    /// A Fragmentation Needed message says the path carries at most `mtu`:
+    /// lower the MSS and resend what was too large, without a congestion
+    /// response. Returns whether anything is due again.
+    pub fn on_path_mtu(&mut self, mtu: usize) -> bool {
+        let mss = pmtu::mss_for(mtu, self.id.dst_ip);
+        if let Some(p) = &mut self.plpmtud {
+            p.limit(mss);
+        }
+        if mss < self.mss {
+            self.mss = mss;
+            self.stats.mss_reductions += 1;
+        }
+        self.rtx.resegment(self.send_mss())
+    }
+
+    /// Whether the PLPMTUD probe in flight was just deemed lost; what it
+    /// carried is split up to be resent at the current MSS.
+    fn check_probe(&mut self) -> bool {
+        let Some(probe) = self.plpmtud.as_ref().and_then(|p| p.probe) else {
+            return false;
+        };
+        let lost = self
+            .rtx
+            .iter()
+            .any(|s| s.lost && !s.sacked && seq_le(probe.seq, s.seq) && seq_lt(s.seq, probe.end));
+        if lost {
+            if let Some(p) = &mut self.plpmtud {
+                p.on_probe_lost();
+            }
+            self.stats.mtu_probes_lost += 1;
+            self.rtx.resegment(self.send_mss());
+        }
+        lost
+    }
+
+    /// Payload for a PLPMTUD probe, if one is due and the windows and the
+    /// send buffer allow it (RFC 4821 §7.3).
+    fn probe_len(&mut self, wnd: usize, flight: usize, now: Instant) -> Option<(usize, usize)> {
+        if self.prr.is_some() || !matches!(self.state, State::Established | State::CloseWait) {
+            return None;
+        }
+        let overhead = self.mss - self.send_mss();
+        let mss = self.plpmtud.as_mut()?.next_probe(self.mss, now)?;
+        let len = mss - overhead;
+        (flight + len <= wnd && len <= self.snd_buf.len()).then_some((mss, len))
+    }
+
+    /// The SYN for an active open, carrying data if a Fast Open cookie is
+    /// at hand. The data stays in the send buffer until the SYN-ACK shows
+    /// whether the server took it.
+    pub fn syn(&mut self, now: Instant) -> Segment {
+        self.timers.rto = Some(now + self.rto);
+        let mut seg = self.segment(self.iss, FLAG_SYN, Vec::new());
+        // Data gets what the SYN's options leave of the MSS.
+        let room = self
+            .mss
+            .saturating_sub(seg.hdr.options.len() + self.auth_option_len());
+        let n = match self.fast_open.cookie {
+            Some(_) => self.snd_buf.len().min(room),
+            None => 0,
+        };
+        self.fast_open.syn_data = n;
+        self.stats.bytes_out += n as u64;
+        seg.payload = self.snd_buf[..n].to_vec();
+        seg
+    }
+
+    /// No SYN-ACK: retry with a plain SYN, in case a middlebox dropped the
+    /// one carrying data (RFC 7413 §4.1.3.1), and give up eventually.
+    fn on_syn_timeout(&mut self, now: Instant) {
+        if self.syn_retries >= MAX_SYN_RETRIES {
+            self.abort(UrtcpError::TimedOut);
+            return;
+        }
+        self.syn_retries += 1;
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        if self.fast_open.syn_data > 0 {
+            self.fast_open.syn_data_lost = true;
+        }
+        self.fast_open.request = false;
+        self.fast_open.cookie = None;
+        let syn = self.syn(now);
+        self.outbox.push_back(syn);
+    }
+
+    /// No ACK for our SYN-ACK: send it again, and drop the half-open
+    /// connection after as many tries as a SYN gets.
+    fn on_syn_ack_timeout(&mut self, now: Instant) {
+        if self.syn_retries >= MAX_SYN_RETRIES {
+            self.abort(UrtcpError::TimedOut);
+            return;
+        }
+        self.syn_retries += 1;
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        self.timers.rto = Some(now + self.rto);
+        let syn_ack = self.segment(self.iss, FLAG_SYN | FLAG_ACK, Vec::new());
+        self.outbox.push_back(syn_ack);
+    }
+
+    /// Server side of Fast Open: take the data a SYN with a valid cookie
+    /// carried.
+    pub fn accept_syn_data(&mut self, data: &[u8]) {
+        self.fast_open.accepted = true;
+        if !data.is_empty() {
+            self.deliver(data);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(data.len() as u32);
+        }
+    }
+
+    /// RFC 6298 §2 estimator.
+    fn on_rtt_sample(&mut self, r: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(r);
+                self.rttvar = r / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(r);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + r) / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(r);
+        self.rto = (srtt + (self.rttvar * 4).max(Duration::from_millis(1))).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    fn rack_active(&self) -> bool {
+        self.rack_enabled && self.sack_ok
+    }
+
+    /// Run loss detection; returns whether any segment was newly marked lost.
+    fn detect_loss(&mut self, now: Instant) -> bool {
+        if self.rack_active() {
+            let (lost, timeout) = self.rack.detect_loss(&mut self.rtx, self.srtt, now);
+            self.timers.reorder = timeout.map(|t| now + t);
+            lost
+        } else if self.sack_ok {
+            self.rtx.update_lost(self.mss, self.reordering)
+        } else {
+            false
+        }
+    }
+
+    /// Send a tail loss probe: new data if the window allows, else the last
+    /// outstanding segment again.
+    fn send_probe(&mut self, now: Instant) {
+        if self.rtx.is_empty() || self.prr.is_some() {
+            return;
+        }
+        let flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        let n = (self.snd_wnd as usize)
+            .saturating_sub(flight)
+            .min(self.send_mss())
+            .min(self.snd_buf.len());
+        let seg = if n > 0 {
+            self.tlp.is_retrans = false;
+            self.send_new(n, now)
+        } else {
+            let Some(last) = self.rtx.last_mut() else {
+                return;
+            };
+            last.sent = now;
+            last.retransmits += 1;
+            let (seq, data, fin) = (last.seq, last.data.clone(), last.fin);
+            self.tlp.is_retrans = true;
+            self.stats.bytes_retrans += data.len() as u64;
+            let mut out = self.segment(seq, FLAG_ACK | fin_flag(fin), data);
+            out.ecn = ECN_NOT_ECT;
+            out
+        };
+        self.tlp.end_seq = Some(self.snd_nxt);
+        self.stats.tlp_probes += 1;
+        self.timers.rto = Some(now + self.rto);
+        self.outbox.push_back(seg);
+    }
+
+    /// Arm the probe timer when the tail of the flight could be lost
+    /// without enough ACKs coming back to notice.
+    fn arm_tlp(&mut self, now: Instant) {
+        self.timers.tlp = None;
+        if !self.rack_active() || self.prr.is_some() || self.tlp.end_seq.is_some() {
+            return;
+        }
+        if self.rtx.is_empty() {
+            return;
+        }
+        let rto_left = self
+            .timers
+            .rto
+            .map_or(self.rto, |t| t.saturating_duration_since(now));
+        self.timers.tlp = Some(now + rack::pto(self.srtt, self.rtx.len(), rto_left));
+    }
+
+    /// Handle an inbound TCP segment (without IP header). `ip_ecn` is the ECN
+    /// codepoint of the carrying IP packet.
+    pub fn on_segment(&mut self, seg: &[u8], ip_ecn: u8) -> Result<RxAction> {
         let tv = tcp::parse(seg).ok_or(UrtcpError::Malformed)?;
+        if (tv.flags & FLAG_RST) != 0 {
+            self.on_rst(&tv);
+            return Ok(RxAction::None);
+        }
+        self.last_activity = self.clock.now();
+        self.keepalive_probes = 0;
+        self.arm_keepalive();
+        if self.uto_enabled {
+            for opt in tv.options() {
+                if let TcpOption::UserTimeout { minutes, timeout } = opt {
+                    self.peer_uto = Some(tcp::user_timeout_duration(minutes, timeout));
+                    self.arm_user_timeout();
+                }
+            }
+        }
+        if let Some(ts) = &mut self.ts
+            && let Some(TcpOption::Timestamps { val, .. }) = tv
+                .options()
+                .find(|o| matches!(o, TcpOption::Timestamps { .. }))
+            && seq_le(tv.seq, self.last_ack_sent)
+        {
+            ts.recent = val;
+        }
         match self.state {
             State::SynSent => {
                 let got_syn = (tv.flags & FLAG_SYN) != 0;
                 let got_ack = (tv.flags & FLAG_ACK) != 0;
-                if got_syn && got_ack && tv.ack == self.iss.wrapping_add(1) {
+                let syn_data = self.fast_open.syn_data;
+                let data_acked = syn_data > 0
+                    && tv.ack == self.iss.wrapping_add(1).wrapping_add(syn_data as u32);
+                if got_syn && got_ack && (tv.ack == self.iss.wrapping_add(1) || data_acked) {
+                    if data_acked {
+                        let _ = self.snd_buf.split_to(syn_data);
+                    }
+                    if self.fast_open.request {
+                        self.fast_open.new_cookie = tv.options().find_map(|o| match o {
+                            TcpOption::FastOpen(c) if !c.is_empty() => Some(c.to_vec()),
+                            _ => None,
+                        });
+                    }
+                    self.timers.rto = None;
                     self.rcv_nxt = tv.seq.wrapping_add(1);
                     self.snd_una = tv.ack;
                     self.snd_nxt = self.snd_una;
+                    self.snd_wnd = tv.window as u32;
+                    self.sack_ok &= tv.options().any(|o| o == TcpOption::SackPermitted);
+                    let ts_val = tv.options().find_map(|o| match o {
+                        TcpOption::Timestamps { val, .. } => Some(val),
+                        _ => None,
+                    });
+                    self.ts = self
+                        .ts
+                        .zip(ts_val)
+                        .map(|(ts, recent)| Timestamps { recent, ..ts });
+                    // ECN-setup SYN-ACK: ECE without CWR (RFC 3168 §6.1.1)
+                    self.ecn.enabled &= tv.flags & (FLAG_ECE | FLAG_CWR) == FLAG_ECE;
+                    self.ecn.recover = self.iss;
                     self.state = State::Established;
+                    if let Some(crypt) = &mut self.crypt {
+                        match crypt.on_syn_ack(eno_option(&tv)) {
+                            Some(init1) => self.snd_buf.extend_from_slice(&init1),
+                            None => self.fall_back_to_plain(),
+                        }
+                    }
                     return Ok(RxAction::SendAck);
                 }
             }
-            State::SynReceived => {
-                if (tv.flags & FLAG_ACK) != 0 && tv.ack == self.iss.wrapping_add(1) {
-                    self.snd_una = tv.ack;
-                    self.state = State::Established;
-                    return Ok(RxAction::None);
+            // Under Fast Open the ACK may also cover data we already sent.
+            State::SynReceived
+                if (tv.flags & FLAG_ACK) != 0
+                    && seq_lt(self.iss, tv.ack)
+                    && seq_le(tv.ack, self.snd_nxt) =>
+            {
+                self.timers.rto = None;
+                self.snd_una = self.iss.wrapping_add(1);
+                self.snd_wnd = tv.window as u32;
+                self.ecn.recover = self.iss;
+                self.state = State::Established;
+                // A confirms tcpcrypt with ENO in its first ACK (RFC 8547 §4.6).
+                if self.crypt.is_some() && eno_option(&tv).is_none() {
+                    self.fall_back_to_plain();
                 }
+                return Ok(self.on_established(&tv, ip_ecn));
             }
-            State::Established => {
-                if (tv.flags & FLAG_FIN) != 0 {
-                    self.rcv_nxt = tv.seq.wrapping_add(1);
-                    // next: transition to CloseWait/LastAck etc.
-                    return Ok(RxAction::SendAck);
+            // The peer's SYN again: our SYN-ACK was lost.
+            State::SynReceived
+                if tv.flags & (FLAG_SYN | FLAG_ACK) == FLAG_SYN && seq_lt(tv.seq, self.rcv_nxt) =>
+            {
+                return Ok(RxAction::SendSynAck);
+            }
+            State::Established
+            | State::FinWait1
+            | State::FinWait2
+            | State::CloseWait
+            | State::Closing
+            | State::LastAck
+            | State::TimeWait => return Ok(self.on_established(&tv, ip_ecn)),
+            _ => {}
+        }
+        Ok(RxAction::None)
+    }
+
+    /// Segment processing once synchronized.
+    fn on_established(&mut self, tv: &TcpView<'_>, ip_ecn: u8) -> RxAction {
+        self.on_ecn_marks(tv.flags, ip_ecn, !tv.payload.is_empty());
+        if (tv.flags & FLAG_ACK) != 0 {
+            self.on_ack(tv);
+        }
+        if self.fin_acked() {
+            if let Some(linger) = self.linger.take() {
+                self.timers.linger = None;
+                let _ = linger.send(Ok(()));
+            }
+            match self.state {
+                State::FinWait1 => {
+                    self.state = State::FinWait2;
+                    self.arm_fin_wait2(self.clock.now());
                 }
-                if !tv.payload.is_empty() {
-                    let _ = self.app_rx.try_send(tv.payload.to_vec());
-                    self.rcv_nxt = tv.seq.wrapping_add(tv.payload.len() as u32);
-                    return Ok(RxAction::SendAck);
+                State::Closing => self.enter_time_wait(),
+                State::LastAck => {
+                    self.state = State::Closed;
+                    self.timers = ConnTimers::default();
+                    return RxAction::None;
                 }
+                _ => {}
             }
+        }
+        // Only until the peer's FIN; text after it is ignored.
+        let receiving = matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        if (tv.flags & FLAG_URG) != 0 && tv.urg_ptr > 0 && receiving {
+            let up = tv.seq.wrapping_add(tv.urg_ptr as u32);
+            // Only a newer mark, and only if its byte is still to come.
+            if self.rcv_up.is_none_or(|cur| seq_lt(cur, up)) && seq_lt(self.rcv_nxt, up) {
+                self.rcv_up = Some(up);
+            }
+        }
+        if !tv.payload.is_empty() && receiving {
+            self.on_data(tv.seq, tv.payload);
+        }
+        let fin_seq = tv.seq.wrapping_add(tv.payload.len() as u32);
+        if (tv.flags & FLAG_FIN) != 0 {
+            if receiving && fin_seq == self.rcv_nxt {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.on_fin();
+                return RxAction::SendAck;
+            }
+            if seq_lt(fin_seq, self.rcv_nxt) {
+                // Our ACK of it was lost.
+                return RxAction::SendAck;
+            }
+        }
+        if !tv.payload.is_empty() {
+            return RxAction::SendAck;
+        }
+        // An old sequence number, e.g. a keepalive probe, still
+        // elicits an ACK (RFC 9293 §3.10.7.4).
+        if tv.flags & (FLAG_SYN | FLAG_FIN | FLAG_RST) == 0 && seq_lt(tv.seq, self.rcv_nxt) {
+            return RxAction::SendAck;
+        }
+        RxAction::None
+    }
+
+    /// The peer is done sending: signal end of stream and move on.
+    fn on_fin(&mut self) {
+        let _ = self.app_rx.try_send(Ok(Incoming::Fin));
+        match self.state {
+            State::Established => self.state = State::CloseWait,
+            State::FinWait1 if !self.fin_acked() => self.state = State::Closing,
+            State::FinWait1 | State::FinWait2 => self.enter_time_wait(),
             _ => {}
         }
-        Ok(RxAction::None)
     }
 
-    pub fn poll_send(&mut self) -> Result<Option<Vec<u8>>> {
-        if let Ok(Some(buf)) = self.app_tx.try_recv().map(Some).or(Ok(None)) {
-            self.snd_nxt = self.snd_nxt.wrapping_add(buf.len() as u32);
-            return Ok(Some(buf));
+    /// Receiver side of RFC 3168 §6.1.3: latch CE into ECE until the sender
+    /// confirms with CWR. DCTCP receivers follow RFC 8257 §3.2 instead.
+    fn on_ecn_marks(&mut self, flags: u16, ip_ecn: u8, has_data: bool) {
+        if !self.ecn.enabled {
+            return;
+        }
+        if self.cc.ecn_feedback() == EcnFeedback::Dctcp {
+            let ce = ip_ecn == ECN_CE;
+            if has_data && ce != self.ecn.echo {
+                // CE state flipped: ACK anything not yet acknowledged under the
+                // old state first, so the sender sees exact marked byte counts.
+                if self.last_ack_sent != self.rcv_nxt {
+                    let ack = self.segment(self.snd_nxt, FLAG_ACK, Vec::new());
+                    self.outbox.push_back(ack);
+                }
+                self.ecn.echo = ce;
+            }
+            return;
+        }
+        if (flags & FLAG_CWR) != 0 {
+            self.ecn.echo = false;
+        }
+        if ip_ecn == ECN_CE && has_data {
+            self.ecn.echo = true;
+        }
+    }
+
+    /// Accept in-window payload: deliver what is in order, queue the rest.
+    fn on_data(&mut self, seq: u32, data: &[u8]) {
+        let end = seq.wrapping_add(data.len() as u32);
+        if seq_le(end, self.rcv_nxt) {
+            return; // duplicate
+        }
+        if seq_lt(self.rcv_nxt, seq) {
+            if self.ooo.len() < MAX_OOO_SEGMENTS {
+                let at = self
+                    .ooo
+                    .iter()
+                    .position(|(s, _)| seq_lt(seq, *s))
+                    .unwrap_or(self.ooo.len());
+                self.ooo.insert(at, (seq, data.to_vec()));
+                self.last_ooo = Some(seq);
+            }
+            return;
+        }
+        let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+        self.deliver(&data[skip..]);
+        self.rcv_nxt = end;
+        // Pull in whatever the new data made contiguous.
+        while let Some((s, _)) = self.ooo.first() {
+            if seq_lt(self.rcv_nxt, *s) {
+                break;
+            }
+            let (s, d) = self.ooo.remove(0);
+            let end = s.wrapping_add(d.len() as u32);
+            if seq_lt(self.rcv_nxt, end) {
+                let skip = self.rcv_nxt.wrapping_sub(s) as usize;
+                self.deliver(&d[skip..]);
+                self.rcv_nxt = end;
+            }
+        }
+        if self.ooo.is_empty() {
+            self.last_ooo = None;
+        }
+    }
+
+    /// Hand in-order data starting at `rcv_nxt` to the application, split
+    /// around the urgent byte if it is in there.
+    fn deliver(&mut self, data: &[u8]) {
+        if let Some(crypt) = &mut self.crypt {
+            match crypt.open(data) {
+                Ok(opened) => {
+                    self.snd_buf.extend_from_slice(&opened.reply);
+                    if !opened.plaintext.is_empty() && !self.rd_shutdown {
+                        let _ = self.app_rx.try_send(Ok(Incoming::Data(opened.plaintext)));
+                    }
+                }
+                Err(e) => self.abort(e),
+            }
+            return;
+        }
+        if self.rd_shutdown {
+            return;
+        }
+        let urgent = self
+            .rcv_up
+            .map(|up| up.wrapping_sub(1).wrapping_sub(self.rcv_nxt) as usize);
+        let Some(at) = urgent.filter(|at| *at < data.len()) else {
+            let _ = self.app_rx.try_send(Ok(Incoming::Data(data.to_vec())));
+            return;
+        };
+        self.rcv_up = None;
+        if at > 0 {
+            let _ = self
+                .app_rx
+                .try_send(Ok(Incoming::Data(data[..at].to_vec())));
+        }
+        let rest = if self.oob_inline {
+            &data[at..]
+        } else {
+            let _ = self.app_rx.try_send(Ok(Incoming::Oob(data[at])));
+            &data[at + 1..]
+        };
+        let _ = self.app_rx.try_send(Ok(Incoming::UrgentMark));
+        if !rest.is_empty() {
+            let _ = self.app_rx.try_send(Ok(Incoming::Data(rest.to_vec())));
+        }
+    }
+
+    /// SACK blocks describing the out-of-order queue, the block holding the
+    /// most recent arrival first (RFC 2018 §4).
+    fn sack_blocks(&self) -> Vec<(u32, u32)> {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for (s, d) in &self.ooo {
+            let e = s.wrapping_add(d.len() as u32);
+            match ranges.last_mut() {
+                Some(last) if seq_le(*s, last.1) => {
+                    if seq_lt(last.1, e) {
+                        last.1 = e;
+                    }
+                }
+                _ => ranges.push((*s, e)),
+            }
+        }
+        if let Some(recent) = self.last_ooo
+            && let Some(i) = ranges
+                .iter()
+                .position(|(l, r)| seq_le(*l, recent) && seq_lt(recent, *r))
+        {
+            let first = ranges.remove(i);
+            ranges.insert(0, first);
+        }
+        // Whatever room the timestamp and authentication options leave.
+        let ts_len = if self.ts.is_some() { 10 } else { 0 };
+        let room = MAX_OPTIONS_LEN - ts_len - self.auth_option_len() - self.eno_option_len() - 2;
+        ranges.truncate((room / 8).min(MAX_SACK_BLOCKS));
+        ranges
+    }
+
+    fn on_ack(&mut self, tv: &TcpView<'_>) {
+        let (ack, flags) = (tv.ack, tv.flags);
+        let now = self.clock.now();
+        let sacked_before = self.rtx.sacked_bytes();
+        if self.sack_ok {
+            for opt in tv.options() {
+                if let TcpOption::Sack(raw) = opt {
+                    for (l, r) in tcp::sack_blocks(raw) {
+                        if let Some(d) = self.rtx.sack(l, r) {
+                            self.rack.on_delivered(d, now);
+                        }
+                    }
+                }
+            }
+        }
+        let flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        let mut acked = 0;
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            acked = ack.wrapping_sub(self.snd_una) as usize;
+            self.snd_una = ack;
+            if let Some(d) = self.rtx.ack(ack) {
+                self.rack.on_delivered(d, now);
+                if !d.retrans {
+                    self.on_rtt_sample(now.saturating_duration_since(d.sent));
+                }
+            }
+            self.dupacks = 0;
+            self.retries = 0;
+            self.timers.rto = (!self.rtx.is_empty()).then(|| now + self.rto);
+            self.unacked_since = (!self.rtx.is_empty()).then_some(now);
+            if self.snd_up.is_some_and(|up| seq_le(up, self.snd_una)) {
+                self.snd_up = None;
+            }
+            self.arm_user_timeout();
+            if let Some(mss) = self.plpmtud.as_mut().and_then(|p| p.on_ack(ack)) {
+                self.mss = self.mss.max(mss);
+            }
+            let degree = self.rtx.reorder_bytes() / self.mss;
+            self.reordering = self.reordering.max(degree.min(MAX_REORDERING));
+        } else if ack == self.snd_una
+            && flight > 0
+            && tv.payload.is_empty()
+            && (flags & (FLAG_SYN | FLAG_FIN)) == 0
+            && tv.window as u32 == self.snd_wnd
+        {
+            // RFC 5681 §2 duplicate ACK
+            self.dupacks += 1;
+        }
+        self.snd_wnd = tv.window as u32;
+        if let Some(cwv) = &mut self.cwv {
+            cwv.on_ack(acked, self.srtt, now);
+        }
+        // DeliveredData = change in snd_una + change in SACKed bytes; without
+        // SACK each duplicate ACK stands for one delivered segment.
+        let mut delivered = (acked + self.rtx.sacked_bytes()).saturating_sub(sacked_before);
+        if !self.sack_ok && acked == 0 && self.dupacks > 0 {
+            delivered = self.mss;
+        }
+
+        if self.ecn.enabled {
+            let ece = (flags & FLAG_ECE) != 0;
+            self.cc.on_ecn_ack(acked, ece, self.snd_una, self.snd_nxt);
+        }
+        // Sender side: one reduction per window of data (RFC 3168 §6.1.2).
+        if self.ecn.enabled
+            && (flags & FLAG_ECE) != 0
+            && self.prr.is_none()
+            && seq_lt(self.ecn.recover, self.snd_una)
+        {
+            let flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            self.cc.on_ecn_echo(flight, self.mss);
+            self.stats.ecn_reductions += 1;
+            self.ecn.recover = self.snd_nxt;
+            self.ecn.cwr = true;
+        }
+
+        if let Some(end) = self.tlp.end_seq
+            && seq_le(end, self.snd_una)
+        {
+            // Without DSACK we cannot tell whether the original or the probe
+            // got through, so treat a retransmitted probe as a repaired loss.
+            if self.tlp.is_retrans && self.prr.is_none() {
+                let flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                self.cc.on_loss(flight, self.mss);
+                self.cc.set_cwnd(self.cc.ssthresh());
+                self.stats.tlp_recoveries += 1;
+            }
+            self.tlp = Tlp::default();
+        }
+
+        if self
+            .prr
+            .as_ref()
+            .is_some_and(|p| seq_le(p.episode.recover, self.snd_una))
+        {
+            self.exit_recovery();
+        } else if self.prr.is_some() {
+            if self.sack_ok {
+                self.detect_loss(now);
+            } else if acked > 0 {
+                // NewReno partial ACK: the next hole is lost too.
+                self.rtx.mark_head_lost();
+            }
+            let pipe = self.pipe();
+            let mss = self.mss;
+            if let Some(prr) = &mut self.prr {
+                prr.on_ack(delivered, pipe, mss);
+            }
+        } else {
+            let cwnd = self.cc.cwnd();
+            if acked > 0 && self.cwv.as_ref().is_none_or(|c| c.may_grow(cwnd)) {
+                self.cc.on_ack(acked, self.mss);
+            }
+            if let Some(cwv) = &mut self.cwv {
+                cwv.check_nvp(&mut *self.cc, self.mss, now);
+            }
+            let lost = self.detect_loss(now);
+            // RACK replaces the duplicate ACK threshold when it is active.
+            let dup_loss = !self.rack_active() && self.dupacks >= self.reordering;
+            if lost || dup_loss {
+                self.enter_recovery(delivered);
+            }
+        }
+        self.arm_tlp(now);
+    }
+
+    fn pipe(&self) -> usize {
+        let pipe = self.rtx.pipe();
+        if self.sack_ok {
+            pipe
+        } else {
+            pipe.saturating_sub(self.dupacks * self.mss)
+        }
+    }
+
+    fn enter_recovery(&mut self, delivered: usize) {
+        let flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        let loss_flight = self
+            .cwv
+            .as_ref()
+            .map_or(flight, |c| c.loss_flight(flight, self.cc.cwnd()));
+        self.cc.on_loss(loss_flight, self.mss);
+        if !self.sack_ok {
+            self.rtx.mark_head_lost();
+        }
+        // No separate ECN reaction for the same window.
+        self.ecn.recover = self.snd_nxt;
+        self.stats.fast_recoveries += 1;
+        let mut prr = Prr::start(self.snd_nxt, flight, self.cc.ssthresh(), self.sack_ok);
+        prr.on_ack(delivered, self.pipe(), self.mss);
+        self.prr = Some(prr);
+    }
+
+    fn exit_recovery(&mut self) {
+        if let Some(prr) = self.prr.take() {
+            let (episode, cwnd) = prr.finish();
+            self.cc.set_cwnd(cwnd);
+            self.record_episode(episode);
+        }
+    }
+
+    fn record_episode(&mut self, episode: RecoveryEpisode) {
+        if self.stats.recoveries.len() == RECOVERY_HISTORY {
+            self.stats.recoveries.pop_front();
+        }
+        self.stats.recoveries.push_back(episode);
+    }
+
+    /// Counters snapshot, including the recovery episode in progress.
+    pub fn stats(&self) -> ConnStats {
+        let mut stats = self.stats.clone();
+        if let Some(prr) = &self.prr {
+            if stats.recoveries.len() == RECOVERY_HISTORY {
+                stats.recoveries.pop_front();
+            }
+            stats.recoveries.push_back(prr.episode.clone());
+        }
+        stats
+    }
+
+    /// Build the header for an outgoing segment, folding in ECN signalling.
+    pub fn segment(&mut self, seq: u32, mut flags: u16, payload: Vec<u8>) -> Segment {
+        let mut ecn = ECN_NOT_ECT;
+        if (flags & FLAG_SYN) != 0 {
+            if self.ecn.enabled {
+                // ECN-setup SYN is ECE|CWR, the SYN-ACK only ECE
+                flags |= if (flags & FLAG_ACK) != 0 {
+                    FLAG_ECE
+                } else {
+                    FLAG_ECE | FLAG_CWR
+                };
+            }
+        } else if self.ecn.enabled {
+            if self.ecn.echo && (flags & FLAG_ACK) != 0 {
+                flags |= FLAG_ECE;
+            }
+            if !payload.is_empty() {
+                ecn = ECN_ECT0;
+                if self.ecn.cwr {
+                    flags |= FLAG_CWR;
+                    self.ecn.cwr = false;
+                }
+            }
+        }
+        if (flags & FLAG_ACK) != 0 {
+            self.last_ack_sent = self.rcv_nxt;
+        }
+        let mut hdr = TcpHeader {
+            src_port: self.id.src_port,
+            dst_port: self.id.dst_port,
+            seq,
+            ack: if (flags & FLAG_ACK) != 0 {
+                self.rcv_nxt
+            } else {
+                0
+            },
+            data_offset: 5,
+            flags,
+            window: RCV_WND as u16,
+            urg_ptr: 0,
+            options: BytesMut::new(),
+        };
+        if let Some(up) = self.snd_up
+            && (flags & (FLAG_ACK | FLAG_SYN | FLAG_RST)) == FLAG_ACK
+            && seq_lt(seq, up)
+        {
+            hdr.flags |= FLAG_URG;
+            hdr.urg_ptr = up.wrapping_sub(seq).min(u16::MAX as u32) as u16;
+        }
+        let mut opts = Vec::new();
+        let sack_raw;
+        if (flags & FLAG_SYN) != 0 {
+            if self.sack_ok {
+                opts.push(TcpOption::SackPermitted);
+            }
+            if (flags & FLAG_ACK) != 0 {
+                if let Some(cookie) = &self.fast_open.cookie {
+                    opts.push(TcpOption::FastOpen(cookie));
+                }
+            } else if self.fast_open.request {
+                opts.push(TcpOption::FastOpen(
+                    self.fast_open.cookie.as_deref().unwrap_or_default(),
+                ));
+            }
+        } else if self.sack_ok && (flags & FLAG_ACK) != 0 && !self.ooo.is_empty() {
+            sack_raw = tcp::encode_sack_blocks(&self.sack_blocks());
+            opts.push(TcpOption::Sack(&sack_raw));
+        }
+        if let Some(eno) = self
+            .crypt
+            .as_ref()
+            .and_then(|c| c.eno_option(flags & FLAG_SYN != 0))
+            && (flags & FLAG_RST) == 0
+        {
+            opts.push(TcpOption::Eno(eno));
+        }
+        if let Some(ts) = &self.ts
+            && (flags & FLAG_RST) == 0
+        {
+            opts.push(TcpOption::Timestamps {
+                val: ts.val(self.clock.now()),
+                ecr: ts.recent,
+            });
+        }
+        if self.uto_pending
+            && (flags & FLAG_RST) == 0
+            && let Some(uto) = self.user_timeout
+        {
+            opts.push(tcp::user_timeout_option(uto));
+            // Until the handshake completes the SYN may be lost; keep it on.
+            self.uto_pending = !matches!(self.state, State::Established);
+        }
+        if !opts.is_empty() {
+            hdr.set_options(&opts);
+        }
+        self.stats.segs_out += 1;
+        self.stats.bytes_out += payload.len() as u64;
+        Segment { hdr, ecn, payload }
+    }
+
+    /// Queue application data for transmission; none is taken after a
+    /// shutdown for writing.
+    pub fn queue_send(&mut self, data: &[u8]) {
+        if self.fin_queued {
+            return;
+        }
+        match &mut self.crypt {
+            Some(crypt) => {
+                let frames = crypt.seal(data);
+                self.snd_buf.extend_from_slice(&frames);
+            }
+            None => self.snd_buf.extend_from_slice(data),
+        }
+    }
+
+    /// tcpcrypt was not negotiated: send what was held back for the keys
+    /// in the clear.
+    fn fall_back_to_plain(&mut self) {
+        if let Some(crypt) = self.crypt.take() {
+            self.snd_buf.extend_from_slice(&crypt.into_pending());
+        }
+    }
+
+    /// Queue data whose last byte is urgent. An encrypted stream has no
+    /// urgent pointer to offer, so there it is sent as ordinary data.
+    pub fn queue_urgent(&mut self, data: &[u8]) {
+        self.queue_send(data);
+        if !data.is_empty() && !self.fin_queued && self.crypt.is_none() {
+            self.snd_up = Some(self.snd_nxt.wrapping_add(self.snd_buf.len() as u32));
+        }
+    }
+
+    /// Next segment to transmit: a lost segment if PRR or cwnd allow it,
+    /// else new data from the send buffer if cwnd and the peer's window do.
+    pub fn poll_send(&mut self) -> Result<Option<Segment>> {
+        while let Ok(buf) = self.app_tx.try_recv() {
+            self.queue_send(&buf);
+        }
+        // A Fast Open server may answer before the handshake completes.
+        // After our FIN only retransmissions remain.
+        let synced = match self.state {
+            State::Established
+            | State::CloseWait
+            | State::FinWait1
+            | State::Closing
+            | State::LastAck => true,
+            State::SynReceived => self.fast_open.accepted,
+            _ => false,
+        };
+        if !synced {
+            return Ok(None);
+        }
+        let now = self.clock.now();
+        self.check_probe();
+        let pipe = self.pipe();
+        let cwnd = self.cc.cwnd();
+        let paced = !self.pacer.as_ref().is_none_or(|p| p.ready(now));
+        if let Some(seg) = self.rtx.next_lost() {
+            let len = seg.data.len();
+            let allowed = match &self.prr {
+                Some(prr) => prr.allows(len, true),
+                None => pipe + len <= cwnd,
+            };
+            if !allowed {
+                return Ok(None);
+            }
+            if paced {
+                self.timers.pace = self.pacer.as_ref().and_then(|p| p.next_send);
+                return Ok(None);
+            }
+            seg.retrans = true;
+            seg.retransmits += 1;
+            seg.sent = now;
+            let (seq, data, fin) = (seg.seq, seg.data.clone(), seg.fin);
+            if let Some(prr) = &mut self.prr {
+                prr.on_send(len, true);
+            }
+            self.stats.bytes_retrans += len as u64;
+            self.timers.rto.get_or_insert(now + self.rto);
+            self.on_paced_send(len, now);
+            if let Some(cwv) = &mut self.cwv {
+                cwv.on_send(now);
+            }
+            let mut out = self.segment(seq, FLAG_ACK | fin_flag(fin), data);
+            // RFC 3168 §6.1.5: retransmissions are not ECN-capable.
+            out.ecn = ECN_NOT_ECT;
+            return Ok(Some(out));
+        }
+        if self.snd_buf.is_empty() {
+            // A FIN takes no room in the window.
+            return Ok(self.fin_due().then(|| self.send_new(0, now)));
+        }
+        // Our initial window is a single segment.
+        if self.rtx.is_empty()
+            && let Some(cwv) = &mut self.cwv
+            && cwv.restart_after_idle(&mut *self.cc, self.mss, self.rto, now)
+        {
+            self.stats.idle_restarts += 1;
+        }
+        let cwnd = self.cc.cwnd();
+        let flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        let send_mss = self.send_mss();
+        if self.prr.is_none()
+            && flight + send_mss.min(self.snd_buf.len()) >= cwnd
+            && let Some(cwv) = &mut self.cwv
+        {
+            cwv.on_cwnd_limited();
+        }
+        let wnd = match &self.prr {
+            Some(_) => self.snd_wnd as usize,
+            None => cwnd.min(self.snd_wnd as usize),
+        };
+        let probe = self.probe_len(wnd, flight, now);
+        let n = match probe {
+            Some((_, len)) => len,
+            None => wnd
+                .saturating_sub(flight)
+                .min(send_mss)
+                .min(self.snd_buf.len()),
+        };
+        if n == 0 || self.prr.as_ref().is_some_and(|p| !p.allows(n, false)) {
+            return Ok(None);
+        }
+        if paced {
+            self.timers.pace = self.pacer.as_ref().and_then(|p| p.next_send);
+            return Ok(None);
+        }
+        self.on_paced_send(n, now);
+        if let Some(prr) = &mut self.prr {
+            prr.on_send(n, false);
+        }
+        let seg = self.send_new(n, now);
+        if let Some((mss, _)) = probe
+            && let Some(p) = &mut self.plpmtud
+        {
+            p.probe = Some(Probe {
+                seq: seg.hdr.seq,
+                end: self.snd_nxt,
+                mss,
+            });
+            self.stats.mtu_probes += 1;
+        }
+        self.arm_tlp(now);
+        Ok(Some(seg))
+    }
+
+    fn on_paced_send(&mut self, bytes: usize, now: Instant) {
+        let rate = self.cc.pacing_rate(self.srtt);
+        if let Some(pacer) = &mut self.pacer {
+            pacer.on_send(bytes, rate, now);
+        }
+    }
+
+    /// Payload per segment: the MSS less the options every segment carries.
+    fn send_mss(&self) -> usize {
+        let ts_len = if self.ts.is_some() { TS_OPTION_LEN } else { 0 };
+        self.mss
+            .saturating_sub(ts_len + self.auth_option_len() + self.eno_option_len())
+            .max(1)
+    }
+
+    /// Room for the ENO option host A repeats until the key exchange is done.
+    fn eno_option_len(&self) -> usize {
+        self.crypt
+            .as_ref()
+            .and_then(|c| c.eno_option(false))
+            .map_or(0, |e| (2 + e.len()).next_multiple_of(4))
+    }
+
+    fn auth_option_len(&self) -> usize {
+        self.auth.as_ref().map_or(0, AuthState::option_len)
+    }
+
+    /// Whether the FIN is next: queued, not yet sent, the send buffer
+    /// drained and the handshake complete.
+    fn fin_due(&self) -> bool {
+        self.fin_queued
+            && self.fin_seq.is_none()
+            && self.snd_buf.is_empty()
+            && self.crypt.as_ref().is_none_or(Tcpcrypt::established)
+            && matches!(self.state, State::Established | State::CloseWait)
+    }
+
+    /// Cut `n` bytes of new data off the send buffer, with our FIN if that
+    /// drains it after a shutdown.
+    fn send_new(&mut self, n: usize, now: Instant) -> Segment {
+        let payload = self.snd_buf.split_to(n).to_vec();
+        let seq = self.snd_nxt;
+        let fin = self.fin_due();
+        if fin {
+            self.fin_seq = Some(seq.wrapping_add(n as u32));
+            self.state = match self.state {
+                State::CloseWait => State::LastAck,
+                _ => State::FinWait1,
+            };
+        }
+        self.snd_nxt = self.snd_nxt.wrapping_add(n as u32 + fin as u32);
+        if self.unacked_since.is_none() {
+            self.unacked_since = Some(now);
+            self.arm_user_timeout();
+        }
+        self.rtx.push(seq, payload.clone(), fin, now);
+        self.timers.rto.get_or_insert(now + self.rto);
+        if let Some(cwv) = &mut self.cwv {
+            cwv.on_send(now);
         }
-        Ok(None)
+        self.segment(seq, FLAG_ACK | FLAG_PSH | fin_flag(fin), payload)
     }
 }
 
 /// Commands from sockets to the stack’s TCP engine.
 pub enum TcpCmd {
-    Connect(Quad, oneshot::Sender<Result<()>>),
-    Listen(u16, oneshot::Sender<Result<()>>),
+    /// Answered with where the stream sends its close when dropped.
+    Connect(
+        Quad,
+        ConnectOptions,
+        mpsc::Sender<Result<Incoming>>,
+        oneshot::Sender<Result<mpsc::UnboundedSender<TcpCmd>>>,
+    ),
+    Listen(
+        u16,
+        ListenOptions,
+        mpsc::Sender<TcpStream>,
+        oneshot::Sender<Result<()>>,
+    ),
     Send(Quad, Vec<u8>),
-    Close(Quad),
+    /// Data whose last byte is urgent.
+    SendUrgent(Quad, Vec<u8>),
+    Shutdown(Quad, Shutdown),
+    /// Shut down both directions, lingering on the sender if given for at
+    /// most the duration.
+    Close(Quad, Option<(Duration, oneshot::Sender<Result<()>>)>),
+    /// Reset the connection, discarding queued data.
+    Abort(Quad),
+    Stats(Quad, oneshot::Sender<Option<ConnStats>>),
+    SessionId(Quad, oneshot::Sender<Result<Option<Vec<u8>>>>),
+    ListenerStats(u16, oneshot::Sender<Option<ListenerStats>>),
+    SetOption(Quad, SockOpt),
+    Metrics(oneshot::Sender<Vec<DestMetrics>>),
+    /// Forget one destination's cached metrics, or all of them.
+    FlushMetrics(Option<IpAddr>),
+    /// Send an echo request; answered with the round trip time.
+    Ping(Ipv4Addr, PingOptions, oneshot::Sender<Result<Duration>>),
 }
@@ -0,0 +1,366 @@
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::error::*;
+
+/// TCP-ENO encryption spec identifier for tcpcrypt over Curve25519
+/// (TCPCRYPT_ECDHE_Curve25519, RFC 8548 §7).
+pub const TEP_TCPCRYPT_X25519: u8 = 0x23;
+/// AEAD_AES_128_GCM in tcpcrypt's cipher list.
+const CIPHER_AES_128_GCM: u8 = 0x01;
+
+const INIT1_MAGIC: u32 = 0x1510_1a0e;
+const INIT2_MAGIC: u32 = 0x0971_05e0;
+const NONCE_LEN: usize = 32;
+const KEY_LEN: usize = 32;
+/// Bound on Init messages, well above what either takes.
+const MAX_INIT_LEN: usize = 1024;
+
+/// CPRF labels (RFC 8548 §4.3).
+const CONST_NEXTK: u8 = 0x01;
+const CONST_SESSID: u8 = 0x02;
+const CONST_KEY_A: u8 = 0x04;
+const CONST_KEY_B: u8 = 0x05;
+
+const TAG_LEN: usize = 16;
+/// Plaintext per frame: the 16-bit length covers the control byte, the
+/// data and the tag.
+const MAX_FRAME_DATA: usize = u16::MAX as usize - 1 - TAG_LEN;
+
+/// Host A opened actively and starts the key exchange; host B answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    A,
+    B,
+}
+
+/// Keys for one direction. Each frame's nonce is its offset in that
+/// direction's stream of frames.
+struct Direction {
+    cipher: Aes128Gcm,
+    offset: u64,
+}
+
+impl Direction {
+    fn new(key: &[u8]) -> Self {
+        Self {
+            cipher: Aes128Gcm::new_from_slice(key).expect("AES-128 key"),
+            offset: 0,
+        }
+    }
+
+    fn nonce(&self) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&self.offset.to_be_bytes());
+        nonce
+    }
+}
+
+/// Opportunistic encryption of the byte stream with tcpcrypt (RFC 8548),
+/// negotiated with TCP-ENO (RFC 8547). Until the key exchange completes,
+/// data to send is held back; if negotiation fails the connection falls
+/// back to plain TCP with it.
+pub struct Tcpcrypt {
+    role: Role,
+    /// The ENO options of the SYN and SYN-ACK, bound into the keys.
+    transcript: Vec<u8>,
+    /// A: the exchange started; its Init1 went out.
+    init1: Option<Vec<u8>>,
+    secret: Option<StaticSecret>,
+    nonce: [u8; NONCE_LEN],
+    /// Plaintext queued before the keys were ready.
+    pending: Vec<u8>,
+    /// Received bytes not yet making a whole message or frame.
+    rx_buf: Vec<u8>,
+    tx: Option<Direction>,
+    rx: Option<Direction>,
+    session_id: Option<Vec<u8>>,
+}
+
+/// What received stream bytes yield.
+#[derive(Default)]
+pub struct Opened {
+    /// For the application.
+    pub plaintext: Vec<u8>,
+    /// To send: our Init2 and data held back for the keys.
+    pub reply: Vec<u8>,
+}
+
+impl Tcpcrypt {
+    fn new(role: Role) -> Self {
+        Self {
+            role,
+            transcript: Vec::new(),
+            init1: None,
+            secret: None,
+            nonce: random(),
+            pending: Vec::new(),
+            rx_buf: Vec::new(),
+            tx: None,
+            rx: None,
+            session_id: None,
+        }
+    }
+
+    /// Host A, offering tcpcrypt in its SYN.
+    pub fn active() -> Self {
+        let mut crypt = Self::new(Role::A);
+        push_eno(&mut crypt.transcript, &[TEP_TCPCRYPT_X25519]);
+        crypt
+    }
+
+    /// Host B, for a SYN whose ENO option carried `syn_eno`; `None` if it
+    /// offers nothing we support.
+    pub fn passive(syn_eno: &[u8]) -> Option<Self> {
+        if !offers(syn_eno) {
+            return None;
+        }
+        let mut crypt = Self::new(Role::B);
+        push_eno(&mut crypt.transcript, syn_eno);
+        push_eno(&mut crypt.transcript, &[TEP_TCPCRYPT_X25519]);
+        Some(crypt)
+    }
+
+    /// Suboptions of the ENO option a segment with `syn`/`ack` set carries:
+    /// our TEP in the SYN or SYN-ACK, and an empty option in A's segments
+    /// until the exchange completes, the first of which confirms it to B.
+    pub fn eno_option(&self, syn: bool) -> Option<&'static [u8]> {
+        if syn {
+            Some(&[TEP_TCPCRYPT_X25519])
+        } else if self.role == Role::A && self.tx.is_none() {
+            Some(&[])
+        } else {
+            None
+        }
+    }
+
+    /// A: the SYN-ACK carried `eno`. Returns the Init1 to start the stream
+    /// with, or `None` if B did not take tcpcrypt.
+    pub fn on_syn_ack(&mut self, eno: Option<&[u8]>) -> Option<Vec<u8>> {
+        let eno = eno.filter(|e| *e == [TEP_TCPCRYPT_X25519])?;
+        push_eno(&mut self.transcript, eno);
+        let secret = StaticSecret::from(random::<KEY_LEN>());
+        let mut init1 = Vec::new();
+        init1.extend_from_slice(&INIT1_MAGIC.to_be_bytes());
+        let len = 4 + 4 + 1 + 1 + NONCE_LEN + KEY_LEN;
+        init1.extend_from_slice(&(len as u32).to_be_bytes());
+        init1.push(1);
+        init1.push(CIPHER_AES_128_GCM);
+        init1.extend_from_slice(&self.nonce);
+        init1.extend_from_slice(PublicKey::from(&secret).as_bytes());
+        self.secret = Some(secret);
+        self.init1 = Some(init1.clone());
+        Some(init1)
+    }
+
+    /// Whether the key exchange has completed.
+    pub fn established(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Whether data is held back for the keys.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Plaintext held back, for a connection falling back to plain TCP.
+    pub fn into_pending(self) -> Vec<u8> {
+        self.pending
+    }
+
+    /// `TEP || CPRF(ss[0], CONST_SESSID)`, once the keys are set up; for
+    /// applications to bind authentication to (RFC 8548 §4.5).
+    pub fn session_id(&self) -> Option<&[u8]> {
+        self.session_id.as_deref()
+    }
+
+    /// Frames carrying `data`, or nothing while the keys are not ready.
+    pub fn seal(&mut self, data: &[u8]) -> Vec<u8> {
+        let Some(tx) = &mut self.tx else {
+            self.pending.extend_from_slice(data);
+            return Vec::new();
+        };
+        let mut out = Vec::with_capacity(data.len() + 32);
+        for chunk in data.chunks(MAX_FRAME_DATA) {
+            let mut plaintext = Vec::with_capacity(1 + chunk.len());
+            plaintext.push(0); // control: no rekey
+            plaintext.extend_from_slice(chunk);
+            let len = ((plaintext.len() + TAG_LEN) as u16).to_be_bytes();
+            let nonce = tx.nonce();
+            let sealed = tx
+                .cipher
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: &plaintext,
+                        aad: &len,
+                    },
+                )
+                .expect("AES-GCM seals any frame");
+            out.extend_from_slice(&len);
+            out.extend_from_slice(&sealed);
+            tx.offset += (2 + sealed.len()) as u64;
+        }
+        out
+    }
+
+    /// Take received stream bytes: the peer's Init message, then frames.
+    /// An error means the stream was tampered with or is not tcpcrypt.
+    pub fn open(&mut self, data: &[u8]) -> Result<Opened> {
+        self.rx_buf.extend_from_slice(data);
+        let mut opened = Opened::default();
+        if self.rx.is_none() {
+            let Some(msg) = self.take_init()? else {
+                return Ok(opened);
+            };
+            opened.reply = self.on_init(&msg)?;
+        }
+        let Some(rx) = &mut self.rx else {
+            return Ok(opened);
+        };
+        while self.rx_buf.len() >= 2 {
+            let len = u16::from_be_bytes([self.rx_buf[0], self.rx_buf[1]]) as usize;
+            if len < 1 + TAG_LEN {
+                return Err(UrtcpError::DecryptFailed);
+            }
+            if self.rx_buf.len() < 2 + len {
+                break;
+            }
+            let frame: Vec<u8> = self.rx_buf.drain(..2 + len).collect();
+            let nonce = rx.nonce();
+            let plaintext = rx
+                .cipher
+                .decrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: &frame[2..],
+                        aad: &frame[..2],
+                    },
+                )
+                .map_err(|_| UrtcpError::DecryptFailed)?;
+            rx.offset += frame.len() as u64;
+            opened.plaintext.extend_from_slice(&plaintext[1..]);
+        }
+        Ok(opened)
+    }
+
+    /// The peer's whole Init message, once it has arrived.
+    fn take_init(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.rx_buf.len() < 8 {
+            return Ok(None);
+        }
+        let magic = u32::from_be_bytes(self.rx_buf[..4].try_into().unwrap());
+        let expected = match self.role {
+            Role::A => INIT2_MAGIC,
+            Role::B => INIT1_MAGIC,
+        };
+        let len = u32::from_be_bytes(self.rx_buf[4..8].try_into().unwrap()) as usize;
+        if magic != expected || !(8..=MAX_INIT_LEN).contains(&len) {
+            return Err(UrtcpError::DecryptFailed);
+        }
+        if self.rx_buf.len() < len {
+            return Ok(None);
+        }
+        Ok(Some(self.rx_buf.drain(..len).collect()))
+    }
+
+    /// Complete the key exchange with the peer's Init message; B answers
+    /// with Init2. Data held back goes out right behind.
+    fn on_init(&mut self, msg: &[u8]) -> Result<Vec<u8>> {
+        let body = &msg[8..];
+        let mut reply = Vec::new();
+        let (init1, init2, n_a, peer) = match self.role {
+            Role::B => {
+                let (&n, rest) = body.split_first().ok_or(UrtcpError::DecryptFailed)?;
+                let n = n as usize;
+                if rest.len() != n + NONCE_LEN + KEY_LEN || !rest[..n].contains(&CIPHER_AES_128_GCM)
+                {
+                    return Err(UrtcpError::DecryptFailed);
+                }
+                let n_a: [u8; NONCE_LEN] = rest[n..n + NONCE_LEN].try_into().unwrap();
+                let peer: [u8; KEY_LEN] = rest[n + NONCE_LEN..].try_into().unwrap();
+                let secret = StaticSecret::from(random::<KEY_LEN>());
+                reply.extend_from_slice(&INIT2_MAGIC.to_be_bytes());
+                let len = 4 + 4 + 1 + NONCE_LEN + KEY_LEN;
+                reply.extend_from_slice(&(len as u32).to_be_bytes());
+                reply.push(CIPHER_AES_128_GCM);
+                reply.extend_from_slice(&self.nonce);
+                reply.extend_from_slice(PublicKey::from(&secret).as_bytes());
+                self.secret = Some(secret);
+                (msg.to_vec(), reply.clone(), n_a, peer)
+            }
+            Role::A => {
+                if body.len() != 1 + NONCE_LEN + KEY_LEN || body[0] != CIPHER_AES_128_GCM {
+                    return Err(UrtcpError::DecryptFailed);
+                }
+                let peer: [u8; KEY_LEN] = body[1 + NONCE_LEN..].try_into().unwrap();
+                let init1 = self.init1.clone().unwrap_or_default();
+                (init1, msg.to_vec(), self.nonce, peer)
+            }
+        };
+        let secret = self.secret.take().ok_or(UrtcpError::DecryptFailed)?;
+        let shared = secret.diffie_hellman(&PublicKey::from(peer));
+        // PRK = Extract(N_A, eno_transcript | init1 | init2 | ES)
+        let mut ikm = self.transcript.clone();
+        ikm.extend_from_slice(&init1);
+        ikm.extend_from_slice(&init2);
+        ikm.extend_from_slice(shared.as_bytes());
+        let prk = Hkdf::<Sha256>::new(Some(&n_a), &ikm);
+        let ss0 = Hkdf::<Sha256>::from_prk(&cprf::<KEY_LEN>(&prk, CONST_NEXTK))
+            .expect("PRK is a SHA-256 output");
+        let k_ab = cprf::<16>(&ss0, CONST_KEY_A);
+        let k_ba = cprf::<16>(&ss0, CONST_KEY_B);
+        let (tx, rx) = match self.role {
+            Role::A => (k_ab, k_ba),
+            Role::B => (k_ba, k_ab),
+        };
+        self.tx = Some(Direction::new(&tx));
+        self.rx = Some(Direction::new(&rx));
+        let mut sid = vec![TEP_TCPCRYPT_X25519];
+        sid.extend_from_slice(&cprf::<KEY_LEN>(&ss0, CONST_SESSID));
+        self.session_id = Some(sid);
+        let pending = std::mem::take(&mut self.pending);
+        reply.extend(self.seal(&pending));
+        Ok(reply)
+    }
+}
+
+/// Whether ENO suboptions from a SYN offer our TEP. Global suboptions
+/// (below 0x20) are skipped, as is data following a `v` suboption.
+fn offers(eno: &[u8]) -> bool {
+    let mut i = 0;
+    while let Some(&sub) = eno.get(i) {
+        if sub & 0x7f == TEP_TCPCRYPT_X25519 {
+            return true;
+        }
+        i += 1;
+        if sub & 0x80 != 0 {
+            // A length byte, then that much data.
+            i += 1 + eno.get(i).map_or(0, |&n| n as usize);
+        }
+    }
+    false
+}
+
+/// Append an ENO option's suboptions to the transcript, length first.
+fn push_eno(transcript: &mut Vec<u8>, eno: &[u8]) {
+    transcript.push(eno.len() as u8);
+    transcript.extend_from_slice(eno);
+}
+
+/// CPRF(K, CONST, L): HKDF-Expand with the label as info.
+fn cprf<const N: usize>(key: &Hkdf<Sha256>, label: u8) -> [u8; N] {
+    let mut out = [0; N];
+    key.expand(&[label], &mut out)
+        .expect("output within HKDF's limit");
+    out
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut buf = [0; N];
+    getrandom::getrandom(&mut buf).expect("system random number generator");
+    buf
+}
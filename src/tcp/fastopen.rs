@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::auth::{ct_eq, octets};
+
+/// Server cookie length; RFC 7413 allows 4 to 16 bytes.
+pub const TFO_COOKIE_LEN: usize = 8;
+/// How long Fast Open stays off towards a server whose path dropped SYN data.
+pub const TFO_BLACKHOLE_BACKOFF: Duration = Duration::from_secs(3600);
+/// Bound on servers remembered by the client cookie cache.
+const TFO_MAX_SERVERS: usize = 1024;
+
+/// Per-connection TCP Fast Open (RFC 7413) state.
+#[derive(Debug, Default)]
+pub struct FastOpen {
+    /// Client: use Fast Open, asking for a cookie if none is cached.
+    pub request: bool,
+    /// Client: cookie to present in the SYN. Server: cookie to hand out in
+    /// the SYN-ACK.
+    pub cookie: Option<Vec<u8>>,
+    /// Client: bytes of data the SYN carried.
+    pub syn_data: usize,
+    /// Client: cookie the server handed out, for the stack to cache.
+    pub new_cookie: Option<Vec<u8>>,
+    /// Client: the SYN carrying data went unanswered.
+    pub syn_data_lost: bool,
+    /// Server: data in the SYN was accepted, so we may reply before the
+    /// handshake completes.
+    pub accepted: bool,
+}
+
+/// A random key for keyed hashes.
+pub fn random_key() -> [u8; 16] {
+    let mut key = [0; 16];
+    getrandom::getrandom(&mut key).expect("system random number generator");
+    key
+}
+
+/// HMAC-SHA256 under `key` of `parts` in turn, truncated to `N` bytes.
+pub fn keyed_hash<const N: usize>(key: &[u8], parts: &[&[u8]]) -> [u8; N] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    let mut out = [0; N];
+    out.copy_from_slice(&mac.finalize().into_bytes()[..N]);
+    out
+}
+
+#[derive(Debug, Default)]
+struct ServerEntry {
+    cookie: Option<Vec<u8>>,
+    disabled_until: Option<Instant>,
+}
+
+/// Stack-wide Fast Open state: the key server cookies are derived from and
+/// the client's cookie cache, keyed by server address.
+#[derive(Debug)]
+pub struct FastOpenCache {
+    key: [u8; 16],
+    servers: HashMap<IpAddr, ServerEntry>,
+}
+
+impl FastOpenCache {
+    /// `None` picks a random key.
+    pub fn new(key: Option<[u8; 16]>) -> Self {
+        Self {
+            key: key.unwrap_or_else(random_key),
+            servers: HashMap::new(),
+        }
+    }
+
+    /// Server cookie for `client`: a truncated HMAC-SHA256 of its address.
+    pub fn cookie(&self, client: IpAddr) -> [u8; TFO_COOKIE_LEN] {
+        keyed_hash(&self.key, &[&octets(client)])
+    }
+
+    pub fn valid(&self, client: IpAddr, cookie: &[u8]) -> bool {
+        ct_eq(cookie, &self.cookie(client))
+    }
+
+    /// Client state for a new connection to `server`.
+    pub fn client(&self, server: IpAddr, now: Instant) -> FastOpen {
+        let entry = self.servers.get(&server);
+        if entry.is_some_and(|e| e.disabled_until.is_some_and(|t| now < t)) {
+            return FastOpen::default();
+        }
+        FastOpen {
+            request: true,
+            cookie: entry.and_then(|e| e.cookie.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn store(&mut self, server: IpAddr, cookie: Vec<u8>) {
+        if self.servers.len() >= TFO_MAX_SERVERS && !self.servers.contains_key(&server) {
+            return;
+        }
+        self.servers.entry(server).or_default().cookie = Some(cookie);
+    }
+
+    /// SYN data to `server` was lost, likely to a middlebox: fall back to
+    /// regular handshakes for a while (RFC 7413 §4.1.3.1).
+    pub fn disable(&mut self, server: IpAddr, now: Instant) {
+        if self.servers.len() >= TFO_MAX_SERVERS && !self.servers.contains_key(&server) {
+            return;
+        }
+        self.servers.entry(server).or_default().disabled_until = Some(now + TFO_BLACKHOLE_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 9));
+    const SERVER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn cookie_is_valid_only_for_its_client() {
+        let cache = FastOpenCache::new(Some([7; 16]));
+        let cookie = cache.cookie(CLIENT);
+        assert_eq!(cookie, keyed_hash(&[7; 16], &[&[10, 0, 0, 9]]));
+        assert!(cache.valid(CLIENT, &cookie));
+        assert!(!cache.valid("10.0.0.8".parse().unwrap(), &cookie));
+        assert!(!cache.valid(CLIENT, &cookie[..4]));
+        // A restart with another key invalidates what was handed out.
+        assert!(!FastOpenCache::new(Some([8; 16])).valid(CLIENT, &cookie));
+    }
+
+    #[test]
+    fn client_presents_the_cached_cookie() {
+        let now = Instant::now();
+        let mut cache = FastOpenCache::new(None);
+        let fo = cache.client(SERVER, now);
+        assert!(fo.request);
+        assert_eq!(fo.cookie, None);
+
+        cache.store(SERVER, vec![1; 8]);
+        assert_eq!(cache.client(SERVER, now).cookie, Some(vec![1; 8]));
+        assert_eq!(cache.client(CLIENT, now).cookie, None);
+    }
+
+    #[test]
+    fn lost_syn_data_disables_fast_open_for_a_while() {
+        let now = Instant::now();
+        let mut cache = FastOpenCache::new(None);
+        cache.store(SERVER, vec![1; 8]);
+        cache.disable(SERVER, now);
+        let fo = cache.client(SERVER, now + TFO_BLACKHOLE_BACKOFF - Duration::from_secs(1));
+        assert!(!fo.request);
+        assert_eq!(fo.cookie, None);
+
+        // The cookie outlives the backoff.
+        let fo = cache.client(SERVER, now + TFO_BLACKHOLE_BACKOFF);
+        assert!(fo.request);
+        assert_eq!(fo.cookie, Some(vec![1; 8]));
+    }
+}
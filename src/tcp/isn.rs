@@ -0,0 +1,28 @@
+use std::time::Instant;
+
+use super::conn::Quad;
+use super::fastopen::{keyed_hash, random_key};
+
+/// RFC 6528 initial sequence numbers: a clock ticking every four
+/// microseconds plus a truncated HMAC-SHA256 of the connection, so
+/// successive incarnations of a connection move forward while other
+/// connections' ISNs cannot be guessed.
+#[derive(Debug)]
+pub struct IsnGenerator {
+    key: [u8; 16],
+    epoch: Instant,
+}
+
+impl IsnGenerator {
+    pub fn new(epoch: Instant) -> Self {
+        Self {
+            key: random_key(),
+            epoch,
+        }
+    }
+
+    pub fn isn(&self, id: &Quad, now: Instant) -> u32 {
+        let m = (now.saturating_duration_since(self.epoch).as_micros() / 4) as u32;
+        m.wrapping_add(u32::from_be_bytes(keyed_hash(&self.key, &[&id.to_bytes()])))
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{mpsc, oneshot};
+
+use super::conn::{Connection, MAX_RTO, MIN_RTO, TcpCmd};
+use super::pmtu;
+use crate::error::*;
+
+/// Cached metrics older than this are ignored and eventually dropped.
+pub const METRICS_TTL: Duration = Duration::from_secs(3600);
+/// Bound on cached destinations; the stalest entry makes room.
+pub const METRICS_MAX_ENTRIES: usize = 1024;
+
+/// What past connections learned about one destination.
+#[derive(Debug, Clone)]
+pub struct DestMetrics {
+    pub dst: IpAddr,
+    pub srtt: Option<Duration>,
+    pub rttvar: Option<Duration>,
+    /// Set by a congestion response, not just the initial value.
+    pub ssthresh: Option<usize>,
+    /// Learned from ICMP, a black hole or a successful MTU probe.
+    pub pmtu: Option<usize>,
+    /// Reordering degree in segments, used as the duplicate ACK threshold.
+    pub reordering: usize,
+    pub updated: Instant,
+}
+
+/// Per-destination TCP metrics, fed by closing connections and used to
+/// initialize new ones.
+#[derive(Debug, Default)]
+pub struct MetricsCache {
+    entries: HashMap<IpAddr, DestMetrics>,
+}
+
+/// Move a cached RTT towards a new one: up at once, down by 1/8 of the
+/// difference, so one lucky connection does not shrink the estimate.
+fn blend(cached: Option<Duration>, new: Option<Duration>) -> Option<Duration> {
+    match (cached, new) {
+        (Some(c), Some(n)) if c > n => Some(c - (c - n) / 8),
+        (c, n) => n.or(c),
+    }
+}
+
+impl MetricsCache {
+    /// Fresh metrics for `dst`, if any.
+    pub fn get(&self, dst: IpAddr, now: Instant) -> Option<&DestMetrics> {
+        self.entries
+            .get(&dst)
+            .filter(|m| now.saturating_duration_since(m.updated) < METRICS_TTL)
+    }
+
+    /// Record what a closing connection learned about its peer.
+    pub fn update(&mut self, conn: &Connection, now: Instant) {
+        let dst = conn.id.dst_ip;
+        if !self.entries.contains_key(&dst) {
+            self.entries
+                .retain(|_, m| now.saturating_duration_since(m.updated) < METRICS_TTL);
+            if self.entries.len() >= METRICS_MAX_ENTRIES
+                && let Some(oldest) = self.entries.values().min_by_key(|m| m.updated)
+            {
+                let oldest = oldest.dst;
+                self.entries.remove(&oldest);
+            }
+        }
+        let fresh = self.get(dst, now).cloned();
+        let (srtt, rttvar) = match &fresh {
+            Some(m) => (
+                blend(m.srtt, conn.srtt),
+                blend(m.rttvar, conn.srtt.map(|_| conn.rttvar)),
+            ),
+            None => (conn.srtt, conn.srtt.map(|_| conn.rttvar)),
+        };
+        // Only what the connection found out itself replaces what is cached:
+        // an initial ssthresh or a negotiated MSS says nothing of the path.
+        let stats = &conn.stats;
+        let congested = stats.fast_recoveries + stats.rto_timeouts + stats.ecn_reductions > 0;
+        let probed = stats.mss_reductions > 0 || stats.mtu_probes > stats.mtu_probes_lost;
+        let ssthresh = if conn.srtt.is_some() && congested {
+            Some(conn.cc.ssthresh())
+        } else {
+            fresh.as_ref().and_then(|m| m.ssthresh)
+        };
+        let pmtu = if probed {
+            Some(pmtu::mtu_for(conn.mss, dst))
+        } else {
+            fresh.as_ref().and_then(|m| m.pmtu)
+        };
+        self.entries.insert(
+            dst,
+            DestMetrics {
+                dst,
+                srtt,
+                rttvar,
+                ssthresh,
+                pmtu,
+                reordering: conn.reordering,
+                updated: now,
+            },
+        );
+    }
+
+    /// Initialize a new connection from what is known about its peer. Only
+    /// the RTO is derived from the RTT; the first sample starts the estimator
+    /// afresh.
+    pub fn seed(&self, conn: &mut Connection, now: Instant) {
+        let Some(m) = self.get(conn.id.dst_ip, now) else {
+            return;
+        };
+        if let Some(srtt) = m.srtt {
+            let rttvar = m.rttvar.unwrap_or(srtt / 2);
+            conn.rto = (srtt + rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+        }
+        if let Some(ssthresh) = m.ssthresh {
+            conn.cc.set_ssthresh(ssthresh);
+        }
+        if let Some(pmtu) = m.pmtu {
+            conn.mss = conn.mss.min(pmtu::mss_for(pmtu, conn.id.dst_ip)).max(1);
+        }
+        conn.reordering = conn.reordering.max(m.reordering);
+    }
+
+    /// Fresh entries, for inspection.
+    pub fn snapshot(&self, now: Instant) -> Vec<DestMetrics> {
+        self.entries
+            .values()
+            .filter(|m| now.saturating_duration_since(m.updated) < METRICS_TTL)
+            .cloned()
+            .collect()
+    }
+
+    /// Forget `dst`, or everything when `None`.
+    pub fn flush(&mut self, dst: Option<IpAddr>) {
+        match dst {
+            Some(dst) => {
+                self.entries.remove(&dst);
+            }
+            None => self.entries.clear(),
+        }
+    }
+}
+
+/// Cached metrics of the stack behind `ctrl`.
+pub async fn snapshot(ctrl: &mpsc::Sender<TcpCmd>) -> Result<Vec<DestMetrics>> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    ctrl.send(TcpCmd::Metrics(reply_tx))
+        .await
+        .map_err(|_| UrtcpError::Device("control channel".into()))?;
+    reply_rx
+        .await
+        .map_err(|_| UrtcpError::Device("metrics drop".into()))
+}
+
+/// Drop cached metrics for `dst`, or for every destination when `None`.
+pub async fn flush(ctrl: &mpsc::Sender<TcpCmd>, dst: Option<IpAddr>) -> Result<()> {
+    ctrl.send(TcpCmd::FlushMetrics(dst))
+        .await
+        .map_err(|_| UrtcpError::Device("control channel".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::tcp::conn::{Quad, State};
+
+    fn conn(dst: IpAddr) -> Connection {
+        let (app_rx, _) = mpsc::channel(1);
+        let (_, app_tx) = mpsc::channel(1);
+        let id = Quad {
+            src_ip: match dst {
+                IpAddr::V4(_) => [10, 0, 0, 1].into(),
+                IpAddr::V6(_) => "fd00::1".parse().unwrap(),
+            },
+            src_port: 40000,
+            dst_ip: dst,
+            dst_port: 80,
+        };
+        Connection::new(id, State::Closed, app_rx, app_tx, Arc::new(SystemClock))
+    }
+
+    #[test]
+    fn unmeasured_fields_are_not_cached() {
+        let now = Instant::now();
+        let dst: IpAddr = [10, 0, 0, 2].into();
+        let mut cache = MetricsCache::default();
+        let mut c = conn(dst);
+        c.srtt = Some(Duration::from_millis(50));
+        cache.update(&c, now);
+        let m = cache.get(dst, now).unwrap();
+        assert_eq!(m.srtt, Some(Duration::from_millis(50)));
+        assert_eq!((m.ssthresh, m.pmtu), (None, None));
+
+        // A congestion response and a lowered MSS are kept...
+        c.cc.set_ssthresh(20_000);
+        c.stats.fast_recoveries = 1;
+        c.mss = 1000;
+        c.stats.mss_reductions = 1;
+        cache.update(&c, now);
+        let m = cache.get(dst, now).unwrap();
+        assert_eq!((m.ssthresh, m.pmtu), (Some(20_000), Some(1040)));
+
+        // ...and not undone by a connection that learned neither.
+        cache.update(&conn(dst), now);
+        let m = cache.get(dst, now).unwrap();
+        assert_eq!((m.ssthresh, m.pmtu), (Some(20_000), Some(1040)));
+    }
+
+    #[test]
+    fn pmtu_follows_the_address_family() {
+        let now = Instant::now();
+        let dst: IpAddr = "fd00::2".parse().unwrap();
+        let mut cache = MetricsCache::default();
+        let mut c = conn(dst);
+        c.mss = 1220;
+        c.stats.mss_reductions = 1;
+        cache.update(&c, now);
+        assert_eq!(cache.get(dst, now).unwrap().pmtu, Some(1280));
+
+        let mut fresh = conn(dst);
+        cache.seed(&mut fresh, now);
+        assert_eq!(fresh.mss, 1220);
+    }
+}
@@ -1,4 +1,16 @@
+pub mod auth;
 pub mod congestion;
 pub mod conn;
+pub mod crypt;
+pub mod fastopen;
+pub mod isn;
+pub mod metrics;
+pub mod pacing;
+pub mod pmtu;
+pub mod rack;
+pub mod recovery;
+pub mod rtx;
 pub mod socket;
+pub mod syncookie;
 pub mod timers;
+pub mod timewait;
@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+
+/// Spreads a connection's transmissions at its pacing rate instead of
+/// releasing a whole window at once.
+#[derive(Debug, Default)]
+pub struct Pacer {
+    /// Earliest time the next segment may leave.
+    pub next_send: Option<Instant>,
+    /// User cap in bytes per second (like SO_MAX_PACING_RATE).
+    pub max_rate: Option<u64>,
+}
+
+impl Pacer {
+    pub fn ready(&self, now: Instant) -> bool {
+        self.next_send.is_none_or(|t| t <= now)
+    }
+
+    /// Account for `bytes` leaving now at the controller's `rate`, capped by
+    /// the user maximum.
+    pub fn on_send(&mut self, bytes: usize, rate: Option<u64>, now: Instant) {
+        let rate = match (rate, self.max_rate) {
+            (Some(r), Some(m)) => Some(r.min(m)),
+            (r, m) => r.or(m),
+        };
+        let Some(rate) = rate.filter(|r| *r > 0) else {
+            self.next_send = None;
+            return;
+        };
+        let gap = Duration::from_nanos((bytes as u128 * 1_000_000_000 / rate as u128) as u64);
+        // No credit for idle time: the gap counts from now at the earliest.
+        let base = self.next_send.map_or(now, |t| t.max(now));
+        self.next_send = Some(base + gap);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::conn::seq_le;
+
+/// Smallest PMTU a Fragmentation Needed message may lower a path to; lower
+/// claims are raised to it.
+pub const MIN_PMTU: usize = 552;
+/// MSS PLPMTUD falls back to on a black hole, and never probes below.
+pub const BASE_MSS: usize = 1024;
+/// A learned PMTU is forgotten after this, so an increase can be noticed
+/// (RFC 1191 §6.3).
+pub const PMTU_TIMEOUT: Duration = Duration::from_secs(600);
+/// Bound on cached destinations; the stalest entry makes room.
+pub const PMTU_MAX_ENTRIES: usize = 1024;
+/// Lost probes of one size before it is deemed too large (RFC 8899 MAX_PROBES).
+pub const MAX_PROBES: u32 = 3;
+/// After a search completes, probe for a larger PMTU again this much later
+/// (RFC 8899 PMTU_RAISE_TIMER).
+pub const PMTU_RAISE_INTERVAL: Duration = Duration::from_secs(600);
+/// The search stops once the bounds are this close.
+const PROBE_STEP: usize = 16;
+/// Consecutive retransmission timeouts of full-sized segments that make a
+/// path a black hole.
+pub const BLACK_HOLE_RETRIES: u32 = 2;
+/// Common MTUs, for routers that report none (RFC 1191 §7).
+const PLATEAUS: [usize; 9] = [32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296];
+
+/// How hard to look for the path MTU without ICMP's help (RFC 4821).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MtuProbing {
+    Off,
+    /// Only fall back to a smaller MSS when full-sized segments keep timing out.
+    BlackHole,
+    /// Also probe for larger sizes (RFC 4821, RFC 8899).
+    Always,
+}
+
+/// MSS for a path MTU to `dst`: less the IP and TCP headers without
+/// options.
+pub fn mss_for(mtu: usize, dst: IpAddr) -> usize {
+    mtu.saturating_sub(header_len(dst))
+}
+
+/// The path MTU an MSS to `dst` fills; the inverse of `mss_for`.
+pub fn mtu_for(mss: usize, dst: IpAddr) -> usize {
+    mss + header_len(dst)
+}
+
+/// IP and TCP headers without options.
+fn header_len(dst: IpAddr) -> usize {
+    match dst {
+        IpAddr::V4(_) => 40,
+        IpAddr::V6(_) => 60,
+    }
+}
+
+/// The PMTU a Fragmentation Needed message implies: its next-hop MTU, or
+/// without one the plateau below the size of the datagram it quotes.
+pub fn frag_needed_mtu(next_hop_mtu: u16, quoted_len: u16) -> usize {
+    let mtu = match next_hop_mtu as usize {
+        0 => PLATEAUS
+            .into_iter()
+            .find(|p| *p < quoted_len as usize)
+            .unwrap_or(MIN_PMTU),
+        mtu => mtu,
+    };
+    mtu.max(MIN_PMTU)
+}
+
+/// Path MTUs learned from Fragmentation Needed messages, per destination.
+#[derive(Debug, Default)]
+pub struct PmtuCache {
+    entries: HashMap<IpAddr, (usize, Instant)>,
+}
+
+impl PmtuCache {
+    /// The PMTU to `dst`, if one was learned lately.
+    pub fn get(&self, dst: IpAddr, now: Instant) -> Option<usize> {
+        self.entries
+            .get(&dst)
+            .filter(|(_, at)| now.saturating_duration_since(*at) < PMTU_TIMEOUT)
+            .map(|(mtu, _)| *mtu)
+    }
+
+    /// Lower the PMTU to `dst`; returns whether it dropped.
+    pub fn lower(&mut self, dst: IpAddr, mtu: usize, now: Instant) -> bool {
+        if self.get(dst, now).is_some_and(|cur| cur <= mtu) {
+            return false;
+        }
+        if !self.entries.contains_key(&dst) {
+            self.entries
+                .retain(|_, (_, at)| now.saturating_duration_since(*at) < PMTU_TIMEOUT);
+            if self.entries.len() >= PMTU_MAX_ENTRIES
+                && let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, at))| *at)
+            {
+                let oldest = *oldest.0;
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(dst, (mtu, now));
+        true
+    }
+}
+
+/// A probe in flight: the segment `[seq, end)` sent at `mss`.
+#[derive(Debug, Clone, Copy)]
+pub struct Probe {
+    pub seq: u32,
+    pub end: u32,
+    pub mss: usize,
+}
+
+/// Per-connection packetization layer PMTU discovery: a binary search for
+/// the largest MSS the path carries, using data segments as probes
+/// (RFC 4821 §7), and black-hole detection.
+#[derive(Debug, Clone)]
+pub struct Plpmtud {
+    /// Search upwards, not only back off.
+    pub probing: bool,
+    /// What the interface allows.
+    max_mss: usize,
+    /// Largest MSS that may still fit; the current MSS is known to.
+    high: usize,
+    pub probe: Option<Probe>,
+    /// Probes lost at the current probe size.
+    failures: u32,
+    /// Search finished; restarts from `max_mss` then.
+    raise_at: Option<Instant>,
+}
+
+impl Plpmtud {
+    pub fn new(probing: bool, max_mss: usize) -> Self {
+        Self {
+            probing,
+            max_mss,
+            high: max_mss,
+            probe: None,
+            failures: 0,
+            raise_at: None,
+        }
+    }
+
+    /// MSS to probe with next, if a probe is due.
+    pub fn next_probe(&mut self, mss: usize, now: Instant) -> Option<usize> {
+        if !self.probing || self.probe.is_some() {
+            return None;
+        }
+        if let Some(at) = self.raise_at {
+            if now < at {
+                return None;
+            }
+            self.raise_at = None;
+            self.high = self.max_mss;
+        }
+        if self.high < mss + PROBE_STEP {
+            self.raise_at = Some(now + PMTU_RAISE_INTERVAL);
+            return None;
+        }
+        Some(mss + (self.high - mss).div_ceil(2))
+    }
+
+    /// The probe is acknowledged by `ack`; returns the MSS it confirmed.
+    pub fn on_ack(&mut self, ack: u32) -> Option<usize> {
+        let probe = self.probe.filter(|p| seq_le(p.end, ack))?;
+        self.probe = None;
+        self.failures = 0;
+        Some(probe.mss)
+    }
+
+    /// The probe was lost; after `MAX_PROBES` its size is too large.
+    pub fn on_probe_lost(&mut self) {
+        let Some(probe) = self.probe.take() else {
+            return;
+        };
+        self.failures += 1;
+        if self.failures >= MAX_PROBES {
+            self.failures = 0;
+            self.high = probe.mss - 1;
+        }
+    }
+
+    /// The path is known to carry no more than `mss`, e.g. from a
+    /// Fragmentation Needed message. A larger probe is abandoned.
+    pub fn limit(&mut self, mss: usize) {
+        self.high = self.high.min(mss);
+        if self.probe.is_some_and(|p| p.mss > mss) {
+            self.probe = None;
+            self.failures = 0;
+        }
+    }
+
+    /// Full-sized segments at `mss` keep timing out: the MSS to fall back
+    /// to, if there is a smaller one to try.
+    pub fn on_black_hole(&mut self, mss: usize) -> Option<usize> {
+        if mss <= BASE_MSS {
+            return None;
+        }
+        self.probe = None;
+        self.failures = 0;
+        self.raise_at = None;
+        self.high = mss - 1;
+        Some((mss / 2).max(BASE_MSS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Put a probe at `mss` in flight.
+    fn send_probe(p: &mut Plpmtud, mss: usize) {
+        p.probe = Some(Probe {
+            seq: 0,
+            end: mss as u32,
+            mss,
+        });
+    }
+
+    #[test]
+    fn plateau_below_the_quoted_datagram() {
+        assert_eq!(frag_needed_mtu(1400, 1500), 1400);
+        assert_eq!(frag_needed_mtu(0, 1500), 1492);
+        assert_eq!(frag_needed_mtu(0, 1492), 1006);
+        assert_eq!(frag_needed_mtu(0, 4352), 2002);
+        // Never below the minimum, reported or guessed.
+        assert_eq!(frag_needed_mtu(300, 1500), MIN_PMTU);
+        assert_eq!(frag_needed_mtu(0, 600), MIN_PMTU);
+    }
+
+    #[test]
+    fn search_halves_the_distance_to_the_bound() {
+        let now = Instant::now();
+        let mut p = Plpmtud::new(true, 1460);
+        let mut mss = BASE_MSS;
+        let mut steps = Vec::new();
+        while let Some(size) = p.next_probe(mss, now) {
+            steps.push(size);
+            send_probe(&mut p, size);
+            // The path carries 1300.
+            if size <= 1300 {
+                mss = p.on_ack(size as u32).expect("probe acked");
+            } else {
+                for _ in 0..MAX_PROBES {
+                    send_probe(&mut p, size);
+                    p.on_probe_lost();
+                }
+            }
+        }
+        assert_eq!(steps, [1242, 1351, 1296, 1323, 1309]);
+        assert_eq!(mss, 1296);
+
+        // Done until the raise timer, then from the top again.
+        assert_eq!(p.next_probe(mss, now + PMTU_RAISE_INTERVAL / 2), None);
+        assert_eq!(p.next_probe(mss, now + PMTU_RAISE_INTERVAL), Some(1378));
+    }
+
+    #[test]
+    fn size_is_given_up_after_max_probes() {
+        let now = Instant::now();
+        let mut p = Plpmtud::new(true, 1460);
+        for _ in 0..MAX_PROBES - 1 {
+            assert_eq!(p.next_probe(1024, now), Some(1242));
+            send_probe(&mut p, 1242);
+            p.on_probe_lost();
+        }
+        // Still the same size until the last allowed loss...
+        assert_eq!(p.next_probe(1024, now), Some(1242));
+        send_probe(&mut p, 1242);
+        p.on_probe_lost();
+        // ...then the search continues below it.
+        assert_eq!(p.next_probe(1024, now), Some(1133));
+    }
+
+    #[test]
+    fn black_hole_falls_back_to_the_base_mss() {
+        let now = Instant::now();
+        let mut p = Plpmtud::new(true, 1460);
+        send_probe(&mut p, 1242);
+        assert_eq!(p.on_black_hole(1460), Some(BASE_MSS));
+        assert!(p.probe.is_none());
+        assert_eq!(p.on_black_hole(BASE_MSS), None);
+        // A larger MSS is halved rather than dropped to the base.
+        assert_eq!(p.on_black_hole(4000), Some(2000));
+        // The search resumes, below the MSS that failed.
+        assert_eq!(p.next_probe(BASE_MSS, now), Some(2512));
+    }
+
+    #[test]
+    fn cache_expires_and_evicts_the_stalest() {
+        let now = Instant::now();
+        let mut cache = PmtuCache::default();
+        let dst: IpAddr = [10, 0, 0, 2].into();
+        assert!(cache.lower(dst, 1400, now));
+        assert!(!cache.lower(dst, 1450, now));
+        assert!(cache.lower(dst, 1300, now));
+        assert_eq!(cache.get(dst, now + PMTU_TIMEOUT / 2), Some(1300));
+        assert_eq!(cache.get(dst, now + PMTU_TIMEOUT), None);
+        // Once forgotten, a higher value is taken again.
+        assert!(cache.lower(dst, 1450, now + PMTU_TIMEOUT));
+
+        let mut cache = PmtuCache::default();
+        for i in 0..PMTU_MAX_ENTRIES {
+            let dst = IpAddr::from([10, 1, (i >> 8) as u8, i as u8]);
+            cache.lower(dst, 1400, now + Duration::from_millis(i as u64));
+        }
+        let newest = now + Duration::from_secs(1);
+        let dst: IpAddr = [10, 0, 0, 2].into();
+        assert!(cache.lower(dst, 1400, newest));
+        assert_eq!(cache.entries.len(), PMTU_MAX_ENTRIES);
+        assert_eq!(cache.get([10, 1, 0, 0].into(), newest), None);
+        assert_eq!(cache.get([10, 1, 0, 1].into(), newest), Some(1400));
+        assert_eq!(cache.get(dst, newest), Some(1400));
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::{Duration, Instant};
+
+use super::conn::seq_lt;
+use super::rtx::{Delivered, RetransmitQueue};
+
+/// Worst-case delayed ACK timer the peer may run (RFC 8985 §7.2).
+pub const WC_DEL_ACK: Duration = Duration::from_millis(200);
+
+/// RACK time-based loss detection state (RFC 8985 §6).
+#[derive(Debug, Default)]
+pub struct Rack {
+    /// Send time of the most recently sent segment known to be delivered.
+    pub xmit_ts: Option<Instant>,
+    pub end_seq: u32,
+    /// RTT measured on that segment.
+    pub rtt: Duration,
+    pub min_rtt: Option<Duration>,
+}
+
+impl Rack {
+    /// Fold in the newest segment an ACK delivered.
+    pub fn on_delivered(&mut self, d: Delivered, now: Instant) {
+        let rtt = now.saturating_duration_since(d.sent);
+        if d.retrans && self.min_rtt.is_some_and(|m| rtt < m) {
+            // Likely the ACK of the original transmission; not a valid sample.
+            return;
+        }
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |m| m.min(rtt)));
+        if self.sent_after(d.sent, d.end) {
+            self.xmit_ts = Some(d.sent);
+            self.end_seq = d.end;
+            self.rtt = rtt;
+        }
+    }
+
+    /// Whether a segment sent at `t` and ending at `end` is newer than the
+    /// RACK reference segment.
+    fn sent_after(&self, t: Instant, end: u32) -> bool {
+        match self.xmit_ts {
+            None => true,
+            Some(x) => t > x || (t == x && seq_lt(self.end_seq, end)),
+        }
+    }
+
+    /// Reordering window: a quarter of min RTT, at most one SRTT.
+    pub fn reo_wnd(&self, srtt: Option<Duration>) -> Duration {
+        let wnd = self.min_rtt.unwrap_or_default() / 4;
+        srtt.map_or(wnd, |s| wnd.min(s))
+    }
+
+    /// Mark outstanding segments sent before the RACK reference, and older
+    /// than its RTT plus the reordering window, as lost. Returns whether any
+    /// segment was newly marked and, if some may still be reordered, how long
+    /// until they should be checked again.
+    pub fn detect_loss(
+        &self,
+        rtx: &mut RetransmitQueue,
+        srtt: Option<Duration>,
+        now: Instant,
+    ) -> (bool, Option<Duration>) {
+        let Some(xmit_ts) = self.xmit_ts else {
+            return (false, None);
+        };
+        let reo_wnd = self.reo_wnd(srtt);
+        let mut lost = false;
+        let mut timeout: Option<Duration> = None;
+        for seg in rtx.iter_mut() {
+            if seg.sacked || (seg.lost && !seg.retrans) {
+                continue;
+            }
+            let before =
+                seg.sent < xmit_ts || (seg.sent == xmit_ts && seq_lt(seg.end(), self.end_seq));
+            if !before {
+                continue;
+            }
+            let deadline = seg.sent + self.rtt + reo_wnd;
+            if deadline <= now {
+                seg.lost = true;
+                seg.retrans = false;
+                lost = true;
+            } else {
+                let remaining = deadline - now;
+                timeout = Some(timeout.map_or(remaining, |t| t.max(remaining)));
+            }
+        }
+        (lost, timeout)
+    }
+}
+
+/// Tail Loss Probe state (RFC 8985 §7).
+#[derive(Debug, Default)]
+pub struct Tlp {
+    /// `snd_nxt` when the outstanding probe was sent.
+    pub end_seq: Option<u32>,
+    /// The probe retransmitted old data rather than sending new data.
+    pub is_retrans: bool,
+}
+
+/// Probe timeout: 2 * SRTT, plus room for a delayed ACK when a single
+/// segment is outstanding, never later than the RTO would fire.
+pub fn pto(srtt: Option<Duration>, outstanding: usize, rto: Duration) -> Duration {
+    let Some(srtt) = srtt else {
+        return rto.min(Duration::from_secs(1));
+    };
+    let mut pto = srtt * 2;
+    if outstanding == 1 {
+        pto += WC_DEL_ACK;
+    }
+    pto.min(rto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    /// Four 100-byte segments sent 10 ms apart from `t0`; the last one
+    /// SACKed 100 ms after it went out.
+    fn tail_sacked(t0: Instant) -> (Rack, RetransmitQueue) {
+        let mut rtx = RetransmitQueue::default();
+        for i in 0..4u32 {
+            rtx.push(i * 100, vec![0; 100], false, t0 + ms(10 * i as u64));
+        }
+        let mut rack = Rack::default();
+        let d = rtx.sack(300, 400).expect("delivered");
+        rack.on_delivered(d, t0 + ms(130));
+        (rack, rtx)
+    }
+
+    #[test]
+    fn reorder_window_is_a_quarter_min_rtt_capped_by_srtt() {
+        let t0 = Instant::now();
+        let (rack, _) = tail_sacked(t0);
+        assert_eq!(rack.min_rtt, Some(ms(100)));
+        assert_eq!(rack.reo_wnd(None), ms(25));
+        assert_eq!(rack.reo_wnd(Some(ms(10))), ms(10));
+    }
+
+    #[test]
+    fn loss_is_marked_once_rtt_and_window_passed() {
+        let t0 = Instant::now();
+        let (rack, mut rtx) = tail_sacked(t0);
+        // Segment 0 is due at 0 + 100 + 25 ms, segment 2 at 20 + 125 ms.
+        let (lost, timeout) = rack.detect_loss(&mut rtx, None, t0 + ms(130));
+        assert!(lost);
+        assert_eq!(timeout, Some(ms(15)));
+        let marked: Vec<bool> = rtx.iter().map(|s| s.lost).collect();
+        assert_eq!(marked, [true, false, false, false]);
+
+        // Within the window the rest wait; past it they are lost too.
+        let (lost, _) = rack.detect_loss(&mut rtx, None, t0 + ms(140));
+        assert!(lost);
+        let (lost, timeout) = rack.detect_loss(&mut rtx, None, t0 + ms(145));
+        assert!(lost);
+        assert_eq!(timeout, None);
+        assert!(rtx.iter().filter(|s| !s.sacked).all(|s| s.lost));
+    }
+
+    #[test]
+    fn spurious_retransmission_is_no_rtt_sample() {
+        let t0 = Instant::now();
+        let (mut rack, _) = tail_sacked(t0);
+        let d = Delivered {
+            sent: t0 + ms(200),
+            end: 100,
+            retrans: true,
+        };
+        rack.on_delivered(d, t0 + ms(210));
+        assert_eq!(rack.xmit_ts, Some(t0 + ms(30)));
+        assert_eq!(rack.min_rtt, Some(ms(100)));
+    }
+
+    #[test]
+    fn probe_timeout() {
+        let rto = Duration::from_secs(1);
+        // Two SRTTs, plus a delayed ACK for a lone segment.
+        assert_eq!(pto(Some(ms(100)), 3, rto), ms(200));
+        assert_eq!(pto(Some(ms(100)), 1, rto), ms(400));
+        // Never past the RTO, and without an SRTT at most a second.
+        assert_eq!(pto(Some(ms(600)), 2, rto), rto);
+        assert_eq!(pto(None, 2, ms(300)), ms(300));
+        assert_eq!(pto(None, 2, Duration::from_secs(3)), rto);
+    }
+}
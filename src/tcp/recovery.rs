@@ -0,0 +1,145 @@
+/// One fast-recovery episode, as tracked by PRR (RFC 6937).
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryEpisode {
+    /// SACK-based (RFC 6675) rather than NewReno recovery.
+    pub sack: bool,
+    /// `snd_nxt` at entry; the episode ends once this is acknowledged.
+    pub recover: u32,
+    /// Flight size at entry (RecoverFS).
+    pub recover_fs: usize,
+    /// Target chosen by the congestion controller.
+    pub ssthresh: usize,
+    pub prr_delivered: usize,
+    /// Bytes sent under PRR, new data and retransmissions alike.
+    pub prr_out: usize,
+    /// Part of `prr_out` that were retransmissions.
+    pub retransmitted: usize,
+    pub finished: bool,
+}
+
+/// Proportional Rate Reduction state for the recovery in progress.
+#[derive(Debug)]
+pub struct Prr {
+    pub episode: RecoveryEpisode,
+    /// Bytes PRR currently allows to be sent.
+    pub sndcnt: usize,
+    /// The fast retransmit goes out regardless of `sndcnt`.
+    pub force_rexmit: bool,
+}
+
+impl Prr {
+    pub fn start(recover: u32, flight: usize, ssthresh: usize, sack: bool) -> Self {
+        Self {
+            episode: RecoveryEpisode {
+                sack,
+                recover,
+                recover_fs: flight.max(1),
+                ssthresh,
+                ..Default::default()
+            },
+            sndcnt: 0,
+            force_rexmit: true,
+        }
+    }
+
+    /// Per-ACK update (RFC 6937 §3). Returns the cwnd PRR implies.
+    pub fn on_ack(&mut self, delivered: usize, pipe: usize, mss: usize) -> usize {
+        let ep = &mut self.episode;
+        ep.prr_delivered += delivered;
+        self.sndcnt = if pipe > ep.ssthresh {
+            // Proportional rate reduction
+            (ep.prr_delivered * ep.ssthresh)
+                .div_ceil(ep.recover_fs)
+                .saturating_sub(ep.prr_out)
+        } else {
+            // PRR-SSRB: slow start back up to ssthresh
+            let limit = ep.prr_delivered.saturating_sub(ep.prr_out).max(delivered) + mss;
+            (ep.ssthresh - pipe).min(limit)
+        };
+        pipe + self.sndcnt
+    }
+
+    pub fn on_send(&mut self, bytes: usize, rexmit: bool) {
+        self.episode.prr_out += bytes;
+        if rexmit {
+            self.episode.retransmitted += bytes;
+            self.force_rexmit = false;
+        }
+        self.sndcnt = self.sndcnt.saturating_sub(bytes);
+    }
+
+    /// Whether a segment of `len` bytes may go out now.
+    pub fn allows(&self, len: usize, rexmit: bool) -> bool {
+        (rexmit && self.force_rexmit) || self.sndcnt >= len
+    }
+
+    /// Recovery is over: the finished episode, and the cwnd to go on with,
+    /// which is ssthresh (RFC 6937 §3).
+    pub fn finish(self) -> (RecoveryEpisode, usize) {
+        let mut episode = self.episode;
+        episode.finished = true;
+        let cwnd = episode.ssthresh;
+        (episode, cwnd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    #[test]
+    fn prr_sends_in_proportion_to_delivery() {
+        // Reno halves: 10 segments in flight, ssthresh 5.
+        let mut prr = Prr::start(0, 10 * MSS, 5 * MSS, true);
+        assert!(prr.allows(MSS, true));
+        prr.on_send(MSS, true);
+        assert!(!prr.allows(MSS, true));
+
+        // Each delivered segment earns half a segment to send.
+        let mut pipe = 10 * MSS;
+        for _ in 0..4 {
+            pipe -= MSS;
+            prr.on_ack(MSS, pipe, MSS);
+            while prr.allows(MSS, false) {
+                prr.on_send(MSS, false);
+                pipe += MSS;
+            }
+        }
+        let ep = &prr.episode;
+        assert_eq!(ep.prr_delivered, 4 * MSS);
+        assert_eq!(ep.prr_out, 2 * MSS);
+        assert_eq!(ep.retransmitted, MSS);
+    }
+
+    #[test]
+    fn ssrb_slow_starts_past_the_conservative_bound() {
+        let mut prr = Prr::start(0, 10 * MSS, 5 * MSS, true);
+        prr.on_send(MSS, true);
+        // Heavy loss: the pipe fell well below ssthresh.
+        let cwnd = prr.on_ack(2 * MSS, 2 * MSS, MSS);
+        // PRR-CRB would allow prr_delivered - prr_out = 1 segment; SSRB
+        // adds one more per ACK.
+        let crb = prr.episode.prr_delivered - prr.episode.prr_out;
+        assert_eq!(crb, MSS);
+        assert_eq!(prr.sndcnt, 3 * MSS);
+        assert_eq!(cwnd, 5 * MSS);
+
+        // Never past ssthresh.
+        let cwnd = prr.on_ack(MSS, 4500, MSS);
+        assert_eq!(prr.sndcnt, 500);
+        assert_eq!(cwnd, 5 * MSS);
+    }
+
+    #[test]
+    fn cwnd_is_ssthresh_on_exit() {
+        let mut prr = Prr::start(0, 10 * MSS, 7 * MSS, false);
+        prr.on_send(MSS, true);
+        prr.on_ack(MSS, 8 * MSS, MSS);
+        let (episode, cwnd) = prr.finish();
+        assert!(episode.finished);
+        assert_eq!(cwnd, 7 * MSS);
+        assert_eq!(episode.prr_out, MSS);
+    }
+}
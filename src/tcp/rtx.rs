@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use super::conn::{seq_le, seq_lt};
+
+/// A transmitted segment that is not yet cumulatively acknowledged.
+#[derive(Debug, Clone)]
+pub struct TxSegment {
+    pub seq: u32,
+    pub data: Vec<u8>,
+    /// Our FIN follows the data.
+    pub fin: bool,
+    /// Time of the most recent (re)transmission.
+    pub sent: Instant,
+    pub sacked: bool,
+    /// Deemed lost by the scoreboard (RFC 6675 IsLost) or NewReno.
+    pub lost: bool,
+    /// Retransmitted since it was last marked lost.
+    pub retrans: bool,
+    pub retransmits: u32,
+}
+
+impl TxSegment {
+    pub fn end(&self) -> u32 {
+        self.seq
+            .wrapping_add(self.data.len() as u32)
+            .wrapping_add(self.fin as u32)
+    }
+
+    fn delivered(&self) -> Delivered {
+        Delivered {
+            sent: self.sent,
+            end: self.end(),
+            retrans: self.retrans || self.retransmits > 0,
+        }
+    }
+}
+
+/// The most recently sent of the segments an ACK newly delivered.
+#[derive(Debug, Clone, Copy)]
+pub struct Delivered {
+    pub sent: Instant,
+    pub end: u32,
+    /// Ambiguous for RTT purposes (Karn).
+    pub retrans: bool,
+}
+
+fn newest(cur: Option<Delivered>, d: Delivered) -> Option<Delivered> {
+    match cur {
+        Some(c) if c.sent > d.sent || (c.sent == d.sent && seq_le(d.end, c.end)) => Some(c),
+        _ => Some(d),
+    }
+}
+
+/// Retransmission queue doubling as the SACK scoreboard.
+#[derive(Debug, Default)]
+pub struct RetransmitQueue {
+    segs: VecDeque<TxSegment>,
+    sacked_bytes: usize,
+    /// Most SACKed bytes seen above a segment that then arrived without
+    /// being retransmitted.
+    reorder_bytes: usize,
+}
+
+/// RFC 6675 DupThresh.
+pub const DUP_THRESH: usize = 3;
+
+impl RetransmitQueue {
+    pub fn push(&mut self, seq: u32, data: Vec<u8>, fin: bool, now: Instant) {
+        self.segs.push_back(TxSegment {
+            seq,
+            data,
+            fin,
+            sent: now,
+            sacked: false,
+            lost: false,
+            retrans: false,
+            retransmits: 0,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TxSegment> {
+        self.segs.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut TxSegment> {
+        self.segs.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.segs.len()
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut TxSegment> {
+        self.segs.back_mut()
+    }
+
+    pub fn sacked_bytes(&self) -> usize {
+        self.sacked_bytes
+    }
+
+    pub fn reorder_bytes(&self) -> usize {
+        self.reorder_bytes
+    }
+
+    /// Drop everything below the cumulative `ack`, trimming a partially
+    /// acknowledged head segment.
+    pub fn ack(&mut self, ack: u32) -> Option<Delivered> {
+        let mut delivered = None;
+        while let Some(head) = self.segs.front_mut() {
+            if seq_le(head.end(), ack) {
+                if head.sacked {
+                    self.sacked_bytes -= head.data.len();
+                } else {
+                    if head.retransmits == 0 {
+                        // Overtaken rather than lost.
+                        self.reorder_bytes = self.reorder_bytes.max(self.sacked_bytes);
+                    }
+                    delivered = newest(delivered, head.delivered());
+                }
+                self.segs.pop_front();
+            } else {
+                if seq_lt(head.seq, ack) {
+                    let n = ack.wrapping_sub(head.seq) as usize;
+                    if head.sacked {
+                        self.sacked_bytes -= n;
+                    }
+                    head.data.drain(..n);
+                    head.seq = ack;
+                }
+                break;
+            }
+        }
+        delivered
+    }
+
+    /// Mark segments fully covered by the SACK block `[left, right)`.
+    pub fn sack(&mut self, left: u32, right: u32) -> Option<Delivered> {
+        let mut delivered = None;
+        for seg in self.segs.iter_mut() {
+            if !seg.sacked && seq_le(left, seg.seq) && seq_le(seg.end(), right) {
+                seg.sacked = true;
+                self.sacked_bytes += seg.data.len();
+                delivered = newest(delivered, seg.delivered());
+            }
+        }
+        delivered
+    }
+
+    /// RFC 6675 IsLost: a hole is lost once more than (DupThresh - 1) * MSS
+    /// bytes above it have been SACKed. Returns whether any hole is lost.
+    pub fn update_lost(&mut self, mss: usize, dup_thresh: usize) -> bool {
+        let mut above = 0;
+        let mut any = false;
+        for seg in self.segs.iter_mut().rev() {
+            if seg.sacked {
+                above += seg.data.len();
+            } else if above > (dup_thresh - 1) * mss {
+                seg.lost = true;
+            }
+            any |= seg.lost && !seg.sacked;
+        }
+        any
+    }
+
+    /// RTO: everything outstanding is presumed lost and due again.
+    pub fn mark_all_lost(&mut self) {
+        for seg in self.segs.iter_mut().filter(|s| !s.sacked) {
+            seg.lost = true;
+            seg.retrans = false;
+        }
+    }
+
+    /// Without SACK the only loss we can infer is the first hole.
+    pub fn mark_head_lost(&mut self) {
+        if let Some(head) = self.segs.front_mut() {
+            head.lost = true;
+            head.retrans = false;
+        }
+    }
+
+    /// Split unSACKed segments larger than `max` bytes into ones that fit,
+    /// marked lost: sent with DF set, the originals cannot have arrived.
+    /// Returns whether any were split.
+    pub fn resegment(&mut self, max: usize) -> bool {
+        if !self.segs.iter().any(|s| !s.sacked && s.data.len() > max) {
+            return false;
+        }
+        let max = max.max(1);
+        let mut segs = VecDeque::with_capacity(self.segs.len());
+        for seg in self.segs.drain(..) {
+            if seg.sacked || seg.data.len() <= max {
+                segs.push_back(seg);
+                continue;
+            }
+            let pieces = seg.data.len().div_ceil(max);
+            for (i, chunk) in seg.data.chunks(max).enumerate() {
+                segs.push_back(TxSegment {
+                    seq: seg.seq.wrapping_add((i * max) as u32),
+                    data: chunk.to_vec(),
+                    fin: seg.fin && i + 1 == pieces,
+                    sent: seg.sent,
+                    sacked: false,
+                    lost: true,
+                    retrans: false,
+                    retransmits: seg.retransmits,
+                });
+            }
+        }
+        self.segs = segs;
+        true
+    }
+
+    /// RFC 6675 pipe: bytes believed to still be in the network.
+    pub fn pipe(&self) -> usize {
+        self.segs
+            .iter()
+            .filter(|s| !s.sacked)
+            .map(|s| {
+                let mut n = 0;
+                if !s.lost {
+                    n += s.data.len();
+                }
+                if s.retrans {
+                    n += s.data.len();
+                }
+                n
+            })
+            .sum()
+    }
+
+    /// Lowest lost segment not yet retransmitted.
+    pub fn next_lost(&mut self) -> Option<&mut TxSegment> {
+        self.segs
+            .iter_mut()
+            .find(|s| s.lost && !s.sacked && !s.retrans)
+    }
+}
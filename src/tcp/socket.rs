@@ -1,27 +1,143 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Shutdown};
+
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{Duration, timeout};
 
-use super::conn::{Quad, TcpCmd};
+use super::auth::{MasterKey, TcpAuth};
+use super::congestion::CongestionAlgorithm;
+use super::conn::{ConnStats, Quad, TcpCmd};
 use crate::error::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TcpSocketAddr {
-    pub ip: [u8; 4],
+    pub ip: IpAddr,
     pub port: u16,
 }
 
+/// What the stack hands a `TcpStream`, in stream order.
+#[derive(Debug)]
+pub enum Incoming {
+    Data(Vec<u8>),
+    /// The urgent mark: in inline mode the next byte is the urgent byte, out
+    /// of band it is where the urgent byte was taken out.
+    UrgentMark,
+    /// The urgent byte, when not delivered inline.
+    Oob(u8),
+    /// The peer's FIN: end of stream.
+    Fin,
+}
+
 /// A minimal async stream-like API backed by the stack.
 pub struct TcpStream {
     id: Quad,
     tx_cmd: mpsc::Sender<TcpCmd>,
-    app_rx: mpsc::Receiver<Vec<u8>>,
-    app_tx: mpsc::Sender<Vec<u8>>,
+    /// For the close on drop, which must not be lost to a full `tx_cmd`.
+    tx_drop: mpsc::UnboundedSender<TcpCmd>,
+    app_rx: mpsc::Receiver<Result<Incoming>>,
+    /// Received ahead of `read` while looking for urgent data.
+    ahead: VecDeque<Result<Incoming>>,
+    oob: Option<u8>,
+    /// End of stream seen, or reading shut down.
+    eof: bool,
+    wr_shutdown: bool,
+    linger: Option<Duration>,
+    /// Closed or aborted explicitly, so dropping has nothing left to do.
+    closed: bool,
+}
+
+/// Per-connection options settable on a `TcpStream`.
+#[derive(Debug, Clone)]
+pub enum SockOpt {
+    /// Cap on the pacing rate in bytes per second; `None` removes the cap.
+    MaxPacingRate(Option<u64>),
+    /// Keepalive probing; `None` turns it off.
+    Keepalive(Option<Keepalive>),
+    /// Leave urgent bytes in the data stream instead of `read_oob`
+    /// (SO_OOBINLINE).
+    OobInline(bool),
+    /// Longest time data may stay unacknowledged before the connection is
+    /// aborted (TCP_USER_TIMEOUT); `None` retransmits indefinitely.
+    UserTimeout(Option<Duration>),
+    /// Replace the TCP-AO master keys, e.g. to add the next one of a
+    /// rollover (TCP_AO_ADD_KEY, TCP_AO_DEL_KEY).
+    AuthKeys(Vec<MasterKey>),
+    /// Ask the peer to switch to sending with the key it knows by this
+    /// KeyID (TCP_AO_INFO's RNext).
+    AuthRNextKey(u8),
+}
+
+/// Keepalive timing (SO_KEEPALIVE with TCP_KEEPIDLE, TCP_KEEPINTVL and
+/// TCP_KEEPCNT). Defaults match the usual 2h / 75s / 9 probes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Silence from the peer before the first probe.
+    pub idle: Duration,
+    /// Between unanswered probes.
+    pub interval: Duration,
+    /// Unanswered probes before the connection is aborted.
+    pub probes: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(7200),
+            interval: Duration::from_secs(75),
+            probes: 9,
+        }
+    }
+}
+
+/// Per-listener options applied to every accepted connection.
+#[derive(Debug, Clone, Default)]
+pub struct ListenOptions {
+    /// Overrides the stack's congestion control choice, e.g. DCTCP for a
+    /// listener only reachable from inside the data center.
+    pub congestion: Option<CongestionAlgorithm>,
+    /// Accept TCP Fast Open (RFC 7413), with at most this many connections
+    /// that were accepted on SYN data still awaiting the handshake's ACK.
+    pub fast_open: Option<usize>,
+    /// TCP-AO or TCP-MD5 keys by peer address. Segments from these peers
+    /// must be signed, and from others must not be.
+    pub auth: HashMap<IpAddr, TcpAuth>,
+}
+
+/// Per-listener counters.
+#[derive(Debug, Clone, Default)]
+pub struct ListenerStats {
+    /// SYNs answered with a SYN cookie because the half-open queue was full.
+    pub syn_cookies_sent: u64,
+    /// ACKs whose cookie checked out and became connections.
+    pub syn_cookies_validated: u64,
+    /// ACKs that arrived while cookies were in use but carried none of ours.
+    pub syn_cookies_failed: u64,
+    /// SYNs dropped with the half-open queue full and cookies off.
+    pub syn_drops: u64,
+    /// Connections in SYN-RECEIVED: the half-open queue.
+    pub half_open: usize,
+    /// Segments for the port with no connection, dropped for a missing or
+    /// bad TCP-AO or TCP-MD5 signature.
+    pub auth_failures: u64,
+}
+
+/// Options for an active open.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Use TCP Fast Open: send `data` in the SYN if a cookie for the server
+    /// is cached, else ask for one.
+    pub fast_open: bool,
+    /// First data to send; in the SYN under Fast Open, otherwise once the
+    /// handshake completes.
+    pub data: Vec<u8>,
+    /// Sign segments with TCP-AO or TCP-MD5. Fast Open is not used then.
+    pub auth: Option<TcpAuth>,
 }
 
 pub struct TcpListener {
     local: TcpSocketAddr,
     tx_cmd: mpsc::Sender<TcpCmd>,
-    // For skeleton: not fully implemented
+    accept_rx: mpsc::Receiver<TcpStream>,
 }
 
 impl TcpStream {
@@ -30,7 +146,15 @@ impl TcpStream {
         local: TcpSocketAddr,
         remote: TcpSocketAddr,
     ) -> Result<Self> {
-        let (app_tx_s, app_tx_r) = mpsc::channel(64);
+        Self::connect_with(tx_cmd, local, remote, ConnectOptions::default()).await
+    }
+
+    pub async fn connect_with(
+        tx_cmd: mpsc::Sender<TcpCmd>,
+        local: TcpSocketAddr,
+        remote: TcpSocketAddr,
+        opts: ConnectOptions,
+    ) -> Result<Self> {
         let (app_rx_s, app_rx_r) = mpsc::channel(64);
 
         let id = Quad {
@@ -42,53 +166,304 @@ impl TcpStream {
 
         let (reply_tx, reply_rx) = oneshot::channel();
         tx_cmd
-            .send(TcpCmd::Connect(id, reply_tx))
+            .send(TcpCmd::Connect(id, opts, app_rx_s, reply_tx))
             .await
             .map_err(|_| UrtcpError::Device("control channel".into()))?;
-        timeout(Duration::from_secs(2), reply_rx)
+        let tx_drop = timeout(Duration::from_secs(2), reply_rx)
             .await
             .map_err(|_| UrtcpError::Device("connect timeout".into()))?
             .map_err(|_| UrtcpError::Device("connect drop".into()))??;
 
-        Ok(Self {
+        Ok(Self::from_parts(id, tx_cmd, tx_drop, app_rx_r))
+    }
+
+    pub(crate) fn from_parts(
+        id: Quad,
+        tx_cmd: mpsc::Sender<TcpCmd>,
+        tx_drop: mpsc::UnboundedSender<TcpCmd>,
+        app_rx: mpsc::Receiver<Result<Incoming>>,
+    ) -> Self {
+        Self {
             id,
             tx_cmd,
-            app_rx: app_rx_r,
-            app_tx: app_tx_s,
-        })
+            tx_drop,
+            app_rx,
+            ahead: VecDeque::new(),
+            oob: None,
+            eof: false,
+            wr_shutdown: false,
+            linger: None,
+            closed: false,
+        }
+    }
+
+    pub fn local_addr(&self) -> TcpSocketAddr {
+        TcpSocketAddr {
+            ip: self.id.src_ip,
+            port: self.id.src_port,
+        }
     }
 
+    pub fn peer_addr(&self) -> TcpSocketAddr {
+        TcpSocketAddr {
+            ip: self.id.dst_ip,
+            port: self.id.dst_port,
+        }
+    }
+
+    /// Queue `data`; `BrokenPipe` once writing was shut down.
     pub async fn write_all(&self, data: Vec<u8>) -> Result<()> {
+        if self.wr_shutdown {
+            return Err(UrtcpError::BrokenPipe);
+        }
         self.tx_cmd
             .send(TcpCmd::Send(self.id, data))
             .await
             .map_err(|_| UrtcpError::Device("control channel".into()))
     }
 
+    /// Send `data` with its last byte as urgent data (MSG_OOB). The urgent
+    /// pointer refers to the byte after it (RFC 6093).
+    pub async fn write_urgent(&self, data: Vec<u8>) -> Result<()> {
+        if self.wr_shutdown {
+            return Err(UrtcpError::BrokenPipe);
+        }
+        self.tx_cmd
+            .send(TcpCmd::SendUrgent(self.id, data))
+            .await
+            .map_err(|_| UrtcpError::Device("control channel".into()))
+    }
+
+    /// Next chunk of data, `None` at end of stream. A chunk never spans the
+    /// urgent mark. An aborted connection, e.g. after unanswered keepalives,
+    /// reports why.
     pub async fn read(&mut self) -> Result<Option<Vec<u8>>> {
-        Ok(self.app_rx.recv().await)
+        loop {
+            if self.eof {
+                return Ok(None);
+            }
+            let item = match self.ahead.pop_front() {
+                Some(item) => item,
+                None => match self.app_rx.recv().await {
+                    Some(item) => item,
+                    None => return Ok(None),
+                },
+            };
+            match item? {
+                Incoming::Data(data) => return Ok(Some(data)),
+                Incoming::UrgentMark => {}
+                Incoming::Oob(byte) => self.oob = Some(byte),
+                Incoming::Fin => self.eof = true,
+            }
+        }
+    }
+
+    /// Pull in whatever has already arrived, taking out the urgent byte.
+    fn read_ahead(&mut self) {
+        while let Ok(item) = self.app_rx.try_recv() {
+            match item {
+                Ok(Incoming::Oob(byte)) => self.oob = Some(byte),
+                item => self.ahead.push_back(item),
+            }
+        }
+    }
+
+    /// Whether the next `read` starts at the urgent mark (SIOCATMARK).
+    pub fn at_mark(&mut self) -> bool {
+        self.read_ahead();
+        matches!(self.ahead.front(), Some(Ok(Incoming::UrgentMark)))
+    }
+
+    /// The urgent byte, if one has arrived and out-of-band delivery is on.
+    /// `WouldBlock` otherwise.
+    pub fn read_oob(&mut self) -> Result<u8> {
+        self.read_ahead();
+        self.oob.take().ok_or(UrtcpError::WouldBlock)
+    }
+
+    /// Per-connection counters, including PRR loss recovery episodes.
+    pub async fn stats(&self) -> Result<ConnStats> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx_cmd
+            .send(TcpCmd::Stats(self.id, reply_tx))
+            .await
+            .map_err(|_| UrtcpError::Device("control channel".into()))?;
+        reply_rx
+            .await
+            .map_err(|_| UrtcpError::Device("stats drop".into()))?
+            .ok_or(UrtcpError::ConnNotFound)
+    }
+
+    /// The tcpcrypt session ID, for channel binding: `None` while the key
+    /// exchange is in progress, and for good if the stream is not encrypted.
+    pub async fn session_id(&self) -> Result<Option<Vec<u8>>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx_cmd
+            .send(TcpCmd::SessionId(self.id, reply_tx))
+            .await
+            .map_err(|_| UrtcpError::Device("control channel".into()))?;
+        reply_rx
+            .await
+            .map_err(|_| UrtcpError::Device("session id drop".into()))?
     }
 
-    pub async fn close(&self) -> Result<()> {
+    pub async fn set_option(&self, opt: SockOpt) -> Result<()> {
         self.tx_cmd
-            .send(TcpCmd::Close(self.id))
+            .send(TcpCmd::SetOption(self.id, opt))
             .await
             .map_err(|_| UrtcpError::Device("control channel".into()))
     }
+
+    /// Limit this connection to `rate` bytes per second, e.g. for bandwidth
+    /// capping. `None` leaves only the congestion controller's pacing.
+    pub async fn set_max_pacing_rate(&self, rate: Option<u64>) -> Result<()> {
+        self.set_option(SockOpt::MaxPacingRate(rate)).await
+    }
+
+    /// Override the stack's keepalive default for this connection.
+    pub async fn set_keepalive(&self, keepalive: Option<Keepalive>) -> Result<()> {
+        self.set_option(SockOpt::Keepalive(keepalive)).await
+    }
+
+    /// Abort the connection once sent data has gone unacknowledged for
+    /// `timeout`, rather than backing off retransmissions indefinitely.
+    pub async fn set_user_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.set_option(SockOpt::UserTimeout(timeout)).await
+    }
+
+    pub async fn set_oob_inline(&self, inline: bool) -> Result<()> {
+        self.set_option(SockOpt::OobInline(inline)).await
+    }
+
+    /// Half-close: after `Write` a FIN follows the queued data while reading
+    /// goes on; after `Read` further data is discarded and `read` reports
+    /// end of stream.
+    pub async fn shutdown(&mut self, how: Shutdown) -> Result<()> {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.eof = true;
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.wr_shutdown = true;
+        }
+        self.tx_cmd
+            .send(TcpCmd::Shutdown(self.id, how))
+            .await
+            .map_err(|_| UrtcpError::Device("control channel".into()))
+    }
+
+    /// How `close` and dropping the stream treat unsent and unacknowledged
+    /// data (SO_LINGER). `None`, the default, closes gracefully in the
+    /// background. `Some(t)` makes `close` wait up to `t` for the peer to
+    /// acknowledge everything; `Some(Duration::ZERO)` aborts instead.
+    pub fn set_linger(&mut self, linger: Option<Duration>) {
+        self.linger = linger;
+    }
+
+    pub fn linger(&self) -> Option<Duration> {
+        self.linger
+    }
+
+    /// Close both directions: the FIN goes out after the queued data. With a
+    /// linger time, wait for the peer to acknowledge it; `TimedOut` if it did
+    /// not in time, in which case the close carries on in the background.
+    pub async fn close(mut self) -> Result<()> {
+        self.closed = true;
+        if self.linger.is_some_and(|t| t.is_zero()) {
+            return self.send_abort().await;
+        }
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx_cmd
+            .send(TcpCmd::Close(self.id, self.linger.map(|t| (t, reply_tx))))
+            .await
+            .map_err(|_| UrtcpError::Device("control channel".into()))?;
+        if self.linger.is_none() {
+            return Ok(());
+        }
+        // The stack times the linger, on its clock.
+        reply_rx
+            .await
+            // Gone without everything acknowledged: reset.
+            .map_err(|_| UrtcpError::ConnectionReset)?
+    }
+
+    /// Reset the connection at once, discarding data not yet sent or
+    /// acknowledged.
+    pub async fn abort(mut self) -> Result<()> {
+        self.closed = true;
+        self.send_abort().await
+    }
+
+    async fn send_abort(&self) -> Result<()> {
+        self.tx_cmd
+            .send(TcpCmd::Abort(self.id))
+            .await
+            .map_err(|_| UrtcpError::Device("control channel".into()))
+    }
+}
+
+/// Dropping a stream closes it gracefully, or aborts it under a zero
+/// linger time; it never waits, and the close is not lost to a busy stack.
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        let cmd = match self.linger {
+            Some(t) if t.is_zero() => TcpCmd::Abort(self.id),
+            _ => TcpCmd::Close(self.id, None),
+        };
+        let _ = self.tx_drop.send(cmd);
+    }
 }
 
 impl TcpListener {
     pub async fn bind(tx_cmd: mpsc::Sender<TcpCmd>, local: TcpSocketAddr) -> Result<Self> {
+        Self::bind_with(tx_cmd, local, ListenOptions::default()).await
+    }
+
+    pub async fn bind_with(
+        tx_cmd: mpsc::Sender<TcpCmd>,
+        local: TcpSocketAddr,
+        opts: ListenOptions,
+    ) -> Result<Self> {
+        let (accept_tx, accept_rx) = mpsc::channel(128);
         let (reply_tx, reply_rx) = oneshot::channel();
         tx_cmd
-            .send(TcpCmd::Listen(local.port, reply_tx))
+            .send(TcpCmd::Listen(local.port, opts, accept_tx, reply_tx))
             .await
             .map_err(|_| UrtcpError::Device("control channel".into()))?;
         reply_rx
             .await
             .map_err(|_| UrtcpError::Device("listen drop".into()))??;
-        Ok(Self { local, tx_cmd })
+        Ok(Self {
+            local,
+            tx_cmd,
+            accept_rx,
+        })
+    }
+
+    /// Wait for the next connection that completed the handshake.
+    pub async fn accept(&mut self) -> Result<TcpStream> {
+        self.accept_rx
+            .recv()
+            .await
+            .ok_or_else(|| UrtcpError::Device("listener closed".into()))
+    }
+
+    pub fn local_addr(&self) -> TcpSocketAddr {
+        self.local
     }
 
-    // In a full impl, accept() would wait on a channel for new Quad + app queues.
+    /// SYN cookie and half-open queue counters.
+    pub async fn stats(&self) -> Result<ListenerStats> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx_cmd
+            .send(TcpCmd::ListenerStats(self.local.port, reply_tx))
+            .await
+            .map_err(|_| UrtcpError::Device("control channel".into()))?;
+        reply_rx
+            .await
+            .map_err(|_| UrtcpError::Device("stats drop".into()))?
+            .ok_or(UrtcpError::ConnNotFound)
+    }
 }
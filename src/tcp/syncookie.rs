@@ -0,0 +1,198 @@
+use std::time::{Duration, Instant};
+
+use super::conn::Quad;
+use super::fastopen::{keyed_hash, random_key};
+
+/// MSS values a cookie can encode; the peer gets the largest one not above
+/// what it advertised.
+pub const SYN_COOKIE_MSS: [u16; 4] = [536, 1300, 1440, 1460];
+/// The cookie's time counter advances once per period.
+const COUNTER_PERIOD: Duration = Duration::from_secs(60);
+/// Counter periods a cookie stays valid for.
+const MAX_AGE: u32 = 2;
+/// How long after the last cookie sent an ACK may still carry one.
+pub const SYN_COOKIE_LIFETIME: Duration =
+    Duration::from_secs(COUNTER_PERIOD.as_secs() * MAX_AGE as u64);
+
+/// Low TSval bits carrying the options of a cookie handshake: window scale
+/// (0xf for none), SACK permitted and ECN setup.
+const TS_WSCALE_MASK: u32 = 0x0f;
+const TS_SACK: u32 = 0x10;
+const TS_ECN: u32 = 0x20;
+const TS_OPT_MASK: u32 = 0x3f;
+
+/// Options a SYN negotiated, to be recovered from the ACK's timestamp echo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CookieOptions {
+    pub wscale: Option<u8>,
+    pub sack: bool,
+    pub ecn: bool,
+}
+
+/// Stateless SYN-ACKs for listeners under a SYN flood. The ISN encodes a
+/// keyed hash of the connection, a coarse time counter and the MSS index,
+/// so the ACK completing the handshake carries all we need to rebuild it.
+#[derive(Debug)]
+pub struct SynCookies {
+    key: [u8; 16],
+    epoch: Instant,
+}
+
+impl SynCookies {
+    /// Cookies whose time counter starts at `epoch`.
+    pub fn new(epoch: Instant) -> Self {
+        Self {
+            key: random_key(),
+            epoch,
+        }
+    }
+
+    /// Truncated HMAC-SHA256 of `id`, and of the counter if given.
+    fn hash(&self, id: &Quad, count: Option<u32>) -> u32 {
+        let id = id.to_bytes();
+        let hash = match count {
+            Some(count) => keyed_hash(&self.key, &[&id, &[1], &count.to_be_bytes()]),
+            None => keyed_hash(&self.key, &[&id, &[0]]),
+        };
+        u32::from_be_bytes(hash)
+    }
+
+    fn counter(&self, now: Instant) -> u32 {
+        (now.saturating_duration_since(self.epoch).as_secs() / COUNTER_PERIOD.as_secs()) as u32
+    }
+
+    /// Index into `SYN_COOKIE_MSS` for a peer advertising `mss`.
+    pub fn mss_index(mss: u16) -> usize {
+        SYN_COOKIE_MSS.iter().rposition(|&m| m <= mss).unwrap_or(0)
+    }
+
+    /// ISN for the SYN-ACK answering `peer_isn` on `id`:
+    /// H(id) + peer_isn + (count << 24) + (H(id, count) + mss_index) mod 2^24.
+    pub fn make(&self, id: &Quad, peer_isn: u32, mss_index: usize, now: Instant) -> u32 {
+        let count = self.counter(now);
+        self.hash(id, None)
+            .wrapping_add(peer_isn)
+            .wrapping_add(count << 24)
+            .wrapping_add(self.hash(id, Some(count)).wrapping_add(mss_index as u32) & 0x00ff_ffff)
+    }
+
+    /// The MSS a cookie returned in an ACK encodes, if it is ours and fresh.
+    pub fn check(&self, id: &Quad, peer_isn: u32, cookie: u32, now: Instant) -> Option<u16> {
+        let v = cookie
+            .wrapping_sub(self.hash(id, None))
+            .wrapping_sub(peer_isn);
+        let now_count = self.counter(now);
+        // Only the low 8 bits of the counter survive in the cookie.
+        let age = now_count.wrapping_sub(v >> 24) & 0xff;
+        if age >= MAX_AGE {
+            return None;
+        }
+        let count = now_count.wrapping_sub(age);
+        let index = v
+            .wrapping_sub(count << 24)
+            .wrapping_sub(self.hash(id, Some(count)))
+            & 0x00ff_ffff;
+        SYN_COOKIE_MSS.get(index as usize).copied()
+    }
+}
+
+/// TSval for a cookie SYN-ACK: the clock at `now_ms` with `opts` in the low
+/// bits, kept from running ahead of the clock.
+pub fn encode_ts(now_ms: u32, opts: CookieOptions) -> u32 {
+    let mut bits = opts.wscale.map_or(TS_WSCALE_MASK, |s| u32::from(s.min(14)));
+    if opts.sack {
+        bits |= TS_SACK;
+    }
+    if opts.ecn {
+        bits |= TS_ECN;
+    }
+    let val = (now_ms & !TS_OPT_MASK) | bits;
+    if val > now_ms {
+        val.wrapping_sub(TS_OPT_MASK + 1)
+    } else {
+        val
+    }
+}
+
+/// Options encoded by `encode_ts`, from the timestamp the peer echoes.
+pub fn decode_ts(ecr: u32) -> CookieOptions {
+    let wscale = ecr & TS_WSCALE_MASK;
+    CookieOptions {
+        wscale: (wscale != TS_WSCALE_MASK).then_some(wscale as u8),
+        sack: ecr & TS_SACK != 0,
+        ecn: ecr & TS_ECN != 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(src_port: u16) -> Quad {
+        Quad {
+            src_ip: [10, 0, 0, 2].into(),
+            src_port,
+            dst_ip: [10, 0, 0, 9].into(),
+            dst_port: 4000,
+        }
+    }
+
+    #[test]
+    fn cookie_round_trips_with_the_mss() {
+        let epoch = Instant::now();
+        let cookies = SynCookies::new(epoch);
+        let id = quad(80);
+        for (advertised, mss) in [
+            (1460, 1460),
+            (1450, 1440),
+            (1400, 1300),
+            (600, 536),
+            (100, 536),
+        ] {
+            let index = SynCookies::mss_index(advertised);
+            let cookie = cookies.make(&id, 1000, index, epoch);
+            assert_eq!(cookies.check(&id, 1000, cookie, epoch), Some(mss));
+        }
+    }
+
+    #[test]
+    fn stale_counter_is_rejected() {
+        let epoch = Instant::now();
+        let cookies = SynCookies::new(epoch);
+        let id = quad(80);
+        let cookie = cookies.make(&id, 1000, 3, epoch + COUNTER_PERIOD);
+        // Good for the period it was made in and the next one...
+        let last = epoch + COUNTER_PERIOD * (MAX_AGE + 1) - Duration::from_secs(1);
+        assert_eq!(cookies.check(&id, 1000, cookie, last), Some(1460));
+        // ...and no longer after.
+        let expired = epoch + COUNTER_PERIOD * (MAX_AGE + 1);
+        assert_eq!(cookies.check(&id, 1000, cookie, expired), None);
+    }
+
+    #[test]
+    fn cookie_is_bound_to_its_connection() {
+        let epoch = Instant::now();
+        let cookies = SynCookies::new(epoch);
+        let cookie = cookies.make(&quad(80), 1000, 3, epoch);
+        assert_eq!(cookies.check(&quad(81), 1000, cookie, epoch), None);
+        // Nor does another listener's key accept it.
+        let other = SynCookies::new(epoch);
+        assert_eq!(other.check(&quad(80), 1000, cookie, epoch), None);
+    }
+
+    #[test]
+    fn options_survive_the_timestamp() {
+        let opts = CookieOptions {
+            wscale: Some(7),
+            sack: true,
+            ecn: false,
+        };
+        let ts = encode_ts(100_000, opts);
+        assert!(ts <= 100_000);
+        assert_eq!(decode_ts(ts), opts);
+        assert_eq!(
+            decode_ts(encode_ts(100_000, CookieOptions::default())),
+            CookieOptions::default()
+        );
+    }
+}
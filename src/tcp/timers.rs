@@ -1,17 +1,335 @@
-use std::time::Duration;
-use tokio::time::{self, Interval};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
 
-pub struct TimerWheel {
-    tick: Interval,
+use crate::clock::Clock;
+
+/// Resolution of the timer wheel.
+pub const TICK: Duration = Duration::from_millis(1);
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// Six levels of 64 slots span 2^36 ticks, a little over two years.
+const LEVELS: usize = 6;
+const SPAN: u64 = 1 << (SLOT_BITS as usize * LEVELS);
+const NIL: usize = usize::MAX;
+
+struct Entry<K> {
+    key: K,
+    /// Deadline in ticks since the wheel's origin.
+    at: u64,
+    level: usize,
+    slot: usize,
+    prev: usize,
+    next: usize,
+}
+
+/// Hierarchical timer wheel (Varghese and Lauck): each level has 64 slots,
+/// each 64 times as wide as a slot on the level below. A deadline goes on
+/// the lowest level whose current window it falls in, and moves down a
+/// level whenever the wheel reaches its slot. Scheduling and cancelling
+/// are O(1); at most one deadline is kept per key.
+pub struct TimerWheel<K> {
+    origin: Instant,
+    /// Ticks processed so far.
+    elapsed: u64,
+    entries: Vec<Option<Entry<K>>>,
+    free: Vec<usize>,
+    index: HashMap<K, usize>,
+    /// Head of each slot's list of entries.
+    heads: [[usize; SLOTS]; LEVELS],
+    /// Bit `s` of `occupied[l]` is set when slot `s` of level `l` has entries.
+    occupied: [u64; LEVELS],
 }
 
-impl TimerWheel {
-    pub fn new() -> Self {
+impl<K: Copy + Eq + Hash> TimerWheel<K> {
+    pub fn new(origin: Instant) -> Self {
         Self {
-            tick: time::interval(Duration::from_millis(50)),
+            origin,
+            elapsed: 0,
+            entries: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+            heads: [[NIL; SLOTS]; LEVELS],
+            occupied: [0; LEVELS],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Fire `key` at `deadline`, replacing any deadline it had. Deadlines
+    /// are rounded up to the next tick, so they never fire early.
+    pub fn schedule(&mut self, key: K, deadline: Instant) {
+        self.cancel(&key);
+        let since = deadline.saturating_duration_since(self.origin);
+        let at = since.as_nanos().div_ceil(TICK.as_nanos()) as u64;
+        let entry = Entry {
+            key,
+            at,
+            level: 0,
+            slot: 0,
+            prev: NIL,
+            next: NIL,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.entries[i] = Some(entry);
+                i
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            }
+        };
+        self.index.insert(key, i);
+        self.link(i);
+    }
+
+    /// Drop `key`'s deadline, if it has one.
+    pub fn cancel(&mut self, key: &K) {
+        if let Some(i) = self.index.remove(key) {
+            self.unlink(i);
+            self.entries[i] = None;
+            self.free.push(i);
+        }
+    }
+
+    /// When the wheel next needs servicing: the earliest deadline, or
+    /// earlier when one has to move down a level first.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let (_, _, start) = self.next_slot()?;
+        Some(self.origin + Duration::from_nanos(start * TICK.as_nanos() as u64))
+    }
+
+    /// Advance to `now`, returning the keys whose deadlines have passed.
+    pub fn poll(&mut self, now: Instant) -> Vec<K> {
+        let target =
+            (now.saturating_duration_since(self.origin).as_nanos() / TICK.as_nanos()) as u64;
+        let mut due = Vec::new();
+        while let Some((level, slot, start)) = self.next_slot().filter(|s| s.2 <= target) {
+            self.elapsed = start;
+            let mut i = std::mem::replace(&mut self.heads[level][slot], NIL);
+            self.occupied[level] &= !(1 << slot);
+            while i != NIL {
+                let entry = self.entries[i].as_mut().expect("linked entry");
+                let next = entry.next;
+                if entry.at <= self.elapsed {
+                    due.push(entry.key);
+                    self.index.remove(&entry.key);
+                    self.entries[i] = None;
+                    self.free.push(i);
+                } else {
+                    self.link(i);
+                }
+                i = next;
+            }
+        }
+        self.elapsed = self.elapsed.max(target);
+        due
+    }
+
+    /// Level, slot and start tick of the first slot with entries.
+    fn next_slot(&self) -> Option<(usize, usize, u64)> {
+        // Every entry shares the bits above its level with `elapsed`, and
+        // sits at or after `elapsed`'s slot there, so the lowest occupied
+        // level holds the earliest deadlines.
+        let level = self.occupied.iter().position(|o| *o != 0)?;
+        let shift = SLOT_BITS as usize * level;
+        let pos = (self.elapsed >> shift) as usize % SLOTS;
+        let slot = (self.occupied[level] & (u64::MAX << pos)).trailing_zeros() as usize;
+        let window = self.elapsed & !((SLOTS as u64) << shift).wrapping_sub(1);
+        let start = (window + ((slot as u64) << shift)).max(self.elapsed);
+        Some((level, slot, start))
+    }
+
+    /// Put entry `i` on the level and slot its deadline falls in.
+    fn link(&mut self, i: usize) {
+        let elapsed = self.elapsed;
+        let entry = self.entries[i].as_mut().expect("live entry");
+        // Past deadlines are due on the next poll; ones beyond the top
+        // level's window fire early and are expected to be rescheduled.
+        entry.at = entry.at.clamp(elapsed, elapsed | (SPAN - 1));
+        let significant = 63 - ((elapsed ^ entry.at) | (SLOTS as u64 - 1)).leading_zeros();
+        let level = significant as usize / SLOT_BITS as usize;
+        let slot = (entry.at >> (SLOT_BITS as usize * level)) as usize % SLOTS;
+        let head = self.heads[level][slot];
+        entry.level = level;
+        entry.slot = slot;
+        entry.prev = NIL;
+        entry.next = head;
+        if head != NIL {
+            self.entries[head].as_mut().expect("linked entry").prev = i;
+        }
+        self.heads[level][slot] = i;
+        self.occupied[level] |= 1 << slot;
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let entry = self.entries[i].as_ref().expect("live entry");
+        let (level, slot, prev, next) = (entry.level, entry.slot, entry.prev, entry.next);
+        match prev {
+            NIL => self.heads[level][slot] = next,
+            p => self.entries[p].as_mut().expect("linked entry").next = next,
+        }
+        if next != NIL {
+            self.entries[next].as_mut().expect("linked entry").prev = prev;
+        }
+        if self.heads[level][slot] == NIL {
+            self.occupied[level] &= !(1 << slot);
         }
     }
-    pub async fn tick(&mut self) {
-        self.tick.tick().await;
+}
+
+/// Per-connection deadlines. The stack keeps the earliest of them on its
+/// timer wheel.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConnTimers {
+    pub rto: Option<Instant>,
+    /// Tail loss probe.
+    pub tlp: Option<Instant>,
+    /// RACK reordering window.
+    pub reorder: Option<Instant>,
+    /// Pacer release time for the next segment.
+    pub pace: Option<Instant>,
+    /// Next keepalive check or probe.
+    pub keepalive: Option<Instant>,
+    /// Unacknowledged data has been outstanding for the user timeout.
+    pub user_timeout: Option<Instant>,
+    /// An orphan has waited long enough for the peer's FIN.
+    pub fin_wait2: Option<Instant>,
+    /// A lingering close has waited its linger time.
+    pub linger: Option<Instant>,
+}
+
+impl ConnTimers {
+    pub fn next(&self) -> Option<Instant> {
+        [
+            self.rto,
+            self.tlp,
+            self.reorder,
+            self.pace,
+            self.keepalive,
+            self.user_timeout,
+            self.fin_wait2,
+            self.linger,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+}
+
+/// Sleep on `clock` until `at`, or forever if nothing is scheduled.
+pub async fn sleep_until(clock: &dyn Clock, at: Option<Instant>) {
+    match at {
+        Some(at) => clock.sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn next_slot_finds_the_cascade_point() {
+        let origin = Instant::now();
+        let mut w = TimerWheel::new(origin);
+        // 130 ticks is past level 0's window: level 1, slot 2.
+        w.schedule(1, origin + ms(130));
+        assert_eq!(w.next_slot(), Some((1, 2, 128)));
+        assert_eq!(w.next_deadline(), Some(origin + ms(128)));
+
+        // Reaching the slot moves the entry down, without firing it.
+        assert!(w.poll(origin + ms(128)).is_empty());
+        assert_eq!(w.next_slot(), Some((0, 2, 130)));
+        assert_eq!(w.poll(origin + ms(130)), [1]);
+        assert_eq!(w.next_slot(), None);
+        assert!(w.is_empty());
+    }
+
+    #[test]
+    fn next_slot_prefers_lower_levels() {
+        let origin = Instant::now();
+        let mut w = TimerWheel::new(origin);
+        w.poll(origin + ms(10));
+        w.schedule(1, origin + ms(5000));
+        w.schedule(2, origin + ms(40));
+        // Already past: due in the current slot.
+        w.schedule(3, origin + ms(3));
+        assert_eq!(w.next_slot(), Some((0, 10, 10)));
+        assert_eq!(w.poll(origin + ms(10)), [3]);
+        assert_eq!(w.next_slot(), Some((0, 40, 40)));
+    }
+
+    #[test]
+    fn fires_at_each_deadline_across_levels() {
+        let origin = Instant::now();
+        let mut w = TimerWheel::new(origin);
+        let deadlines = [1, 63, 64, 65, 200, 4095, 4096, 5000, 300_000, 20_000_000];
+        for (key, at) in deadlines.iter().enumerate() {
+            w.schedule(key, origin + ms(*at));
+        }
+        // Wake only when the wheel asks to, as the stack does.
+        let mut fired = Vec::new();
+        while let Some(at) = w.next_deadline() {
+            for key in w.poll(at) {
+                fired.push((key, at - origin));
+            }
+        }
+        let expected: Vec<_> = deadlines
+            .iter()
+            .enumerate()
+            .map(|(key, at)| (key, ms(*at)))
+            .collect();
+        assert_eq!(fired, expected);
+    }
+
+    #[test]
+    fn late_poll_fires_everything_due() {
+        let origin = Instant::now();
+        let mut w = TimerWheel::new(origin);
+        for (key, at) in [(1, 70), (2, 9000), (3, 9001), (4, 100_000)] {
+            w.schedule(key, origin + ms(at));
+        }
+        let mut due = w.poll(origin + ms(9000));
+        due.sort();
+        assert_eq!(due, [1, 2]);
+        assert_eq!(w.next_deadline(), Some(origin + ms(9001)));
+        assert_eq!(w.len(), 2);
+    }
+
+    #[test]
+    fn deadlines_round_up_to_the_tick() {
+        let origin = Instant::now();
+        let mut w = TimerWheel::new(origin);
+        w.schedule(1, origin + Duration::from_micros(1500));
+        assert!(w.poll(origin + ms(1)).is_empty());
+        assert_eq!(w.poll(origin + ms(2)), [1]);
+    }
+
+    #[test]
+    fn reschedule_and_cancel() {
+        let origin = Instant::now();
+        let mut w = TimerWheel::new(origin);
+        w.schedule(1, origin + ms(10));
+        w.schedule(2, origin + ms(20));
+        w.schedule(1, origin + ms(30));
+        w.cancel(&2);
+        assert_eq!(w.len(), 1);
+        assert!(w.poll(origin + ms(29)).is_empty());
+        assert_eq!(w.poll(origin + ms(30)), [1]);
+        // Freed entries are reused.
+        w.schedule(3, origin + ms(40));
+        assert_eq!(w.entries.len(), 2);
     }
 }
@@ -0,0 +1,359 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+
+use super::auth::AuthState;
+use super::conn::{Connection, MSL, Quad, RCV_WND, Segment, Timestamps, seq_lt};
+use crate::wire::ipv4::ECN_NOT_ECT;
+use crate::wire::tcp::{FLAG_ACK, FLAG_FIN, FLAG_RST, FLAG_SYN, TcpHeader, TcpOption, TcpView};
+
+/// An active open may take over a quad in TIME-WAIT once the old
+/// connection's last timestamp is this old (as Linux `tcp_tw_reuse`).
+pub const TIME_WAIT_REUSE_DELAY: Duration = Duration::from_secs(1);
+
+/// What is left of a connection in TIME-WAIT.
+#[derive(Debug, Clone)]
+struct TimeWait {
+    snd_nxt: u32,
+    rcv_nxt: u32,
+    ts: Option<Timestamps>,
+    /// When the peer was last heard from.
+    last_seen: Instant,
+    expires: Instant,
+    /// Segments still have to be signed and verified.
+    auth: Option<AuthState>,
+}
+
+/// How to answer a segment for a quad in TIME-WAIT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeWaitAction {
+    Drop,
+    /// Acknowledge it again, e.g. a retransmitted FIN.
+    Ack,
+    /// A SYN for a new incarnation: forget the entry and process it afresh.
+    Reopen,
+}
+
+/// Connections in TIME-WAIT, kept apart from full `Connection`s.
+#[derive(Debug)]
+pub struct TimeWaitTable {
+    entries: HashMap<Quad, TimeWait>,
+    /// Expiry order. Every entry lasts 2 * MSL, so insertion order is expiry
+    /// order; restarted entries are queued again and stale slots skipped.
+    queue: VecDeque<(Instant, Quad)>,
+    max: usize,
+}
+
+impl TimeWaitTable {
+    pub fn new(max: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            queue: VecDeque::new(),
+            max,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, id: &Quad) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    /// Keep what TIME-WAIT needs of `conn`. With the table full the
+    /// connection skips TIME-WAIT instead; returns whether it was kept.
+    pub fn insert(&mut self, conn: &Connection, now: Instant) -> bool {
+        if self.entries.len() >= self.max && !self.entries.contains_key(&conn.id) {
+            tracing::debug!(id = ?conn.id, "TIME-WAIT table full");
+            return false;
+        }
+        let expires = now + 2 * MSL;
+        self.entries.insert(
+            conn.id,
+            TimeWait {
+                snd_nxt: conn.snd_nxt,
+                rcv_nxt: conn.rcv_nxt,
+                ts: conn.ts,
+                last_seen: conn.last_activity,
+                expires,
+                auth: conn.auth.clone(),
+            },
+        );
+        self.queue.push_back((expires, conn.id));
+        true
+    }
+
+    /// RFC 9293 §3.10.7.4 TIME-WAIT processing, with RSTs ignored
+    /// (RFC 1337) and a SYN reopening the connection if its timestamp, or
+    /// without timestamps its sequence number, shows it is new (RFC 6191).
+    /// `None` if `id` is not in TIME-WAIT.
+    pub fn on_segment(
+        &mut self,
+        id: &Quad,
+        tv: &TcpView<'_>,
+        now: Instant,
+    ) -> Option<TimeWaitAction> {
+        let tw = self.entries.get_mut(id)?;
+        if (tv.flags & FLAG_RST) != 0 {
+            return Some(TimeWaitAction::Drop);
+        }
+        let ts_val = tv.options().find_map(|o| match o {
+            TcpOption::Timestamps { val, .. } => Some(val),
+            _ => None,
+        });
+        if tv.flags & (FLAG_SYN | FLAG_ACK) == FLAG_SYN {
+            let new = match (tw.ts, ts_val) {
+                (Some(ts), Some(val)) => seq_lt(ts.recent, val),
+                _ => seq_lt(tw.rcv_nxt, tv.seq),
+            };
+            if new {
+                self.entries.remove(id);
+                return Some(TimeWaitAction::Reopen);
+            }
+            return Some(TimeWaitAction::Ack);
+        }
+        tw.last_seen = now;
+        if let (Some(ts), Some(val)) = (&mut tw.ts, ts_val) {
+            ts.recent = val;
+        }
+        if (tv.flags & FLAG_FIN) != 0 {
+            // Our last ACK was lost: repeat it and restart the 2 * MSL wait.
+            tw.expires = now + 2 * MSL;
+            self.queue.push_back((tw.expires, *id));
+            return Some(TimeWaitAction::Ack);
+        }
+        if !tv.payload.is_empty() {
+            return Some(TimeWaitAction::Ack);
+        }
+        Some(TimeWaitAction::Drop)
+    }
+
+    /// Authentication of the connection `id` was; `None` if `id` is not in
+    /// TIME-WAIT.
+    pub fn auth(&mut self, id: &Quad) -> Option<&mut Option<AuthState>> {
+        self.entries.get_mut(id).map(|tw| &mut tw.auth)
+    }
+
+    /// The ACK that TIME-WAIT repeats.
+    pub fn ack(&self, id: &Quad, now: Instant) -> Option<Segment> {
+        let tw = self.entries.get(id)?;
+        let mut hdr = TcpHeader {
+            src_port: id.src_port,
+            dst_port: id.dst_port,
+            seq: tw.snd_nxt,
+            ack: tw.rcv_nxt,
+            data_offset: 5,
+            flags: FLAG_ACK,
+            window: RCV_WND as u16,
+            urg_ptr: 0,
+            options: BytesMut::new(),
+        };
+        if let Some(ts) = tw.ts {
+            hdr.set_options(&[TcpOption::Timestamps {
+                val: ts.val(now),
+                ecr: ts.recent,
+            }]);
+        }
+        Some(Segment {
+            hdr,
+            ecn: ECN_NOT_ECT,
+            payload: Vec::new(),
+        })
+    }
+
+    /// Let an active open take over `id` if timestamps will tell its
+    /// segments from the old connection's: the old one used them and has
+    /// been quiet for `TIME_WAIT_REUSE_DELAY`. Returns the old `snd_nxt`,
+    /// above which the new ISS should start.
+    pub fn reuse(&mut self, id: &Quad, now: Instant) -> Option<u32> {
+        let tw = self.entries.get(id)?;
+        if tw.ts.is_none() || now.saturating_duration_since(tw.last_seen) < TIME_WAIT_REUSE_DELAY {
+            return None;
+        }
+        let snd_nxt = tw.snd_nxt;
+        self.entries.remove(id);
+        Some(snd_nxt)
+    }
+
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.queue.front().map(|(t, _)| *t)
+    }
+
+    /// Drop entries whose 2 * MSL is up.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some(&(t, id)) = self.queue.front() {
+            if t > now {
+                break;
+            }
+            self.queue.pop_front();
+            if self.entries.get(&id).is_some_and(|tw| tw.expires == t) {
+                self.entries.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::tcp::conn::State;
+    use crate::wire::tcp;
+
+    fn quad(dst_port: u16) -> Quad {
+        Quad {
+            src_ip: [10, 0, 0, 2].into(),
+            src_port: 80,
+            dst_ip: [10, 0, 0, 9].into(),
+            dst_port,
+        }
+    }
+
+    /// A connection in TIME-WAIT, with timestamps if `recent` is given.
+    fn conn(id: Quad, recent: Option<u32>, now: Instant) -> Connection {
+        let (app_rx, _) = mpsc::channel(1);
+        let (_, app_tx) = mpsc::channel(1);
+        let mut c = Connection::new(id, State::TimeWait, app_rx, app_tx, Arc::new(SystemClock));
+        c.snd_nxt = 5000;
+        c.rcv_nxt = 1000;
+        c.ts = recent.map(|recent| Timestamps { epoch: now, recent });
+        c.last_activity = now;
+        c
+    }
+
+    /// Encode a segment from the peer, to be parsed again.
+    fn segment(seq: u32, flags: u16, ts_val: Option<u32>) -> BytesMut {
+        let mut hdr = TcpHeader {
+            src_port: 4000,
+            dst_port: 80,
+            seq,
+            ack: 5000,
+            data_offset: 5,
+            flags,
+            window: 65535,
+            urg_ptr: 0,
+            options: BytesMut::new(),
+        };
+        if let Some(val) = ts_val {
+            hdr.set_options(&[TcpOption::Timestamps { val, ecr: 0 }]);
+        }
+        hdr.encode(&[], [10, 0, 0, 9].into(), [10, 0, 0, 2].into())
+    }
+
+    fn on_segment(
+        table: &mut TimeWaitTable,
+        id: &Quad,
+        seg: &[u8],
+        now: Instant,
+    ) -> Option<TimeWaitAction> {
+        table.on_segment(id, &tcp::parse(seg).expect("tcp"), now)
+    }
+
+    #[test]
+    fn rst_is_ignored() {
+        let now = Instant::now();
+        let id = quad(4000);
+        let mut table = TimeWaitTable::new(8);
+        assert!(table.insert(&conn(id, None, now), now));
+        let rst = segment(1000, FLAG_RST, None);
+        assert_eq!(
+            on_segment(&mut table, &id, &rst, now),
+            Some(TimeWaitAction::Drop)
+        );
+        // RFC 1337: the entry stays for its full 2 * MSL.
+        assert!(table.contains(&id));
+        assert_eq!(table.next_expiry(), Some(now + 2 * MSL));
+    }
+
+    #[test]
+    fn syn_with_a_newer_timestamp_reopens() {
+        let now = Instant::now();
+        let id = quad(4000);
+        let mut table = TimeWaitTable::new(8);
+        table.insert(&conn(id, Some(700), now), now);
+        // An older or equal timestamp is a duplicate, even with a higher
+        // sequence number.
+        let old = segment(9000, FLAG_SYN, Some(700));
+        assert_eq!(
+            on_segment(&mut table, &id, &old, now),
+            Some(TimeWaitAction::Ack)
+        );
+        assert!(table.contains(&id));
+
+        let new = segment(10, FLAG_SYN, Some(701));
+        assert_eq!(
+            on_segment(&mut table, &id, &new, now),
+            Some(TimeWaitAction::Reopen)
+        );
+        assert!(!table.contains(&id));
+    }
+
+    #[test]
+    fn syn_without_timestamps_reopens_above_rcv_nxt() {
+        let now = Instant::now();
+        let id = quad(4000);
+        let mut table = TimeWaitTable::new(8);
+        table.insert(&conn(id, None, now), now);
+        let old = segment(1000, FLAG_SYN, None);
+        assert_eq!(
+            on_segment(&mut table, &id, &old, now),
+            Some(TimeWaitAction::Ack)
+        );
+
+        let new = segment(1001, FLAG_SYN, None);
+        assert_eq!(
+            on_segment(&mut table, &id, &new, now),
+            Some(TimeWaitAction::Reopen)
+        );
+        assert!(!table.contains(&id));
+    }
+
+    #[test]
+    fn fin_is_acked_again_and_restarts_the_wait() {
+        let now = Instant::now();
+        let id = quad(4000);
+        let mut table = TimeWaitTable::new(8);
+        table.insert(&conn(id, Some(700), now), now);
+
+        let later = now + MSL;
+        let fin = segment(999, FLAG_FIN | FLAG_ACK, Some(800));
+        assert_eq!(
+            on_segment(&mut table, &id, &fin, later),
+            Some(TimeWaitAction::Ack)
+        );
+        let ack = table.ack(&id, later).expect("in TIME-WAIT");
+        assert_eq!((ack.hdr.seq, ack.hdr.ack), (5000, 1000));
+        assert!(ack.hdr.options.len() >= 10);
+
+        // The first expiry no longer applies; the restarted one does.
+        table.expire(now + 2 * MSL);
+        assert!(table.contains(&id));
+        table.expire(later + 2 * MSL);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn full_table_skips_time_wait() {
+        let now = Instant::now();
+        let mut table = TimeWaitTable::new(2);
+        assert!(table.insert(&conn(quad(4000), None, now), now));
+        assert!(table.insert(&conn(quad(4001), None, now), now));
+        assert!(!table.insert(&conn(quad(4002), None, now), now));
+        assert_eq!(table.len(), 2);
+        // Not in the table, so processed as for any unknown quad.
+        let syn = segment(1, FLAG_SYN, None);
+        assert_eq!(on_segment(&mut table, &quad(4002), &syn, now), None);
+        // A quad already there is refreshed in place.
+        assert!(table.insert(&conn(quad(4000), None, now), now));
+    }
+}
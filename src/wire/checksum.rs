@@ -1,3 +1,5 @@
+use std::net::{IpAddr, Ipv6Addr};
+
 /// RFC 1071 16-bit one's complement checksum.
 pub fn ones_complement(data: &[u8]) -> u16 {
     let mut sum: u32 = 0;
@@ -14,3 +16,31 @@ pub fn ones_complement(data: &[u8]) -> u16 {
     }
     !(sum as u16)
 }
+
+/// The pseudo-header upper-layer checksums cover: RFC 793's for IPv4,
+/// RFC 8200 §8.1's for IPv6.
+pub fn pseudo_header(src: IpAddr, dst: IpAddr, proto: u8, len: usize) -> Vec<u8> {
+    let mut p = Vec::with_capacity(40);
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            p.extend_from_slice(&src.octets());
+            p.extend_from_slice(&dst.octets());
+            p.extend_from_slice(&[0, proto]);
+            p.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            p.extend_from_slice(&to_v6(src).octets());
+            p.extend_from_slice(&to_v6(dst).octets());
+            p.extend_from_slice(&(len as u32).to_be_bytes());
+            p.extend_from_slice(&[0, 0, 0, proto]);
+        }
+    }
+    p
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
@@ -0,0 +1,153 @@
+use bytes::{BufMut, BytesMut};
+
+use super::checksum::ones_complement;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_DEST_UNREACHABLE: u8 = 3;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+/// Destination unreachable: fragmentation needed and DF set (RFC 1191).
+pub const CODE_FRAG_NEEDED: u8 = 4;
+
+/// An ICMPv4 message to send.
+#[derive(Clone, Debug)]
+pub struct IcmpHeader {
+    pub ty: u8,
+    pub code: u8,
+    /// The four bytes after the checksum, whose meaning depends on the type.
+    pub rest: [u8; 4],
+}
+
+impl IcmpHeader {
+    /// An echo request or reply (RFC 792).
+    pub fn echo(ty: u8, ident: u16, seq: u16) -> Self {
+        let mut rest = [0; 4];
+        rest[..2].copy_from_slice(&ident.to_be_bytes());
+        rest[2..].copy_from_slice(&seq.to_be_bytes());
+        Self { ty, code: 0, rest }
+    }
+
+    pub fn encode(&self, payload: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(8 + payload.len());
+        buf.put_u8(self.ty);
+        buf.put_u8(self.code);
+        buf.put_u16(0); // checksum placeholder
+        buf.extend_from_slice(&self.rest);
+        buf.extend_from_slice(payload);
+        let cksum = ones_complement(&buf);
+        buf[2..4].copy_from_slice(&cksum.to_be_bytes());
+        buf
+    }
+}
+
+/// An ICMPv4 message with a valid checksum.
+pub struct IcmpView<'a> {
+    pub ty: u8,
+    pub code: u8,
+    /// The four bytes after the checksum, whose meaning depends on the type.
+    pub rest: [u8; 4],
+    pub payload: &'a [u8],
+}
+
+impl IcmpView<'_> {
+    /// Identifier and sequence number of an echo request or reply.
+    pub fn echo(&self) -> Option<(u16, u16)> {
+        matches!(self.ty, TYPE_ECHO_REQUEST | TYPE_ECHO_REPLY).then(|| {
+            (
+                u16::from_be_bytes([self.rest[0], self.rest[1]]),
+                u16::from_be_bytes([self.rest[2], self.rest[3]]),
+            )
+        })
+    }
+
+    /// Next-hop MTU of a Fragmentation Needed message; zero from routers
+    /// predating RFC 1191.
+    pub fn next_hop_mtu(&self) -> u16 {
+        u16::from_be_bytes([self.rest[2], self.rest[3]])
+    }
+
+    /// The datagram an error message was sent about, as far as quoted.
+    pub fn quoted(&self) -> Option<Quoted<'_>> {
+        let p = self.payload;
+        if p.len() < 20 || p[0] >> 4 != 4 {
+            return None;
+        }
+        let ihl_bytes = (p[0] & 0x0f) as usize * 4;
+        // RFC 792 quotes at least eight bytes past the header.
+        if ihl_bytes < 20 || p.len() < ihl_bytes + 8 {
+            return None;
+        }
+        Some(Quoted {
+            src: [p[12], p[13], p[14], p[15]],
+            dst: [p[16], p[17], p[18], p[19]],
+            proto: p[9],
+            total_len: u16::from_be_bytes([p[2], p[3]]),
+            transport: &p[ihl_bytes..],
+        })
+    }
+}
+
+/// Header and leading transport bytes of a datagram quoted in an ICMP error.
+pub struct Quoted<'a> {
+    pub src: [u8; 4],
+    pub dst: [u8; 4],
+    pub proto: u8,
+    /// Length of the original datagram, not of what was quoted.
+    pub total_len: u16,
+    pub transport: &'a [u8],
+}
+
+pub fn parse(msg: &[u8]) -> Option<IcmpView<'_>> {
+    if msg.len() < 8 || ones_complement(msg) != 0 {
+        return None;
+    }
+    Some(IcmpView {
+        ty: msg[0],
+        code: msg[1],
+        rest: [msg[4], msg[5], msg[6], msg[7]],
+        payload: &msg[8..],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_round_trips() {
+        let msg = IcmpHeader::echo(TYPE_ECHO_REQUEST, 0x1234, 7).encode(b"ping!");
+        assert_eq!(&msg[..8], [8, 0, 0xe5, 0xf3, 0x12, 0x34, 0, 7]);
+        let icmp = parse(&msg).expect("valid message");
+        assert_eq!((icmp.ty, icmp.code), (TYPE_ECHO_REQUEST, 0));
+        assert_eq!(icmp.echo(), Some((0x1234, 7)));
+        assert_eq!(icmp.payload, b"ping!");
+    }
+
+    #[test]
+    fn checksum_is_verified() {
+        let mut msg = IcmpHeader::echo(TYPE_ECHO_REPLY, 1, 1).encode(&[]);
+        assert_eq!(&msg[2..4], [0xff, 0xfd]);
+        msg[7] ^= 1;
+        assert!(parse(&msg).is_none());
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let msg = IcmpHeader::echo(TYPE_ECHO_REQUEST, 1, 1).encode(&[]);
+        for len in 0..8 {
+            assert!(parse(&msg[..len]).is_none());
+        }
+        // An error quoting less than a header and eight bytes has no quote.
+        let mut quote = [0u8; 27];
+        quote[0] = 0x45;
+        let msg = IcmpHeader {
+            ty: TYPE_DEST_UNREACHABLE,
+            code: CODE_FRAG_NEEDED,
+            rest: [0, 0, 0x05, 0xdc],
+        }
+        .encode(&quote);
+        let icmp = parse(&msg).expect("valid message");
+        assert_eq!(icmp.next_hop_mtu(), 1500);
+        assert_eq!(icmp.echo(), None);
+        assert!(icmp.quoted().is_none());
+    }
+}
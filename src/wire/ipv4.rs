@@ -3,6 +3,12 @@ use bytes::{BufMut, BytesMut};
 #[derive(Clone, Copy, Debug)]
 pub struct Ipv4Addr(pub [u8; 4]);
 
+/// ECN codepoints carried in the low two bits of the TOS byte (RFC 3168).
+pub const ECN_NOT_ECT: u8 = 0b00;
+pub const ECN_ECT1: u8 = 0b01;
+pub const ECN_ECT0: u8 = 0b10;
+pub const ECN_CE: u8 = 0b11;
+
 #[derive(Clone, Debug)]
 pub struct Ipv4Header {
     pub src: Ipv4Addr,
@@ -10,20 +16,52 @@ pub struct Ipv4Header {
     pub proto: u8, // 6 for TCP
     pub ident: u16,
     pub ttl: u8,
+    pub ecn: u8, // one of ECN_*
+    /// Set DF; without it `fragment` may split the payload.
+    pub dont_fragment: bool,
 }
 
 impl Ipv4Header {
     pub fn encode(&self, payload: &[u8]) -> BytesMut {
+        self.encode_fragment(payload, 0, false)
+    }
+
+    /// `payload` in datagrams of at most `mtu` bytes: one if it fits or DF
+    /// is set, else fragments of it (RFC 791 §3.2).
+    pub fn fragment(&self, payload: &[u8], mtu: usize) -> Vec<BytesMut> {
+        if self.dont_fragment || 20 + payload.len() <= mtu {
+            return vec![self.encode(payload)];
+        }
+        // Offsets count eight-byte units.
+        let chunk = (mtu.saturating_sub(20) / 8 * 8).max(8);
+        payload
+            .chunks(chunk)
+            .enumerate()
+            .map(|(i, data)| {
+                let offset = i * chunk;
+                self.encode_fragment(data, offset, offset + data.len() < payload.len())
+            })
+            .collect()
+    }
+
+    fn encode_fragment(&self, payload: &[u8], offset: usize, more: bool) -> BytesMut {
         let ihl = 5u8;
         let ver_ihl = (4u8 << 4) | ihl;
         let total_len = (ihl as usize * 4 + payload.len()) as u16;
 
         let mut buf = BytesMut::with_capacity(total_len as usize);
         buf.put_u8(ver_ihl);
-        buf.put_u8(0); // DSCP/ECN
+        buf.put_u8(self.ecn & 0x03); // DSCP/ECN
         buf.put_u16(total_len);
         buf.put_u16(self.ident);
-        buf.put_u16(0x4000); // flags/frag: DF
+        let mut frag = (offset / 8) as u16 & FRAG_OFFSET_MASK;
+        if self.dont_fragment {
+            frag |= IP_DF;
+        }
+        if more {
+            frag |= IP_MF;
+        }
+        buf.put_u16(frag); // flags/frag
         buf.put_u8(self.ttl);
         buf.put_u8(self.proto);
         buf.put_u16(0); // checksum placeholder
@@ -38,14 +76,188 @@ impl Ipv4Header {
     }
 }
 
+/// Flags in the fragment field.
+pub const IP_DF: u16 = 0x4000;
+pub const IP_MF: u16 = 0x2000;
+const FRAG_OFFSET_MASK: u16 = 0x1fff;
+
+/// IP option kinds (RFC 791 and successors). The top bit says whether
+/// fragments carry the option too.
+pub const IPOPT_END: u8 = 0;
+pub const IPOPT_NOP: u8 = 1;
+pub const IPOPT_RR: u8 = 7;
+pub const IPOPT_TS: u8 = 68;
+pub const IPOPT_SEC: u8 = 130;
+pub const IPOPT_LSRR: u8 = 131;
+pub const IPOPT_SID: u8 = 136;
+pub const IPOPT_SSRR: u8 = 137;
+pub const IPOPT_RA: u8 = 148;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ipv4Option<'a> {
+    /// `pointer` is one-based, into the option including its header.
+    RecordRoute {
+        pointer: u8,
+        route: &'a [u8],
+    },
+    Timestamp {
+        pointer: u8,
+        overflow: u8,
+        flags: u8,
+        data: &'a [u8],
+    },
+    LooseSourceRoute {
+        pointer: u8,
+        route: &'a [u8],
+    },
+    StrictSourceRoute {
+        pointer: u8,
+        route: &'a [u8],
+    },
+    /// RFC 1108 security label.
+    Security(&'a [u8]),
+    StreamId(u16),
+    /// RFC 2113.
+    RouterAlert(u16),
+    Unknown {
+        kind: u8,
+        data: &'a [u8],
+    },
+}
+
+impl Ipv4Option<'_> {
+    /// Whether fragments of the datagram repeat the option.
+    pub fn copied(&self) -> bool {
+        self.kind() & 0x80 != 0
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            Ipv4Option::RecordRoute { .. } => IPOPT_RR,
+            Ipv4Option::Timestamp { .. } => IPOPT_TS,
+            Ipv4Option::LooseSourceRoute { .. } => IPOPT_LSRR,
+            Ipv4Option::StrictSourceRoute { .. } => IPOPT_SSRR,
+            Ipv4Option::Security(_) => IPOPT_SEC,
+            Ipv4Option::StreamId(_) => IPOPT_SID,
+            Ipv4Option::RouterAlert(_) => IPOPT_RA,
+            Ipv4Option::Unknown { kind, .. } => *kind,
+        }
+    }
+}
+
+/// Iterator over the options of a parsed header.
+pub struct Ipv4OptionsIter<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for Ipv4OptionsIter<'a> {
+    type Item = Ipv4Option<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&kind, rest) = self.buf.split_first()?;
+            match kind {
+                IPOPT_END => {
+                    self.buf = &[];
+                    return None;
+                }
+                IPOPT_NOP => {
+                    self.buf = rest;
+                    continue;
+                }
+                _ => {}
+            }
+            // Lengths were checked by `parse_ipv4`.
+            let len = rest[0] as usize;
+            let data = &self.buf[2..len];
+            self.buf = &self.buf[len..];
+            return Some(match (kind, data.len()) {
+                (IPOPT_RR, n) if n >= 1 => Ipv4Option::RecordRoute {
+                    pointer: data[0],
+                    route: &data[1..],
+                },
+                (IPOPT_TS, n) if n >= 2 => Ipv4Option::Timestamp {
+                    pointer: data[0],
+                    overflow: data[1] >> 4,
+                    flags: data[1] & 0x0f,
+                    data: &data[2..],
+                },
+                (IPOPT_LSRR, n) if n >= 1 => Ipv4Option::LooseSourceRoute {
+                    pointer: data[0],
+                    route: &data[1..],
+                },
+                (IPOPT_SSRR, n) if n >= 1 => Ipv4Option::StrictSourceRoute {
+                    pointer: data[0],
+                    route: &data[1..],
+                },
+                (IPOPT_SEC, _) => Ipv4Option::Security(data),
+                (IPOPT_SID, 2) => Ipv4Option::StreamId(u16::from_be_bytes([data[0], data[1]])),
+                (IPOPT_RA, 2) => Ipv4Option::RouterAlert(u16::from_be_bytes([data[0], data[1]])),
+                _ => Ipv4Option::Unknown { kind, data },
+            });
+        }
+    }
+}
+
+/// Whether `opts` is a sequence of well-formed options.
+fn options_well_formed(mut opts: &[u8]) -> bool {
+    while let Some((&kind, rest)) = opts.split_first() {
+        match kind {
+            IPOPT_END => return true,
+            IPOPT_NOP => opts = rest,
+            _ => {
+                let Some(&len) = rest.first() else {
+                    return false;
+                };
+                let len = len as usize;
+                if len < 2 || opts.len() < len {
+                    return false;
+                }
+                opts = &opts[len..];
+            }
+        }
+    }
+    true
+}
+
+/// A received IPv4 datagram whose header checked out.
+#[derive(Debug, Clone, Copy)]
 pub struct Ipv4View<'a> {
     pub src: [u8; 4],
     pub dst: [u8; 4],
     pub proto: u8,
+    pub dscp: u8,
+    pub ecn: u8,
+    pub total_len: u16,
+    pub ident: u16,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    /// Where this fragment's data goes in the datagram, in bytes.
+    pub frag_offset: usize,
+    pub ttl: u8,
+    pub checksum: u16,
     pub ihl_bytes: usize,
+    /// The raw header, options included.
+    pub header: &'a [u8],
+    /// Raw options; see `options`.
+    pub options: &'a [u8],
     pub payload: &'a [u8],
 }
 
+impl<'a> Ipv4View<'a> {
+    pub fn options(&self) -> Ipv4OptionsIter<'a> {
+        Ipv4OptionsIter { buf: self.options }
+    }
+
+    /// Part of a datagram that was fragmented.
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments || self.frag_offset > 0
+    }
+}
+
+/// Parse and validate an IPv4 header: version, lengths, checksum and the
+/// option list's framing. Trailing bytes past the total length, e.g. link
+/// layer padding, are ignored.
 pub fn parse_ipv4(frame: &[u8]) -> Option<Ipv4View<'_>> {
     if frame.len() < 20 {
         return None;
@@ -59,19 +271,152 @@ pub fn parse_ipv4(frame: &[u8]) -> Option<Ipv4View<'_>> {
     if ihl_bytes < 20 || frame.len() < ihl_bytes {
         return None;
     }
-    let total_len = u16::from_be_bytes([frame[2], frame[3]]) as usize;
-    if total_len > frame.len() {
+    let total_len = u16::from_be_bytes([frame[2], frame[3]]);
+    if (total_len as usize) < ihl_bytes || total_len as usize > frame.len() {
         return None;
     }
-    let proto = frame[9];
-    let src = [frame[12], frame[13], frame[14], frame[15]];
-    let dst = [frame[16], frame[17], frame[18], frame[19]];
-    let payload = &frame[ihl_bytes..total_len];
+    if super::checksum::ones_complement(&frame[..ihl_bytes]) != 0 {
+        return None;
+    }
+    let options = &frame[20..ihl_bytes];
+    if !options_well_formed(options) {
+        return None;
+    }
+    let frag = u16::from_be_bytes([frame[6], frame[7]]);
     Some(Ipv4View {
-        src,
-        dst,
-        proto,
+        src: [frame[12], frame[13], frame[14], frame[15]],
+        dst: [frame[16], frame[17], frame[18], frame[19]],
+        proto: frame[9],
+        dscp: frame[1] >> 2,
+        ecn: frame[1] & 0x03,
+        total_len,
+        ident: u16::from_be_bytes([frame[4], frame[5]]),
+        dont_fragment: frag & IP_DF != 0,
+        more_fragments: frag & IP_MF != 0,
+        frag_offset: (frag & FRAG_OFFSET_MASK) as usize * 8,
+        ttl: frame[8],
+        checksum: u16::from_be_bytes([frame[10], frame[11]]),
         ihl_bytes,
-        payload,
+        header: &frame[..ihl_bytes],
+        options,
+        payload: &frame[ihl_bytes..total_len as usize],
     })
 }
+
+/// The datagram a reassembled payload came in: the first fragment's
+/// `header` with the fragment fields cleared and lengths, ECN and checksum
+/// fixed up.
+pub fn defragmented(header: &[u8], payload: &[u8], ce: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(header.len() + payload.len());
+    buf.extend_from_slice(header);
+    let total_len = (header.len() + payload.len()).min(u16::MAX as usize) as u16;
+    buf[2..4].copy_from_slice(&total_len.to_be_bytes());
+    buf[6..8].copy_from_slice(&0u16.to_be_bytes());
+    if ce {
+        buf[1] |= ECN_CE;
+    }
+    buf[10..12].copy_from_slice(&[0, 0]);
+    let cksum = super::checksum::ones_complement(&buf);
+    buf[10..12].copy_from_slice(&cksum.to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A datagram with `opts`, padded to a multiple of four bytes, and a
+    /// four-byte payload.
+    fn datagram(opts: &[u8]) -> Vec<u8> {
+        let mut opts = opts.to_vec();
+        opts.resize(opts.len().next_multiple_of(4), IPOPT_END);
+        let ihl = 20 + opts.len();
+        let mut buf = vec![0; ihl];
+        buf[0] = 0x40 | (ihl / 4) as u8;
+        buf[2..4].copy_from_slice(&((ihl + 4) as u16).to_be_bytes());
+        buf[8] = 64;
+        buf[9] = 17;
+        buf[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        buf[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        buf[20..].copy_from_slice(&opts);
+        let cksum = crate::wire::checksum::ones_complement(&buf);
+        buf[10..12].copy_from_slice(&cksum.to_be_bytes());
+        buf.extend_from_slice(b"data");
+        buf
+    }
+
+    #[test]
+    fn options_in_order_skipping_nops() {
+        let frame = datagram(&[
+            IPOPT_NOP, IPOPT_RR, 7, 4, 1, 2, 3, 4, IPOPT_NOP, IPOPT_RA, 4, 0, 0,
+        ]);
+        let ip = parse_ipv4(&frame).unwrap();
+        let opts: Vec<_> = ip.options().collect();
+        assert_eq!(
+            opts,
+            [
+                Ipv4Option::RecordRoute {
+                    pointer: 4,
+                    route: &[1, 2, 3, 4],
+                },
+                Ipv4Option::RouterAlert(0),
+            ]
+        );
+        assert!(!opts[0].copied());
+        assert!(opts[1].copied());
+        assert_eq!(ip.payload, b"data");
+    }
+
+    #[test]
+    fn timestamp_fields() {
+        let frame = datagram(&[IPOPT_TS, 8, 5, 0x21, 9, 9, 9, 9]);
+        let ip = parse_ipv4(&frame).unwrap();
+        assert_eq!(
+            ip.options().collect::<Vec<_>>(),
+            [Ipv4Option::Timestamp {
+                pointer: 5,
+                overflow: 2,
+                flags: 1,
+                data: &[9, 9, 9, 9],
+            }]
+        );
+    }
+
+    #[test]
+    fn end_of_list_stops_iteration() {
+        // Whatever follows End of Options List is padding, however it looks.
+        let frame = datagram(&[IPOPT_SID, 4, 0, 7, IPOPT_END, 1, 200, 3]);
+        let ip = parse_ipv4(&frame).unwrap();
+        assert_eq!(ip.options().collect::<Vec<_>>(), [Ipv4Option::StreamId(7)]);
+    }
+
+    #[test]
+    fn unexpected_lengths_are_unknown() {
+        let frame = datagram(&[IPOPT_SID, 3, 1, 30, 4, 5, 6]);
+        let ip = parse_ipv4(&frame).unwrap();
+        assert_eq!(
+            ip.options().collect::<Vec<_>>(),
+            [
+                Ipv4Option::Unknown {
+                    kind: IPOPT_SID,
+                    data: &[1],
+                },
+                Ipv4Option::Unknown {
+                    kind: 30,
+                    data: &[5, 6],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_options_reject_the_datagram() {
+        // Length below the two-byte minimum.
+        assert!(parse_ipv4(&datagram(&[IPOPT_RR, 1, 0, 0])).is_none());
+        // Length past the end of the header.
+        assert!(parse_ipv4(&datagram(&[IPOPT_NOP, IPOPT_RR, 7, 4])).is_none());
+        // Kind without a length.
+        assert!(parse_ipv4(&datagram(&[IPOPT_NOP, IPOPT_NOP, IPOPT_NOP, IPOPT_SEC])).is_none());
+    }
+}
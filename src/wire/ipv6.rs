@@ -0,0 +1,471 @@
+use std::net::Ipv6Addr;
+
+use bytes::{BufMut, BytesMut};
+
+/// Length of the fixed header.
+pub const IPV6_HEADER_LEN: usize = 40;
+/// Every link carries at least this much (RFC 8200 §5).
+pub const IPV6_MIN_MTU: usize = 1280;
+
+/// The fixed IPv6 header (RFC 8200 §3).
+#[derive(Clone, Debug)]
+pub struct Ipv6Header {
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+    pub next_header: u8, // 6 for TCP
+    pub hop_limit: u8,
+    pub ecn: u8, // one of ECN_*
+    /// Low 20 bits only.
+    pub flow_label: u32,
+}
+
+impl Ipv6Header {
+    pub fn encode(&self, payload: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(IPV6_HEADER_LEN + payload.len());
+        // Version, traffic class (DSCP 0, ECN) and flow label.
+        buf.put_u32(6 << 28 | ((self.ecn & 0x03) as u32) << 20 | (self.flow_label & 0xf_ffff));
+        buf.put_u16(payload.len() as u16);
+        buf.put_u8(self.next_header);
+        buf.put_u8(self.hop_limit);
+        buf.extend_from_slice(&self.src.octets());
+        buf.extend_from_slice(&self.dst.octets());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    /// `payload` in packets of at most `mtu` bytes: one if it fits, else
+    /// fragments of it identified by `ident` (RFC 8200 §4.5).
+    pub fn fragment(&self, payload: &[u8], mtu: usize, ident: u32) -> Vec<BytesMut> {
+        if IPV6_HEADER_LEN + payload.len() <= mtu {
+            return vec![self.encode(payload)];
+        }
+        let hdr = Ipv6Header {
+            next_header: NEXT_FRAGMENT,
+            ..self.clone()
+        };
+        // Offsets count eight-byte units.
+        let chunk = (mtu.saturating_sub(IPV6_HEADER_LEN + 8) / 8 * 8).max(8);
+        payload
+            .chunks(chunk)
+            .enumerate()
+            .map(|(i, data)| {
+                let offset = i * chunk;
+                let mut field = offset as u16 & FRAG_OFFSET_MASK;
+                if offset + data.len() < payload.len() {
+                    field |= FRAG_M;
+                }
+                let mut frag = Vec::with_capacity(8 + data.len());
+                frag.extend_from_slice(&[self.next_header, 0]);
+                frag.extend_from_slice(&field.to_be_bytes());
+                frag.extend_from_slice(&ident.to_be_bytes());
+                frag.extend_from_slice(data);
+                hdr.encode(&frag)
+            })
+            .collect()
+    }
+}
+
+/// View of the fixed header; `payload` starts right after it, with any
+/// extension headers.
+pub struct Ipv6View<'a> {
+    pub src: [u8; 16],
+    pub dst: [u8; 16],
+    pub dscp: u8,
+    pub ecn: u8,
+    pub flow_label: u32,
+    pub payload_len: u16,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    /// The raw fixed header.
+    pub header: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+/// Parse an IPv6 header. Trailing bytes past the payload length, e.g. link
+/// layer padding, are ignored; jumbograms are not supported.
+pub fn parse_ipv6(frame: &[u8]) -> Option<Ipv6View<'_>> {
+    if frame.len() < IPV6_HEADER_LEN || frame[0] >> 4 != 6 {
+        return None;
+    }
+    let first = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+    let traffic_class = (first >> 20) as u8;
+    let payload_len = u16::from_be_bytes([frame[4], frame[5]]);
+    let end = IPV6_HEADER_LEN + payload_len as usize;
+    if end > frame.len() {
+        return None;
+    }
+    let mut src = [0; 16];
+    src.copy_from_slice(&frame[8..24]);
+    let mut dst = [0; 16];
+    dst.copy_from_slice(&frame[24..40]);
+    Some(Ipv6View {
+        src,
+        dst,
+        dscp: traffic_class >> 2,
+        ecn: traffic_class & 0x03,
+        flow_label: first & 0xf_ffff,
+        payload_len,
+        next_header: frame[6],
+        hop_limit: frame[7],
+        header: &frame[..IPV6_HEADER_LEN],
+        payload: &frame[IPV6_HEADER_LEN..end],
+    })
+}
+
+/// Extension header types (RFC 8200 §4).
+pub const NEXT_HOP_BY_HOP: u8 = 0;
+pub const NEXT_ROUTING: u8 = 43;
+pub const NEXT_FRAGMENT: u8 = 44;
+pub const NEXT_NO_NEXT: u8 = 59;
+pub const NEXT_DEST_OPTS: u8 = 60;
+
+/// Fragment header flag: more fragments follow.
+const FRAG_M: u16 = 0x0001;
+const FRAG_OFFSET_MASK: u16 = 0xfff8;
+
+/// A Fragment header.
+#[derive(Clone, Copy, Debug)]
+pub struct FragmentHeader {
+    /// What the fragments carry, once put back together.
+    pub next_header: u8,
+    /// Where this fragment's data goes, in bytes.
+    pub offset: usize,
+    pub more: bool,
+    pub ident: u32,
+}
+
+/// Where the extension header chain of a packet ends.
+pub struct Extensions<'a> {
+    /// Upper-layer protocol of `payload`, or with `fragment` what the
+    /// fragments carry.
+    pub next_header: u8,
+    /// Extension headers ahead of `payload`, Fragment header excluded.
+    pub headers: &'a [u8],
+    /// Offset in `headers` of the last one's Next Header field, if any.
+    last_next_header: Option<usize>,
+    pub fragment: Option<FragmentHeader>,
+    /// The upper-layer data, or a fragment of it.
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv6View<'a> {
+    /// Walk the extension headers to the upper-layer header, or to a Fragment
+    /// header. `None` when the packet must be discarded: malformed headers,
+    /// hop-by-hop options not right after the IPv6 header, options or a
+    /// routing header that call for it (RFC 8200 §4.2, §4.4), atomic
+    /// fragments, and first fragments without the whole header chain
+    /// (RFC 7112). Headers not listed above are taken for upper layers.
+    pub fn extensions(&self) -> Option<Extensions<'a>> {
+        let buf = self.payload;
+        let mut nh = self.next_header;
+        let mut at = 0;
+        let mut last_next_header = None;
+        loop {
+            match nh {
+                NEXT_HOP_BY_HOP | NEXT_ROUTING | NEXT_DEST_OPTS => {
+                    if nh == NEXT_HOP_BY_HOP && at != 0 {
+                        return None;
+                    }
+                    let len = extension_len(&buf[at..])?;
+                    extension_ok(nh, &buf[at..at + len])?;
+                    last_next_header = Some(at);
+                    nh = buf[at];
+                    at += len;
+                }
+                NEXT_FRAGMENT => {
+                    let frag = buf.get(at..at + 8)?;
+                    let field = u16::from_be_bytes([frag[2], frag[3]]);
+                    let fragment = FragmentHeader {
+                        next_header: frag[0],
+                        offset: (field & FRAG_OFFSET_MASK) as usize,
+                        more: field & FRAG_M != 0,
+                        ident: u32::from_be_bytes([frag[4], frag[5], frag[6], frag[7]]),
+                    };
+                    // Only ever sent in response to forged ICMP (RFC 8021).
+                    if fragment.offset == 0 && !fragment.more {
+                        return None;
+                    }
+                    let payload = &buf[at + 8..];
+                    if fragment.offset == 0 && !header_chain_complete(fragment.next_header, payload)
+                    {
+                        return None;
+                    }
+                    return Some(Extensions {
+                        next_header: fragment.next_header,
+                        headers: &buf[..at],
+                        last_next_header,
+                        fragment: Some(fragment),
+                        payload,
+                    });
+                }
+                _ => {
+                    return Some(Extensions {
+                        next_header: nh,
+                        headers: &buf[..at],
+                        last_next_header,
+                        fragment: None,
+                        payload: &buf[at..],
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Extensions<'_> {
+    /// The unfragmentable part a reassembled packet starts with: the IPv6
+    /// header and `headers`, the last Next Header naming what the fragments
+    /// carry (RFC 8200 §4.5).
+    pub fn unfragmentable(&self, header: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(header.len() + self.headers.len());
+        buf.extend_from_slice(header);
+        buf.extend_from_slice(self.headers);
+        let at = self.last_next_header.map_or(6, |at| header.len() + at);
+        buf[at] = self.next_header;
+        buf
+    }
+}
+
+/// Length of the extension header at the start of `buf`, if all there.
+fn extension_len(buf: &[u8]) -> Option<usize> {
+    let len = (*buf.get(1)? as usize + 1) * 8;
+    (buf.len() >= len).then_some(len)
+}
+
+/// Whether an options or routing header lets the packet through.
+fn extension_ok(nh: u8, ext: &[u8]) -> Option<()> {
+    if nh == NEXT_ROUTING {
+        // We forward nothing, so only a routing header with no segments
+        // left is ignorable, whatever its type.
+        return (ext[3] == 0).then_some(());
+    }
+    let mut opts = &ext[2..];
+    while let Some(&ty) = opts.first() {
+        // Pad1
+        if ty == 0 {
+            opts = &opts[1..];
+            continue;
+        }
+        let len = 2 + *opts.get(1)? as usize;
+        if opts.len() < len {
+            return None;
+        }
+        // Anything but padding is unknown to us: the two high bits of the
+        // type say whether to skip it or discard the packet.
+        if ty != 1 && ty >> 6 != 0 {
+            return None;
+        }
+        opts = &opts[len..];
+    }
+    Some(())
+}
+
+/// Whether a first fragment holds the rest of the header chain through
+/// the upper-layer header (RFC 7112 §5).
+fn header_chain_complete(mut nh: u8, buf: &[u8]) -> bool {
+    let mut at = 0;
+    loop {
+        match nh {
+            NEXT_ROUTING | NEXT_DEST_OPTS => {
+                let Some(len) = extension_len(&buf[at..]) else {
+                    return false;
+                };
+                nh = buf[at];
+                at += len;
+            }
+            // Hop-by-hop options past the start, or fragments nested.
+            NEXT_HOP_BY_HOP | NEXT_FRAGMENT => return false,
+            _ => return buf.len() - at >= upper_header_len(nh),
+        }
+    }
+}
+
+/// Smallest upper-layer header of protocol `nh` we know of.
+fn upper_header_len(nh: u8) -> usize {
+    match nh {
+        6 => 20,
+        17 => 8,
+        58 => 4,
+        _ => 0,
+    }
+}
+
+/// The packet a reassembled payload came in: `unfragmentable` followed by
+/// `payload`, with the payload length and ECN fixed up.
+pub fn defragmented(unfragmentable: &[u8], payload: &[u8], ce: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(unfragmentable.len() + payload.len());
+    buf.extend_from_slice(unfragmentable);
+    let payload_len = (buf.len() - IPV6_HEADER_LEN + payload.len()).min(u16::MAX as usize) as u16;
+    buf[4..6].copy_from_slice(&payload_len.to_be_bytes());
+    if ce {
+        buf[1] |= super::ipv4::ECN_CE << 4;
+    }
+    buf.extend_from_slice(payload);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP: u8 = 6;
+
+    fn header(next_header: u8) -> Ipv6Header {
+        Ipv6Header {
+            src: "2001:db8::1".parse().unwrap(),
+            dst: "2001:db8::2".parse().unwrap(),
+            next_header,
+            hop_limit: 64,
+            ecn: 0,
+            flow_label: 0,
+        }
+    }
+
+    /// An eight-byte options header holding `opts`, padded with PadN.
+    fn options(next: u8, opts: &[u8]) -> Vec<u8> {
+        let mut buf = vec![next, 0];
+        buf.extend_from_slice(opts);
+        let pad = 8 - buf.len();
+        buf.extend_from_slice(&[1, pad as u8 - 2]);
+        buf.resize(8, 0);
+        buf
+    }
+
+    fn packet(next_header: u8, exts: &[Vec<u8>], upper: &[u8]) -> Vec<u8> {
+        let mut payload = exts.concat();
+        payload.extend_from_slice(upper);
+        header(next_header).encode(&payload).to_vec()
+    }
+
+    #[test]
+    fn no_extension_headers() {
+        let frame = packet(TCP, &[], &[0; 20]);
+        let ip = parse_ipv6(&frame).unwrap();
+        let ext = ip.extensions().unwrap();
+        assert_eq!(ext.next_header, TCP);
+        assert!(ext.headers.is_empty());
+        assert!(ext.fragment.is_none());
+        assert_eq!(ext.payload.len(), 20);
+    }
+
+    #[test]
+    fn walks_options_headers() {
+        let frame = packet(
+            NEXT_HOP_BY_HOP,
+            &[options(NEXT_DEST_OPTS, &[]), options(TCP, &[0, 0])],
+            b"upper",
+        );
+        let ip = parse_ipv6(&frame).unwrap();
+        let ext = ip.extensions().unwrap();
+        assert_eq!(ext.next_header, TCP);
+        assert_eq!(ext.headers.len(), 16);
+        assert_eq!(ext.payload, b"upper");
+    }
+
+    #[test]
+    fn hop_by_hop_only_first() {
+        let frame = packet(
+            NEXT_DEST_OPTS,
+            &[options(NEXT_HOP_BY_HOP, &[]), options(TCP, &[])],
+            &[],
+        );
+        assert!(parse_ipv6(&frame).unwrap().extensions().is_none());
+    }
+
+    #[test]
+    fn unknown_options_by_action_bits() {
+        // 00: skip the option.
+        let frame = packet(NEXT_DEST_OPTS, &[options(TCP, &[0x1e, 0])], &[]);
+        assert!(parse_ipv6(&frame).unwrap().extensions().is_some());
+        // Anything else: discard the packet.
+        for ty in [0x5e, 0x9e, 0xde] {
+            let frame = packet(NEXT_DEST_OPTS, &[options(TCP, &[ty, 0])], &[]);
+            assert!(parse_ipv6(&frame).unwrap().extensions().is_none());
+        }
+    }
+
+    #[test]
+    fn routing_header_with_segments_left() {
+        let mut routing = vec![TCP, 0, 0, 0, 0, 0, 0, 0];
+        let frame = packet(NEXT_ROUTING, std::slice::from_ref(&routing), &[]);
+        assert!(parse_ipv6(&frame).unwrap().extensions().is_some());
+        routing[3] = 1;
+        let frame = packet(NEXT_ROUTING, &[routing], &[]);
+        assert!(parse_ipv6(&frame).unwrap().extensions().is_none());
+    }
+
+    #[test]
+    fn truncated_extension_header() {
+        let mut frame = packet(NEXT_DEST_OPTS, &[options(TCP, &[])], &[]);
+        // Claims sixteen bytes, has eight.
+        frame[IPV6_HEADER_LEN + 1] = 1;
+        assert!(parse_ipv6(&frame).unwrap().extensions().is_none());
+    }
+
+    #[test]
+    fn small_payload_is_not_fragmented() {
+        let packets = header(TCP).fragment(&[7; 100], IPV6_MIN_MTU, 1);
+        assert_eq!(packets.len(), 1);
+        let ip = parse_ipv6(&packets[0]).unwrap();
+        assert!(ip.extensions().unwrap().fragment.is_none());
+    }
+
+    #[test]
+    fn fragments_round_trip() {
+        let payload: Vec<u8> = (0..4000u32).map(|i| i as u8).collect();
+        let packets = header(TCP).fragment(&payload, IPV6_MIN_MTU, 0x1234_5678);
+        assert_eq!(packets.len(), 4);
+        let mut joined = vec![0; payload.len()];
+        for (i, p) in packets.iter().enumerate() {
+            assert!(p.len() <= IPV6_MIN_MTU);
+            let ip = parse_ipv6(p).unwrap();
+            assert_eq!(ip.next_header, NEXT_FRAGMENT);
+            let ext = ip.extensions().unwrap();
+            let frag = ext.fragment.unwrap();
+            assert_eq!(ext.next_header, TCP);
+            assert_eq!(frag.ident, 0x1234_5678);
+            assert_eq!(frag.more, i + 1 < packets.len());
+            assert!(frag.offset.is_multiple_of(8));
+            joined[frag.offset..frag.offset + ext.payload.len()].copy_from_slice(ext.payload);
+        }
+        assert_eq!(joined, payload);
+    }
+
+    #[test]
+    fn atomic_fragments_are_discarded() {
+        let frag = vec![TCP, 0, 0, 0, 0, 0, 0, 1];
+        let frame = packet(NEXT_FRAGMENT, &[frag], &[0; 20]);
+        assert!(parse_ipv6(&frame).unwrap().extensions().is_none());
+    }
+
+    #[test]
+    fn first_fragment_needs_the_upper_header() {
+        // Offset 0, more fragments, and only half a TCP header.
+        let frag = vec![TCP, 0, 0, 1, 0, 0, 0, 1];
+        let frame = packet(NEXT_FRAGMENT, &[frag], &[0; 10]);
+        assert!(parse_ipv6(&frame).unwrap().extensions().is_none());
+    }
+
+    #[test]
+    fn defragmented_keeps_unfragmentable_headers() {
+        let frag = vec![TCP, 0, 0, 1, 0, 0, 0, 9];
+        let frame = packet(
+            NEXT_DEST_OPTS,
+            &[options(NEXT_FRAGMENT, &[]), frag],
+            &[5; 24],
+        );
+        let ip = parse_ipv6(&frame).unwrap();
+        let ext = ip.extensions().unwrap();
+        let unfrag = ext.unfragmentable(ip.header);
+        let whole = defragmented(&unfrag, &[5; 40], true);
+
+        let ip = parse_ipv6(&whole).unwrap();
+        assert_eq!(ip.next_header, NEXT_DEST_OPTS);
+        assert_eq!(ip.ecn, crate::wire::ipv4::ECN_CE);
+        let ext = ip.extensions().unwrap();
+        assert_eq!(ext.next_header, TCP);
+        assert!(ext.fragment.is_none());
+        assert_eq!(ext.headers.len(), 8);
+        assert_eq!(ext.payload, [5; 40]);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod checksum;
+pub mod icmpv4;
 pub mod ipv4;
+pub mod ipv6;
 pub mod tcp;
@@ -1,4 +1,9 @@
 use bytes::{BufMut, BytesMut};
+use std::net::IpAddr;
+use std::ops::Range;
+use std::time::Duration;
+
+use super::checksum::pseudo_header;
 
 #[derive(Clone, Debug, Default)]
 pub struct TcpHeader {
@@ -18,9 +23,307 @@ pub const FLAG_SYN: u16 = 0x02;
 pub const FLAG_RST: u16 = 0x04;
 pub const FLAG_PSH: u16 = 0x08;
 pub const FLAG_ACK: u16 = 0x10;
+pub const FLAG_URG: u16 = 0x20;
+pub const FLAG_ECE: u16 = 0x40;
+pub const FLAG_CWR: u16 = 0x80;
+
+pub const OPT_END: u8 = 0;
+pub const OPT_NOP: u8 = 1;
+pub const OPT_MSS: u8 = 2;
+pub const OPT_WSCALE: u8 = 3;
+pub const OPT_SACK_PERMITTED: u8 = 4;
+pub const OPT_SACK: u8 = 5;
+pub const OPT_TIMESTAMPS: u8 = 8;
+pub const OPT_MD5: u8 = 19;
+pub const OPT_USER_TIMEOUT: u8 = 28;
+pub const OPT_AO: u8 = 29;
+pub const OPT_FAST_OPEN: u8 = 34;
+pub const OPT_ENO: u8 = 69;
+
+/// A single TCP option, borrowed from the segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpOption<'a> {
+    Mss(u16),
+    WindowScale(u8),
+    SackPermitted,
+    /// Raw SACK blocks; see `sack_blocks`.
+    Sack(&'a [u8]),
+    Timestamps {
+        val: u32,
+        ecr: u32,
+    },
+    /// RFC 2385 MD5 signature.
+    Md5(&'a [u8]),
+    /// RFC 5925 authentication option.
+    Ao {
+        key_id: u8,
+        rnext_key_id: u8,
+        mac: &'a [u8],
+    },
+    /// RFC 5482 UTO: `timeout` is 15 bits, in minutes if `minutes` is set.
+    UserTimeout {
+        minutes: bool,
+        timeout: u16,
+    },
+    /// RFC 7413 cookie; empty to request one.
+    FastOpen(&'a [u8]),
+    /// RFC 8547 TCP-ENO suboptions.
+    Eno(&'a [u8]),
+    Unknown {
+        kind: u8,
+        data: &'a [u8],
+    },
+}
+
+impl TcpOption<'_> {
+    /// Append the wire form of this option (unpadded).
+    pub fn encode(&self, buf: &mut BytesMut) {
+        match *self {
+            TcpOption::Mss(mss) => {
+                buf.put_u8(OPT_MSS);
+                buf.put_u8(4);
+                buf.put_u16(mss);
+            }
+            TcpOption::WindowScale(shift) => {
+                buf.put_u8(OPT_WSCALE);
+                buf.put_u8(3);
+                buf.put_u8(shift);
+            }
+            TcpOption::SackPermitted => {
+                buf.put_u8(OPT_SACK_PERMITTED);
+                buf.put_u8(2);
+            }
+            TcpOption::Sack(blocks) => {
+                buf.put_u8(OPT_SACK);
+                buf.put_u8(2 + blocks.len() as u8);
+                buf.extend_from_slice(blocks);
+            }
+            TcpOption::Timestamps { val, ecr } => {
+                buf.put_u8(OPT_TIMESTAMPS);
+                buf.put_u8(10);
+                buf.put_u32(val);
+                buf.put_u32(ecr);
+            }
+            TcpOption::Md5(digest) => {
+                buf.put_u8(OPT_MD5);
+                buf.put_u8(2 + digest.len() as u8);
+                buf.extend_from_slice(digest);
+            }
+            TcpOption::Ao {
+                key_id,
+                rnext_key_id,
+                mac,
+            } => {
+                buf.put_u8(OPT_AO);
+                buf.put_u8(4 + mac.len() as u8);
+                buf.put_u8(key_id);
+                buf.put_u8(rnext_key_id);
+                buf.extend_from_slice(mac);
+            }
+            TcpOption::UserTimeout { minutes, timeout } => {
+                buf.put_u8(OPT_USER_TIMEOUT);
+                buf.put_u8(4);
+                buf.put_u16(((minutes as u16) << 15) | (timeout & 0x7fff));
+            }
+            TcpOption::FastOpen(cookie) => {
+                buf.put_u8(OPT_FAST_OPEN);
+                buf.put_u8(2 + cookie.len() as u8);
+                buf.extend_from_slice(cookie);
+            }
+            TcpOption::Eno(subopts) => {
+                buf.put_u8(OPT_ENO);
+                buf.put_u8(2 + subopts.len() as u8);
+                buf.extend_from_slice(subopts);
+            }
+            TcpOption::Unknown { kind, data } => {
+                buf.put_u8(kind);
+                buf.put_u8(2 + data.len() as u8);
+                buf.extend_from_slice(data);
+            }
+        }
+    }
+}
+
+/// Iterator over the options area of a TCP header. Stops at End-of-Options
+/// or at the first malformed option.
+pub struct OptionsIter<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for OptionsIter<'a> {
+    type Item = TcpOption<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&kind, rest) = self.buf.split_first()?;
+            match kind {
+                OPT_END => {
+                    self.buf = &[];
+                    return None;
+                }
+                OPT_NOP => {
+                    self.buf = rest;
+                    continue;
+                }
+                _ => {}
+            }
+            let len = *rest.first()? as usize;
+            if len < 2 || self.buf.len() < len {
+                self.buf = &[];
+                return None;
+            }
+            let data = &self.buf[2..len];
+            self.buf = &self.buf[len..];
+            return Some(match (kind, data.len()) {
+                (OPT_MSS, 2) => TcpOption::Mss(u16::from_be_bytes([data[0], data[1]])),
+                (OPT_WSCALE, 1) => TcpOption::WindowScale(data[0]),
+                (OPT_SACK_PERMITTED, 0) => TcpOption::SackPermitted,
+                (OPT_SACK, n) if n.is_multiple_of(8) => TcpOption::Sack(data),
+                (OPT_TIMESTAMPS, 8) => TcpOption::Timestamps {
+                    val: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+                    ecr: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+                },
+                (OPT_MD5, 16) => TcpOption::Md5(data),
+                (OPT_AO, n) if n >= 2 => TcpOption::Ao {
+                    key_id: data[0],
+                    rnext_key_id: data[1],
+                    mac: &data[2..],
+                },
+                (OPT_USER_TIMEOUT, 2) => {
+                    let raw = u16::from_be_bytes([data[0], data[1]]);
+                    TcpOption::UserTimeout {
+                        minutes: raw & 0x8000 != 0,
+                        timeout: raw & 0x7fff,
+                    }
+                }
+                (OPT_FAST_OPEN, n) if n == 0 || (4..=16).contains(&n) => TcpOption::FastOpen(data),
+                (OPT_ENO, _) => TcpOption::Eno(data),
+                _ => TcpOption::Unknown { kind, data },
+            });
+        }
+    }
+}
+
+/// UTO option for `timeout`: seconds while they fit in 15 bits, else
+/// minutes rounded up.
+pub fn user_timeout_option<'a>(timeout: Duration) -> TcpOption<'a> {
+    let secs = timeout.as_secs();
+    if secs <= 0x7fff {
+        TcpOption::UserTimeout {
+            minutes: false,
+            timeout: secs as u16,
+        }
+    } else {
+        TcpOption::UserTimeout {
+            minutes: true,
+            timeout: secs.div_ceil(60).min(0x7fff) as u16,
+        }
+    }
+}
+
+/// The timeout a UTO option carries.
+pub fn user_timeout_duration(minutes: bool, timeout: u16) -> Duration {
+    Duration::from_secs(timeout as u64 * if minutes { 60 } else { 1 })
+}
+
+/// Decode the (left, right) edges of raw SACK blocks.
+pub fn sack_blocks(raw: &[u8]) -> impl Iterator<Item = (u32, u32)> + '_ {
+    raw.chunks_exact(8).map(|b| {
+        (
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            u32::from_be_bytes([b[4], b[5], b[6], b[7]]),
+        )
+    })
+}
+
+/// Encode SACK blocks into the raw form carried by `TcpOption::Sack`.
+pub fn encode_sack_blocks(blocks: &[(u32, u32)]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(blocks.len() * 8);
+    for (l, r) in blocks {
+        raw.extend_from_slice(&l.to_be_bytes());
+        raw.extend_from_slice(&r.to_be_bytes());
+    }
+    raw
+}
+
+/// Fills in a segment's authentication option (TCP-AO or TCP-MD5).
+pub trait SegmentSigner {
+    /// The option with its MAC, which ends it, zeroed.
+    fn option(&self) -> Vec<u8>;
+    /// The MAC of `seg`, encoded with checksum and MAC zeroed.
+    fn sign(&self, seg: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> Vec<u8>;
+}
+
+/// The bytes of the first option of `kind` within the options area `opts`.
+pub fn find_option(opts: &[u8], kind: u8) -> Option<Range<usize>> {
+    let mut i = 0;
+    while i < opts.len() {
+        match opts[i] {
+            OPT_END => return None,
+            OPT_NOP => i += 1,
+            k => {
+                let len = *opts.get(i + 1)? as usize;
+                if len < 2 || i + len > opts.len() {
+                    return None;
+                }
+                if k == kind {
+                    return Some(i..i + len);
+                }
+                i += len;
+            }
+        }
+    }
+    None
+}
 
 impl TcpHeader {
-    pub fn encode(&self, payload: &[u8], src_ip: [u8; 4], dst_ip: [u8; 4]) -> BytesMut {
+    /// Encode `opts` into the options area, NOP-padded to a 32-bit boundary,
+    /// and set `data_offset` to match.
+    pub fn set_options(&mut self, opts: &[TcpOption<'_>]) {
+        let mut buf = BytesMut::new();
+        for opt in opts {
+            opt.encode(&mut buf);
+        }
+        while !buf.len().is_multiple_of(4) {
+            buf.put_u8(OPT_NOP);
+        }
+        self.data_offset = 5 + (buf.len() / 4) as u8;
+        self.options = buf;
+    }
+
+    pub fn encode(&self, payload: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> BytesMut {
+        let mut buf = self.write(payload);
+        set_checksum(&mut buf, src_ip, dst_ip);
+        buf
+    }
+
+    /// Encode with `signer`'s option ahead of the others and its MAC filled
+    /// in. The options must leave room for it.
+    pub fn encode_signed(
+        &self,
+        payload: &[u8],
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        signer: &dyn SegmentSigner,
+    ) -> BytesMut {
+        let mut hdr = self.clone();
+        let mut opts = BytesMut::from(&signer.option()[..]);
+        let mac_end = 20 + opts.len();
+        while !opts.len().is_multiple_of(4) {
+            opts.put_u8(OPT_NOP);
+        }
+        opts.extend_from_slice(&self.options);
+        hdr.data_offset = 5 + (opts.len() / 4) as u8;
+        hdr.options = opts;
+        let mut buf = hdr.write(payload);
+        let mac = signer.sign(&buf, src_ip, dst_ip);
+        buf[mac_end - mac.len()..mac_end].copy_from_slice(&mac);
+        set_checksum(&mut buf, src_ip, dst_ip);
+        buf
+    }
+
+    /// The segment with a zero checksum.
+    fn write(&self, payload: &[u8]) -> BytesMut {
         let hdr_len = (self.data_offset as usize) * 4;
         let total_len = hdr_len + payload.len();
         let mut buf = BytesMut::with_capacity(total_len);
@@ -37,24 +340,21 @@ impl TcpHeader {
             buf.extend_from_slice(&self.options);
         }
         buf.extend_from_slice(payload);
-
-        // pseudo-header checksum
-        let mut pseudo = BytesMut::with_capacity(12 + total_len);
-        pseudo.extend_from_slice(&src_ip);
-        pseudo.extend_from_slice(&dst_ip);
-        pseudo.put_u8(0);
-        pseudo.put_u8(6);
-        pseudo.put_u16(total_len as u16);
-        pseudo.extend_from_slice(&buf);
-
-        let cksum = super::checksum::ones_complement(&pseudo);
-        buf[16] = (cksum >> 8) as u8;
-        buf[17] = (cksum & 0xff) as u8;
         buf
     }
 }
 
-/// Minimal TCP view (no options parsing).
+/// Fill in the checksum of an encoded segment, over the pseudo-header.
+fn set_checksum(buf: &mut BytesMut, src_ip: IpAddr, dst_ip: IpAddr) {
+    let mut pseudo = pseudo_header(src_ip, dst_ip, 6, buf.len());
+    pseudo.extend_from_slice(buf);
+
+    let cksum = super::checksum::ones_complement(&pseudo);
+    buf[16] = (cksum >> 8) as u8;
+    buf[17] = (cksum & 0xff) as u8;
+}
+
+/// Minimal TCP view; options are left raw, see `options()`.
 pub struct TcpView<'a> {
     pub src_port: u16,
     pub dst_port: u16,
@@ -65,9 +365,16 @@ pub struct TcpView<'a> {
     pub window: u16,
     pub checksum: u16,
     pub urg_ptr: u16,
+    pub options: &'a [u8],
     pub payload: &'a [u8],
 }
 
+impl<'a> TcpView<'a> {
+    pub fn options(&self) -> OptionsIter<'a> {
+        OptionsIter { buf: self.options }
+    }
+}
+
 /// Parse a TCP segment. Returns `None` if too short or invalid header length.
 pub fn parse(seg: &[u8]) -> Option<TcpView<'_>> {
     if seg.len() < 20 {
@@ -87,6 +394,7 @@ pub fn parse(seg: &[u8]) -> Option<TcpView<'_>> {
     if hdr_len < 20 || seg.len() < hdr_len {
         return None;
     }
+    let options = &seg[20..hdr_len];
     let payload = &seg[hdr_len..];
     Some(TcpView {
         src_port,
@@ -98,13 +406,14 @@ pub fn parse(seg: &[u8]) -> Option<TcpView<'_>> {
         window,
         checksum,
         urg_ptr,
+        options,
         payload,
     })
 }
 
-/// Verify TCP checksum using IPv4 pseudo header.
+/// Verify TCP checksum using the IPv4 or IPv6 pseudo header.
 /// Returns `true` if checksum is valid.
-pub fn verify_checksum(seg: &[u8], src_ip: [u8; 4], dst_ip: [u8; 4]) -> bool {
+pub fn verify_checksum(seg: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> bool {
     if seg.len() < 20 {
         return false;
     }
@@ -113,12 +422,7 @@ pub fn verify_checksum(seg: &[u8], src_ip: [u8; 4], dst_ip: [u8; 4]) -> bool {
         return false;
     }
     // Build pseudo + TCP with checksum field as-is.
-    let mut pseudo = BytesMut::with_capacity(12 + seg.len());
-    pseudo.extend_from_slice(&src_ip);
-    pseudo.extend_from_slice(&dst_ip);
-    pseudo.put_u8(0);
-    pseudo.put_u8(6);
-    pseudo.put_u16(seg.len() as u16);
+    let mut pseudo = pseudo_header(src_ip, dst_ip, 6, seg.len());
     pseudo.extend_from_slice(seg);
     super::checksum::ones_complement(&pseudo) == 0
 }
@@ -0,0 +1,126 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::time::timeout;
+use urtcp::device::{LoopDevice, NetDevice};
+use urtcp::tcp::socket::{ListenOptions, TcpListener, TcpSocketAddr, TcpStream};
+use urtcp::wire::ipv4::parse_ipv4;
+use urtcp::wire::tcp::{self, FLAG_ACK, FLAG_SYN};
+use urtcp::{AoAlgorithm, ConnectOptions, MasterKey, Stack, StackConfig, TcpAuth};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Loopback end counting the SYNs it sends.
+struct SynCounter {
+    inner: LoopDevice,
+    syns: Arc<AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl NetDevice for SynCounter {
+    async fn recv(&self) -> urtcp::error::Result<BytesMut> {
+        self.inner.recv().await
+    }
+
+    async fn send(&self, frame: &[u8]) -> urtcp::error::Result<()> {
+        if let Some(ip) = parse_ipv4(frame)
+            && let Some(tv) = tcp::parse(ip.payload)
+            && tv.flags & (FLAG_SYN | FLAG_ACK) == FLAG_SYN
+        {
+            self.syns.fetch_add(1, Ordering::SeqCst);
+        }
+        self.inner.send(frame).await
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+}
+
+/// Connect with `auth` on both ends; the very first SYN must be accepted.
+async fn first_syn_accepted(auth: TcpAuth) -> anyhow::Result<()> {
+    let client_ip: IpAddr = [10, 0, 0, 1].into();
+    let server_ip: IpAddr = [10, 0, 0, 2].into();
+    let (a, b) = LoopDevice::pair(1500);
+    let syns = Arc::new(AtomicUsize::new(0));
+    let a = SynCounter {
+        inner: a,
+        syns: syns.clone(),
+    };
+    let sa = Stack::new(
+        a,
+        StackConfig {
+            local_ips: vec![client_ip],
+            ..StackConfig::default()
+        },
+    );
+    let ca = sa.control();
+    tokio::spawn(sa.run());
+    let sb = Stack::new(
+        b,
+        StackConfig {
+            local_ips: vec![server_ip],
+            ..StackConfig::default()
+        },
+    );
+    let cb = sb.control();
+    tokio::spawn(sb.run());
+
+    let remote = TcpSocketAddr {
+        ip: server_ip,
+        port: 179,
+    };
+    let mut listener = TcpListener::bind_with(
+        cb,
+        remote,
+        ListenOptions {
+            auth: [(client_ip, auth.clone())].into(),
+            ..ListenOptions::default()
+        },
+    )
+    .await?;
+    let client = TcpStream::connect_with(
+        ca,
+        TcpSocketAddr {
+            ip: client_ip,
+            port: 40000,
+        },
+        remote,
+        ConnectOptions {
+            auth: Some(auth),
+            ..ConnectOptions::default()
+        },
+    )
+    .await?;
+    let mut server = timeout(WAIT, listener.accept()).await??;
+    client.write_all(b"open".to_vec()).await?;
+    assert_eq!(timeout(WAIT, server.read()).await??, Some(b"open".to_vec()));
+
+    assert_eq!(listener.stats().await?.auth_failures, 0);
+    assert_eq!(syns.load(Ordering::SeqCst), 1);
+    assert_eq!(client.stats().await?.rto_timeouts, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn md5_first_syn_is_signed() -> anyhow::Result<()> {
+    first_syn_accepted(TcpAuth::Md5(b"md5 secret".as_slice().into())).await
+}
+
+#[tokio::test]
+async fn ao_first_syn_is_signed() -> anyhow::Result<()> {
+    for algorithm in [AoAlgorithm::HmacSha1, AoAlgorithm::AesCmac] {
+        first_syn_accepted(TcpAuth::Ao(vec![MasterKey {
+            send_id: 1,
+            recv_id: 1,
+            algorithm,
+            include_options: false,
+            key: b"ao secret".as_slice().into(),
+        }]))
+        .await?;
+    }
+    Ok(())
+}
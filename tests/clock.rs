@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::time::sleep;
+use urtcp::device::{LoopDevice, NetDevice};
+use urtcp::error::UrtcpError;
+use urtcp::tcp::conn::{MAX_RETRIES, MAX_RTO};
+use urtcp::tcp::socket::{TcpListener, TcpSocketAddr, TcpStream};
+use urtcp::{ManualClock, Stack, StackConfig};
+
+/// Loopback end whose sends can be cut off, to make the peer go silent.
+struct Gate {
+    inner: LoopDevice,
+    cut: Arc<AtomicBool>,
+}
+
+#[async_trait::async_trait]
+impl NetDevice for Gate {
+    async fn recv(&self) -> urtcp::error::Result<BytesMut> {
+        self.inner.recv().await
+    }
+
+    async fn send(&self, frame: &[u8]) -> urtcp::error::Result<()> {
+        if self.cut.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.inner.send(frame).await
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+}
+
+/// A connection between two stacks on `clock`, and the switch that
+/// silences the server.
+async fn connect(clock: &ManualClock) -> anyhow::Result<(TcpStream, Arc<AtomicBool>)> {
+    let cut = Arc::new(AtomicBool::new(false));
+    let (a, b) = LoopDevice::pair(1500);
+    let b = Gate {
+        inner: b,
+        cut: cut.clone(),
+    };
+    let sa = Stack::with_clock(
+        a,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 1].into()],
+            ..StackConfig::default()
+        },
+        Arc::new(clock.clone()),
+    );
+    let ca = sa.control();
+    tokio::spawn(sa.run());
+    let sb = Stack::with_clock(
+        b,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            ..StackConfig::default()
+        },
+        Arc::new(clock.clone()),
+    );
+    let cb = sb.control();
+    tokio::spawn(sb.run());
+
+    let remote = TcpSocketAddr {
+        ip: [10, 0, 0, 2].into(),
+        port: 80,
+    };
+    let mut listener = TcpListener::bind(cb, remote).await?;
+    tokio::spawn(async move {
+        let mut s = listener.accept().await?;
+        while s.read().await?.is_some() {}
+        anyhow::Ok(())
+    });
+    let local = TcpSocketAddr {
+        ip: [10, 0, 0, 1].into(),
+        port: 5000,
+    };
+    let stream = TcpStream::connect(ca, local, remote).await?;
+    settle().await;
+    Ok((stream, cut))
+}
+
+/// Let the stacks work through what is due; the manual clock stays put.
+async fn settle() {
+    sleep(Duration::from_millis(20)).await;
+}
+
+#[tokio::test]
+async fn retransmission_timeouts_follow_the_clock() -> anyhow::Result<()> {
+    let clock = ManualClock::default();
+    let (stream, cut) = connect(&clock).await?;
+    cut.store(true, Ordering::SeqCst);
+    stream.write_all(vec![1; 100]).await?;
+
+    // Real time passing does not fire the RTO.
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(stream.stats().await?.rto_timeouts, 0);
+
+    // Each timeout doubles the next; a second is past the first.
+    let mut expected = 0;
+    for backoff in 0..4 {
+        clock.advance(Duration::from_secs(1 << backoff));
+        settle().await;
+        expected += 1;
+        assert_eq!(stream.stats().await?.rto_timeouts, expected);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn linger_times_out_on_the_clock() -> anyhow::Result<()> {
+    let clock = ManualClock::default();
+    let (mut stream, cut) = connect(&clock).await?;
+    cut.store(true, Ordering::SeqCst);
+    stream.write_all(vec![1; 100]).await?;
+    stream.set_linger(Some(Duration::from_secs(30)));
+
+    let close = tokio::spawn(stream.close());
+    settle().await;
+    assert!(!close.is_finished());
+
+    clock.advance(Duration::from_secs(29));
+    settle().await;
+    assert!(!close.is_finished());
+
+    clock.advance(Duration::from_secs(1));
+    let res = tokio::time::timeout(Duration::from_secs(1), close).await??;
+    assert!(matches!(res, Err(UrtcpError::TimedOut)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn user_timeout_applies_while_closing() -> anyhow::Result<()> {
+    let clock = ManualClock::default();
+    let (mut stream, cut) = connect(&clock).await?;
+    stream
+        .set_user_timeout(Some(Duration::from_secs(5)))
+        .await?;
+    cut.store(true, Ordering::SeqCst);
+    stream.write_all(vec![1; 100]).await?;
+    // FIN-WAIT-1, with the data and FIN unacknowledged.
+    stream.shutdown(std::net::Shutdown::Write).await?;
+    settle().await;
+
+    clock.advance(Duration::from_secs(5));
+    settle().await;
+    let res = tokio::time::timeout(Duration::from_secs(1), stream.read()).await?;
+    assert!(matches!(res, Err(UrtcpError::TimedOut)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn user_timeout_outlasts_the_retry_limit() -> anyhow::Result<()> {
+    let clock = ManualClock::default();
+    let (mut stream, cut) = connect(&clock).await?;
+    stream
+        .set_user_timeout(Some(Duration::from_secs(3600)))
+        .await?;
+    cut.store(true, Ordering::SeqCst);
+    stream.write_all(vec![1; 100]).await?;
+    settle().await;
+
+    // Past the retry limit, but well within the hour.
+    for _ in 0..MAX_RETRIES + 5 {
+        clock.advance(MAX_RTO);
+        settle().await;
+    }
+    assert!(stream.stats().await?.rto_timeouts > MAX_RETRIES as u64);
+
+    clock.advance(Duration::from_secs(3600));
+    settle().await;
+    let res = tokio::time::timeout(Duration::from_secs(1), stream.read()).await?;
+    assert!(matches!(res, Err(UrtcpError::TimedOut)));
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use tokio::time::timeout;
+use urtcp::device::LoopDevice;
+use urtcp::tcp::conn::TcpCmd;
+use urtcp::tcp::socket::{TcpListener, TcpSocketAddr, TcpStream};
+use urtcp::{Stack, StackConfig};
+
+#[tokio::test]
+async fn drop_closes_with_the_control_channel_full() -> anyhow::Result<()> {
+    let (a, b) = LoopDevice::pair(1500);
+    let sa = Stack::new(
+        a,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 1].into()],
+            ..StackConfig::default()
+        },
+    );
+    let ca = sa.control();
+    tokio::spawn(sa.run());
+    let sb = Stack::new(
+        b,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            ..StackConfig::default()
+        },
+    );
+    let cb = sb.control();
+    tokio::spawn(sb.run());
+
+    let remote = TcpSocketAddr {
+        ip: [10, 0, 0, 2].into(),
+        port: 80,
+    };
+    let mut listener = TcpListener::bind(cb, remote).await?;
+    let local = TcpSocketAddr {
+        ip: [10, 0, 0, 1].into(),
+        port: 5000,
+    };
+    let client = TcpStream::connect(ca.clone(), local, remote).await?;
+    let mut server = timeout(Duration::from_secs(5), listener.accept()).await??;
+
+    client.write_all(b"last words".to_vec()).await?;
+    // The stack cannot run before the next await, so this fills its
+    // control channel.
+    while ca.try_send(TcpCmd::FlushMetrics(None)).is_ok() {}
+    drop(client);
+
+    let mut got = Vec::new();
+    while let Some(data) = timeout(Duration::from_secs(5), server.read()).await?? {
+        got.extend_from_slice(&data);
+    }
+    assert_eq!(got, b"last words");
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::time::timeout;
+use urtcp::device::{LoopDevice, NetDevice};
+use urtcp::tcp::socket::{ListenOptions, TcpListener, TcpSocketAddr, TcpStream};
+use urtcp::wire::ipv4::{Ipv4Addr, Ipv4Header, parse_ipv4};
+use urtcp::wire::tcp::{self, FLAG_ACK, FLAG_SYN, TcpHeader, TcpOption};
+use urtcp::{ConnectOptions, Stack, StackConfig};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Loopback end noting the largest SYN it sends and the data it carried.
+struct SynSizer {
+    inner: LoopDevice,
+    frame: Arc<AtomicUsize>,
+    data: Arc<AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl NetDevice for SynSizer {
+    async fn recv(&self) -> urtcp::error::Result<BytesMut> {
+        self.inner.recv().await
+    }
+
+    async fn send(&self, frame: &[u8]) -> urtcp::error::Result<()> {
+        if let Some(ip) = parse_ipv4(frame)
+            && let Some(tv) = tcp::parse(ip.payload)
+            && tv.flags & (FLAG_SYN | FLAG_ACK) == FLAG_SYN
+        {
+            self.frame.fetch_max(frame.len(), Ordering::SeqCst);
+            self.data.fetch_max(tv.payload.len(), Ordering::SeqCst);
+        }
+        self.inner.send(frame).await
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+}
+
+/// A segment from 10.0.0.9:`port` to the listener at 10.0.0.2:80.
+fn frame(port: u16, seq: u32, ack: u32, flags: u16, cookie: Option<&[u8]>, data: &[u8]) -> Vec<u8> {
+    let mut hdr = TcpHeader {
+        src_port: port,
+        dst_port: 80,
+        seq,
+        ack,
+        data_offset: 5,
+        flags,
+        window: 65535,
+        urg_ptr: 0,
+        options: Default::default(),
+    };
+    if let Some(cookie) = cookie {
+        hdr.set_options(&[TcpOption::FastOpen(cookie)]);
+    }
+    let seg = hdr.encode(data, [10, 0, 0, 9].into(), [10, 0, 0, 2].into());
+    Ipv4Header {
+        src: Ipv4Addr([10, 0, 0, 9]),
+        dst: Ipv4Addr([10, 0, 0, 2]),
+        proto: 6,
+        ident: 1,
+        ttl: 64,
+        ecn: 0,
+        dont_fragment: false,
+    }
+    .encode(&seg)
+    .to_vec()
+}
+
+/// Send a Fast Open SYN from `port` and wait for the SYN-ACK: its
+/// sequence number, whether it acknowledged `data` and the cookie it
+/// carried.
+async fn syn(
+    raw: &LoopDevice,
+    port: u16,
+    cookie: &[u8],
+    data: &[u8],
+) -> anyhow::Result<(u32, bool, Vec<u8>)> {
+    raw.send(&frame(port, 1000, 0, FLAG_SYN, Some(cookie), data))
+        .await?;
+    loop {
+        let reply = timeout(WAIT, raw.recv()).await??;
+        let ip = parse_ipv4(&reply).expect("ipv4");
+        let tv = tcp::parse(ip.payload).expect("tcp");
+        if tv.dst_port != port || tv.flags & (FLAG_SYN | FLAG_ACK) != FLAG_SYN | FLAG_ACK {
+            continue;
+        }
+        let cookie = tv
+            .options()
+            .find_map(|o| match o {
+                TcpOption::FastOpen(c) => Some(c.to_vec()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let accepted = tv.ack == 1001 + data.len() as u32;
+        return Ok((tv.seq, accepted, cookie));
+    }
+}
+
+#[tokio::test]
+async fn pending_fast_opens_are_limited() -> anyhow::Result<()> {
+    let (raw, dev) = LoopDevice::pair(1500);
+    let stack = Stack::new(
+        dev,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            ..StackConfig::default()
+        },
+    );
+    let ctrl = stack.control();
+    tokio::spawn(stack.run());
+    let _listener = TcpListener::bind_with(
+        ctrl,
+        TcpSocketAddr {
+            ip: [10, 0, 0, 2].into(),
+            port: 80,
+        },
+        ListenOptions {
+            fast_open: Some(1),
+            ..ListenOptions::default()
+        },
+    )
+    .await?;
+
+    let (_, _, cookie) = syn(&raw, 5000, &[], b"").await?;
+    assert!(!cookie.is_empty());
+
+    // One SYN's data is taken; the next waits for the handshake.
+    let (iss, accepted, _) = syn(&raw, 5001, &cookie, b"early").await?;
+    assert!(accepted);
+    let (_, accepted, _) = syn(&raw, 5002, &cookie, b"early").await?;
+    assert!(!accepted);
+
+    // Completing the first handshake makes room again.
+    raw.send(&frame(5001, 1006, iss.wrapping_add(1), FLAG_ACK, None, b""))
+        .await?;
+    let (_, accepted, _) = syn(&raw, 5003, &cookie, b"early").await?;
+    assert!(accepted);
+    Ok(())
+}
+
+#[tokio::test]
+async fn largest_syn_fits_the_mtu() -> anyhow::Result<()> {
+    let (a, b) = LoopDevice::pair(1500);
+    let frame = Arc::new(AtomicUsize::new(0));
+    let data = Arc::new(AtomicUsize::new(0));
+    let a = SynSizer {
+        inner: a,
+        frame: frame.clone(),
+        data: data.clone(),
+    };
+    let sa = Stack::new(
+        a,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 1].into()],
+            ..StackConfig::default()
+        },
+    );
+    let ca = sa.control();
+    tokio::spawn(sa.run());
+    let sb = Stack::new(
+        b,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            ..StackConfig::default()
+        },
+    );
+    let cb = sb.control();
+    tokio::spawn(sb.run());
+
+    let remote = TcpSocketAddr {
+        ip: [10, 0, 0, 2].into(),
+        port: 80,
+    };
+    let mut listener = TcpListener::bind_with(
+        cb,
+        remote,
+        ListenOptions {
+            fast_open: Some(16),
+            ..ListenOptions::default()
+        },
+    )
+    .await?;
+    let payload = vec![7; 4000];
+    // The first connection fetches a cookie, the second spends it.
+    for port in [40000, 40001] {
+        let client = TcpStream::connect_with(
+            ca.clone(),
+            TcpSocketAddr {
+                ip: [10, 0, 0, 1].into(),
+                port,
+            },
+            remote,
+            ConnectOptions {
+                fast_open: true,
+                data: payload.clone(),
+                ..ConnectOptions::default()
+            },
+        )
+        .await?;
+        let mut server = timeout(WAIT, listener.accept()).await??;
+        let mut got = Vec::new();
+        while got.len() < payload.len() {
+            got.extend(timeout(WAIT, server.read()).await??.expect("data"));
+        }
+        assert_eq!(got, payload);
+        assert_eq!(client.stats().await?.rto_timeouts, 0);
+    }
+
+    assert!(data.load(Ordering::SeqCst) > 0);
+    assert!(frame.load(Ordering::SeqCst) <= 1500);
+    Ok(())
+}
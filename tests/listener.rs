@@ -0,0 +1,173 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::{sleep, timeout};
+use urtcp::device::{LoopDevice, NetDevice};
+use urtcp::tcp::socket::{TcpListener, TcpSocketAddr};
+use urtcp::wire::ipv4::{Ipv4Addr, Ipv4Header, parse_ipv4};
+use urtcp::wire::tcp::{self, FLAG_ACK, FLAG_RST, FLAG_SYN, TcpHeader};
+use urtcp::{ManualClock, Stack, StackConfig};
+
+/// A segment from 10.0.0.9:`port` to the listener at 10.0.0.2:80.
+fn frame(port: u16, seq: u32, ack: u32, flags: u16) -> Vec<u8> {
+    let hdr = TcpHeader {
+        src_port: port,
+        dst_port: 80,
+        seq,
+        ack,
+        data_offset: 5,
+        flags,
+        window: 65535,
+        urg_ptr: 0,
+        options: Default::default(),
+    };
+    let seg = hdr.encode(&[], [10, 0, 0, 9].into(), [10, 0, 0, 2].into());
+    Ipv4Header {
+        src: Ipv4Addr([10, 0, 0, 9]),
+        dst: Ipv4Addr([10, 0, 0, 2]),
+        proto: 6,
+        ident: 1,
+        ttl: 64,
+        ecn: 0,
+        dont_fragment: false,
+    }
+    .encode(&seg)
+    .to_vec()
+}
+
+/// Send a SYN from `port` and return the SYN-ACK's sequence number.
+async fn syn(raw: &LoopDevice, port: u16) -> anyhow::Result<u32> {
+    raw.send(&frame(port, 1000, 0, FLAG_SYN)).await?;
+    syn_ack(raw).await
+}
+
+/// Wait for a SYN-ACK and return its sequence number.
+async fn syn_ack(raw: &LoopDevice) -> anyhow::Result<u32> {
+    let reply = timeout(Duration::from_secs(5), raw.recv()).await??;
+    let ip = parse_ipv4(&reply).expect("ipv4");
+    let tv = tcp::parse(ip.payload).expect("tcp");
+    assert_eq!(tv.flags & (FLAG_SYN | FLAG_ACK), FLAG_SYN | FLAG_ACK);
+    Ok(tv.seq)
+}
+
+#[tokio::test]
+async fn half_open_queue_is_counted_per_listener() -> anyhow::Result<()> {
+    let (raw, dev) = LoopDevice::pair(1500);
+    let stack = Stack::new(
+        dev,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            syn_backlog: 2,
+            ..StackConfig::default()
+        },
+    );
+    let ctrl = stack.control();
+    tokio::spawn(stack.run());
+    let mut listener = TcpListener::bind(
+        ctrl,
+        TcpSocketAddr {
+            ip: [10, 0, 0, 2].into(),
+            port: 80,
+        },
+    )
+    .await?;
+
+    let first = syn(&raw, 4000).await?;
+    syn(&raw, 4001).await?;
+    assert_eq!(listener.stats().await?.half_open, 2);
+
+    // The queue is full: answered with a cookie, nothing kept.
+    syn(&raw, 4002).await?;
+    let stats = listener.stats().await?;
+    assert_eq!(stats.half_open, 2);
+    assert_eq!(stats.syn_cookies_sent, 1);
+
+    // Established connections leave the queue...
+    raw.send(&frame(4000, 1001, first.wrapping_add(1), FLAG_ACK))
+        .await?;
+    timeout(Duration::from_secs(5), listener.accept()).await??;
+    assert_eq!(listener.stats().await?.half_open, 1);
+
+    // ...and so do reset ones.
+    raw.send(&frame(4001, 1001, 0, FLAG_RST)).await?;
+    sleep(Duration::from_millis(20)).await;
+    assert_eq!(listener.stats().await?.half_open, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn lost_syn_ack_is_sent_again() -> anyhow::Result<()> {
+    let (raw, dev) = LoopDevice::pair(1500);
+    let stack = Stack::new(
+        dev,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            ..StackConfig::default()
+        },
+    );
+    let ctrl = stack.control();
+    tokio::spawn(stack.run());
+    let mut listener = TcpListener::bind(
+        ctrl,
+        TcpSocketAddr {
+            ip: [10, 0, 0, 2].into(),
+            port: 80,
+        },
+    )
+    .await?;
+
+    // The first SYN-ACK is lost; the retransmission timer sends another...
+    let iss = syn(&raw, 4000).await?;
+    assert_eq!(syn_ack(&raw).await?, iss);
+    // ...and so does the peer's retransmitted SYN.
+    assert_eq!(syn(&raw, 4000).await?, iss);
+
+    raw.send(&frame(4000, 1001, iss.wrapping_add(1), FLAG_ACK))
+        .await?;
+    timeout(Duration::from_secs(5), listener.accept()).await??;
+    assert_eq!(listener.stats().await?.half_open, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn unanswered_syn_ack_gives_up() -> anyhow::Result<()> {
+    let clock = ManualClock::default();
+    let (raw, dev) = LoopDevice::pair(1500);
+    let stack = Stack::with_clock(
+        dev,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            ..StackConfig::default()
+        },
+        Arc::new(clock.clone()),
+    );
+    let ctrl = stack.control();
+    tokio::spawn(stack.run());
+    let listener = TcpListener::bind(
+        ctrl,
+        TcpSocketAddr {
+            ip: [10, 0, 0, 2].into(),
+            port: 80,
+        },
+    )
+    .await?;
+
+    syn(&raw, 4000).await?;
+    assert_eq!(listener.stats().await?.half_open, 1);
+    let mut resent = 0;
+    for _ in 0..10 {
+        clock.advance(Duration::from_secs(60));
+        sleep(Duration::from_millis(20)).await;
+        while let Ok(frame) = timeout(Duration::from_millis(1), raw.recv()).await {
+            let frame = frame?;
+            let ip = parse_ipv4(&frame).expect("ipv4");
+            let tv = tcp::parse(ip.payload).expect("tcp");
+            if tv.flags & (FLAG_SYN | FLAG_ACK) == FLAG_SYN | FLAG_ACK {
+                resent += 1;
+            }
+        }
+    }
+    assert_eq!(resent, 6);
+    assert_eq!(listener.stats().await?.half_open, 0);
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use tokio::time::timeout;
+use urtcp::device::{LoopDevice, NetDevice};
+use urtcp::error::UrtcpError;
+use urtcp::ip::ping::ping;
+use urtcp::wire::icmpv4::{self, IcmpHeader, TYPE_ECHO_REPLY, TYPE_ECHO_REQUEST};
+use urtcp::wire::ipv4::{Ipv4Addr, Ipv4Header, parse_ipv4};
+use urtcp::{PingOptions, Stack, StackConfig};
+
+/// A stack at 10.0.0.2 on `dev`.
+fn stack(dev: LoopDevice, echo_reply: bool) -> Stack<LoopDevice> {
+    Stack::new(
+        dev,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 2].into()],
+            echo_reply,
+            ..StackConfig::default()
+        },
+    )
+}
+
+/// An echo request from 10.0.0.9 to the stack.
+fn echo_request(ident: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+    let msg = IcmpHeader::echo(TYPE_ECHO_REQUEST, ident, seq).encode(data);
+    Ipv4Header {
+        src: Ipv4Addr([10, 0, 0, 9]),
+        dst: Ipv4Addr([10, 0, 0, 2]),
+        proto: 1,
+        ident: 1,
+        ttl: 64,
+        ecn: 0,
+        dont_fragment: false,
+    }
+    .encode(&msg)
+    .to_vec()
+}
+
+#[tokio::test]
+async fn echo_request_is_answered() -> anyhow::Result<()> {
+    let (raw, dev) = LoopDevice::pair(1500);
+    tokio::spawn(stack(dev, true).run());
+
+    raw.send(&echo_request(0x4242, 3, b"hello")).await?;
+    let frame = timeout(Duration::from_secs(5), raw.recv()).await??;
+    let ip = parse_ipv4(&frame).expect("ipv4");
+    assert_eq!((ip.src, ip.dst), ([10, 0, 0, 2], [10, 0, 0, 9]));
+    let icmp = icmpv4::parse(ip.payload).expect("valid reply");
+    assert_eq!(icmp.ty, TYPE_ECHO_REPLY);
+    assert_eq!(icmp.echo(), Some((0x4242, 3)));
+    assert_eq!(icmp.payload, b"hello");
+    Ok(())
+}
+
+#[tokio::test]
+async fn echo_requests_can_be_ignored() -> anyhow::Result<()> {
+    let (raw, dev) = LoopDevice::pair(1500);
+    tokio::spawn(stack(dev, false).run());
+
+    raw.send(&echo_request(1, 1, b"hello")).await?;
+    assert!(
+        timeout(Duration::from_millis(200), raw.recv())
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn ping_between_stacks() -> anyhow::Result<()> {
+    let (a, b) = LoopDevice::pair(1500);
+    let sa = Stack::new(
+        a,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 1].into()],
+            ..StackConfig::default()
+        },
+    );
+    let ctrl = sa.control();
+    tokio::spawn(sa.run());
+    tokio::spawn(stack(b, true).run());
+
+    let dst = [10, 0, 0, 2].into();
+    ping(&ctrl, dst, PingOptions::default()).await?;
+    // Larger than the MTU: fragmented both ways.
+    let big = PingOptions {
+        size: 4000,
+        ..PingOptions::default()
+    };
+    ping(&ctrl, dst, big).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn ping_times_out_without_a_reply() -> anyhow::Result<()> {
+    let (a, _silent) = LoopDevice::pair(1500);
+    let sa = Stack::new(
+        a,
+        StackConfig {
+            local_ips: vec![[10, 0, 0, 1].into()],
+            ..StackConfig::default()
+        },
+    );
+    let ctrl = sa.control();
+    tokio::spawn(sa.run());
+
+    let opts = PingOptions {
+        timeout: Duration::from_millis(100),
+        ..PingOptions::default()
+    };
+    let res = ping(&ctrl, [10, 0, 0, 2].into(), opts).await;
+    assert!(matches!(res, Err(UrtcpError::TimedOut)));
+    Ok(())
+}
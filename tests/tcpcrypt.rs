@@ -0,0 +1,169 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::time::timeout;
+use urtcp::device::{LoopDevice, NetDevice};
+use urtcp::tcp::socket::{TcpListener, TcpSocketAddr, TcpStream};
+use urtcp::{ConnectOptions, Stack, StackConfig};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Loopback end that keeps a copy of every frame it sends.
+struct Tap {
+    inner: LoopDevice,
+    sent: Arc<Mutex<Vec<u8>>>,
+}
+
+#[async_trait::async_trait]
+impl NetDevice for Tap {
+    async fn recv(&self) -> urtcp::error::Result<BytesMut> {
+        self.inner.recv().await
+    }
+
+    async fn send(&self, frame: &[u8]) -> urtcp::error::Result<()> {
+        self.sent.lock().unwrap().extend_from_slice(frame);
+        self.inner.send(frame).await
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+}
+
+struct Pair {
+    client: TcpStream,
+    server: TcpStream,
+    /// Everything the client put on the wire.
+    wire: Arc<Mutex<Vec<u8>>>,
+}
+
+/// Two stacks over a loopback link, with a connection between them whose
+/// SYN carries `data`.
+async fn connect(client_crypt: bool, server_crypt: bool, data: &[u8]) -> anyhow::Result<Pair> {
+    let (a, b) = LoopDevice::pair(1500);
+    let wire = Arc::new(Mutex::new(Vec::new()));
+    let a = Tap {
+        inner: a,
+        sent: wire.clone(),
+    };
+    let client_ip: IpAddr = [10, 0, 0, 1].into();
+    let server_ip: IpAddr = [10, 0, 0, 2].into();
+
+    let sa = Stack::new(
+        a,
+        StackConfig {
+            local_ips: vec![client_ip],
+            tcpcrypt: client_crypt,
+            ..StackConfig::default()
+        },
+    );
+    let ca = sa.control();
+    tokio::spawn(sa.run());
+    let sb = Stack::new(
+        b,
+        StackConfig {
+            local_ips: vec![server_ip],
+            tcpcrypt: server_crypt,
+            ..StackConfig::default()
+        },
+    );
+    let cb = sb.control();
+    tokio::spawn(sb.run());
+
+    let remote = TcpSocketAddr {
+        ip: server_ip,
+        port: 80,
+    };
+    let mut listener = TcpListener::bind(cb, remote).await?;
+    let client = TcpStream::connect_with(
+        ca,
+        TcpSocketAddr {
+            ip: client_ip,
+            port: 5000,
+        },
+        remote,
+        ConnectOptions {
+            data: data.to_vec(),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let server = timeout(WAIT, listener.accept()).await??;
+    Ok(Pair {
+        client,
+        server,
+        wire,
+    })
+}
+
+async fn read_exact(stream: &mut TcpStream, len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut got = Vec::new();
+    while got.len() < len {
+        match timeout(WAIT, stream.read()).await?? {
+            Some(data) => got.extend_from_slice(&data),
+            None => anyhow::bail!("eof after {} of {len} bytes", got.len()),
+        }
+    }
+    Ok(got)
+}
+
+fn on_wire(wire: &Mutex<Vec<u8>>, needle: &[u8]) -> bool {
+    wire.lock()
+        .unwrap()
+        .windows(needle.len())
+        .any(|w| w == needle)
+}
+
+#[tokio::test]
+async fn encrypted_round_trip() -> anyhow::Result<()> {
+    let hello = b"SECRET-HELLO-FROM-CLIENT";
+    let Pair {
+        mut client,
+        mut server,
+        wire,
+    } = connect(true, true, hello).await?;
+
+    // The SYN data is held back until the keys are up, then released.
+    let bulk: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    client.write_all(bulk.clone()).await?;
+    let got = read_exact(&mut server, hello.len() + bulk.len()).await?;
+    assert_eq!(&got[..hello.len()], hello);
+    assert!(got[hello.len()..] == bulk[..]);
+
+    server.write_all(b"SECRET-REPLY".to_vec()).await?;
+    assert_eq!(read_exact(&mut client, 12).await?, b"SECRET-REPLY");
+
+    let client_sid = client.session_id().await?;
+    let server_sid = server.session_id().await?;
+    assert!(client_sid.is_some());
+    assert_eq!(client_sid, server_sid);
+    assert!(!on_wire(&wire, hello));
+    Ok(())
+}
+
+#[tokio::test]
+async fn falls_back_to_plain_tcp() -> anyhow::Result<()> {
+    for (client_crypt, server_crypt) in [(true, false), (false, true)] {
+        let hello = b"PLAIN-HELLO";
+        let Pair {
+            mut client,
+            mut server,
+            wire,
+        } = connect(client_crypt, server_crypt, hello).await?;
+
+        client.write_all(b"more".to_vec()).await?;
+        assert_eq!(
+            read_exact(&mut server, hello.len() + 4).await?,
+            b"PLAIN-HELLOmore"
+        );
+        server.write_all(b"reply".to_vec()).await?;
+        assert_eq!(read_exact(&mut client, 5).await?, b"reply");
+
+        assert_eq!(client.session_id().await?, None);
+        assert_eq!(server.session_id().await?, None);
+        assert!(on_wire(&wire, hello));
+    }
+    Ok(())
+}